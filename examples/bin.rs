@@ -19,8 +19,6 @@
  * along with libssg. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use libssg;
-
 /* This expects the following directory tree:
  *  ├── bin.rs
  *  ├── css
@@ -30,7 +28,9 @@ use libssg;
  *  │   └── *.md
  *  └── templates
  *      ├── default.html
- *      └── index.html
+ *      ├── index.html
+ *      ├── tag.html
+ *      └── tags.html
  *
  *
  *  Run executable in this directory.
@@ -41,6 +41,7 @@ use libssg;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut state = libssg::State::new()?;
+    libssg::init_tracing(state.verbosity());
     state
         .then(libssg::match_pattern(
             "^posts/*",
@@ -50,12 +51,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 libssg::pandoc(),
                 Box::new(|state, path| {
                     let path = path
-                        .strip_prefix(&state.output_dir().parent().unwrap())
-                        .unwrap_or(&path)
+                        .strip_prefix(state.output_dir().parent().unwrap())
+                        .unwrap_or(path)
                         .to_path_buf();
-                    if state.verbosity() > 3 {
-                        println!("adding {} to RSS snapshot", path.display());
-                    }
+                    tracing::trace!(path = %path.display(), "adding to RSS snapshot");
                     let uuid = libssg::uuid_from_path(&path);
                     state.add_to_snapshot("main-rss-feed".into(), uuid);
                     Ok(Default::default())
@@ -72,38 +71,47 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             libssg::pandoc(),
         ))
         .then(libssg::copy("^css/*", libssg::Route::Id))
-        .then(build_rss_feed(
+        .then(libssg::taxonomy("main-rss-feed", {
+            let mut tags = libssg::Taxonomy::new(
+                "tags",
+                libssg::Renderer::Pipeline(vec![
+                    libssg::Renderer::LoadAndApplyTemplate("templates/tag.html"),
+                    libssg::Renderer::LoadAndApplyTemplate("templates/default.html"),
+                ]),
+                libssg::Renderer::Pipeline(vec![
+                    libssg::Renderer::LoadAndApplyTemplate("templates/tags.html"),
+                    libssg::Renderer::LoadAndApplyTemplate("templates/default.html"),
+                ]),
+            );
+            tags.feed = Some(rss_configuration());
+            tags
+        }))
+        .then(libssg::create(
             "rss.xml".into(),
+            libssg::Renderer::body(),
             libssg::rss_feed(
-                "main-rss-feed".into(),
-                libssg::RssItem {
-                    title: "example page".into(),
-                    description: "example using libssg".into(),
-                    link: "http://localhost".into(),
-                    last_build_date: String::new(),
-                    pub_date: "Thu, 01 Jan 1970 00:00:00 +0000".to_string(),
-                    ttl: 1800,
-                },
+                libssg::snapshot("main-rss-feed")
+                    .sort_by_key("date")
+                    .rev()
+                    .take(10),
+                rss_configuration(),
+            ),
+        ))
+        .then(libssg::create(
+            "atom.xml".into(),
+            libssg::Renderer::body(),
+            libssg::atom_feed(
+                libssg::snapshot("main-rss-feed")
+                    .sort_by_key("date")
+                    .rev()
+                    .take(10),
+                libssg::AtomConfig::new("example page", "http://localhost"),
             ),
         ))
         .finish()?;
     Ok(())
 }
 
-fn build_rss_feed(path: std::path::PathBuf, compiler: libssg::Compiler) -> libssg::Rule {
-    Box::new(move |state: &mut libssg::State| {
-        state.add_page(
-            path.clone(),
-            path.clone(),
-            &compiler,
-            libssg::Renderer::Custom(Box::new(|metadata| {
-                Ok(if let libssg::Value::Object(ref map) = metadata {
-                    map.get("body").and_then(|b| b.as_str()).ok_or_else(|| format!("Internal error while building rss feed: metadata does not contain `body`: {:#?}", &map))?.to_string()
-                } else {
-                    String::new()
-                })
-            })),
-        )?;
-        Ok(())
-    })
+fn rss_configuration() -> libssg::RssChannel {
+    libssg::RssChannel::new("example page", "example using libssg", "http://localhost")
 }
@@ -50,26 +50,9 @@ pub mod pandoc {
     use std::collections::HashMap;
     pub fn pandoc() -> Compiler {
         Box::new(|state: &mut State, path: &Path| {
-            let metadata = Command::new("pandoc")
-                .args(&["-t", "json"])
-                .arg(&path)
-                .output()
-                .map_err(|err| format!("failed to execute pandoc: {}", err))?;
-            let pandoc_json: PandocJsonOutput =
-                serde_json::from_str(&String::from_utf8_lossy(&metadata.stdout))
-                    .unwrap_or_default();
-            let mut metadata_map: Map<String, Value> = parse_metadata(pandoc_json);
-            if state.verbosity() > 2 {
-                println!(
-                    "Parsed metadata for {}: {:#?}",
-                    path.display(),
-                    &metadata_map
-                );
-            }
-            let output = Command::new("pandoc")
-                .arg(&path)
-                .output()
-                .map_err(|err| format!("failed to execute pandoc: {}", err))?;
+            let (mut metadata_map, _) = pandoc_ast(state, path)?;
+            debug!(path = %path.display(), metadata = ?metadata_map, "parsed metadata");
+            let output = state.run_command(Command::new("pandoc").arg(path))?;
             metadata_map.insert(
                 "body".to_string(),
                 Value::String(String::from_utf8_lossy(&output.stdout).to_string()),
@@ -78,6 +61,15 @@ pub mod pandoc {
         })
     }
 
+    /// Run `pandoc -t json` on `path` and return the document's metadata and its list of blocks.
+    pub fn pandoc_ast(state: &mut State, path: &Path) -> Result<(Map<String, Value>, Value)> {
+        let output = state.run_command(Command::new("pandoc").args(["-t", "json"]).arg(path))?;
+        let mut pandoc_json: PandocJsonOutput =
+            serde_json::from_str(&String::from_utf8_lossy(&output.stdout)).unwrap_or_default();
+        let blocks = pandoc_json.blocks.take();
+        Ok((parse_metadata(pandoc_json), blocks))
+    }
+
     fn parse_metadata(output: PandocJsonOutput) -> Map<String, Value> {
         let meta = output.meta;
 
@@ -87,6 +79,7 @@ pub mod pandoc {
     }
 
     #[derive(Deserialize, Debug, Default)]
+    #[allow(dead_code)]
     struct PandocJsonOutput {
         blocks: Value,
         #[serde(rename = "pandoc-api-version")]
@@ -96,6 +89,7 @@ pub mod pandoc {
 
     #[derive(Deserialize, Debug)]
     #[serde(tag = "t", content = "c")]
+    #[allow(dead_code, clippy::enum_variant_names)]
     enum PandocMetaValue {
         MetaMap(HashMap<String, PandocMetaValue>),
         MetaList(Vec<PandocMetaValue>),
@@ -105,10 +99,10 @@ pub mod pandoc {
         MetaBlocks(Value),
     }
 
-    impl Into<Value> for PandocMetaValue {
-        fn into(self) -> Value {
+    impl From<PandocMetaValue> for Value {
+        fn from(val: PandocMetaValue) -> Value {
             use PandocMetaValue::*;
-            match self {
+            match val {
                 MetaMap(map) => Value::Object(
                     map.into_iter()
                         .map(|(key, metaval)| (key.clone(), metaval.into()))
@@ -125,7 +119,7 @@ pub mod pandoc {
                     String::new(),
                     |mut acc, inline| {
                         let inline: String = inline.into();
-                        acc.extend(inline.chars());
+                        acc.push_str(&inline);
                         acc
                     },
                 )),
@@ -136,6 +130,7 @@ pub mod pandoc {
 
     #[derive(Deserialize, Debug)]
     #[serde(tag = "t", content = "c")]
+    #[allow(dead_code, clippy::enum_variant_names)]
     enum PandocMetaInline {
         Str(String),
         Emph(Vec<PandocMetaInline>),
@@ -158,19 +153,19 @@ pub mod pandoc {
         Span(Value),
     }
 
-    impl Into<String> for PandocMetaInline {
-        fn into(self) -> String {
+    impl From<PandocMetaInline> for String {
+        fn from(val: PandocMetaInline) -> String {
             use PandocMetaInline::*;
-            match self {
+            match val {
                 Str(inner) => inner,
                 Emph(list) => list.into_iter().fold(String::new(), |mut acc, el| {
                     let el: String = el.into();
-                    acc.extend(el.chars());
+                    acc.push_str(&el);
                     acc
                 }),
                 Strong(list) => list.into_iter().fold(String::new(), |mut acc, el| {
                     let el: String = el.into();
-                    acc.extend(el.chars());
+                    acc.push_str(&el);
                     acc
                 }),
                 Space => String::from(" "),
@@ -178,22 +173,22 @@ pub mod pandoc {
                 LineBreak => String::from("\n"),
                 Strikeout(list) => list.into_iter().fold(String::new(), |mut acc, el| {
                     let el: String = el.into();
-                    acc.extend(el.chars());
+                    acc.push_str(&el);
                     acc
                 }),
                 Superscript(list) => list.into_iter().fold(String::new(), |mut acc, el| {
                     let el: String = el.into();
-                    acc.extend(el.chars());
+                    acc.push_str(&el);
                     acc
                 }),
                 Subscript(list) => list.into_iter().fold(String::new(), |mut acc, el| {
                     let el: String = el.into();
-                    acc.extend(el.chars());
+                    acc.push_str(&el);
                     acc
                 }),
                 SmallCaps(list) => list.into_iter().fold(String::new(), |mut acc, el| {
                     let el: String = el.into();
-                    acc.extend(el.chars());
+                    acc.push_str(&el);
                     acc
                 }),
                 Quoted(_) => String::new(),
@@ -210,99 +205,53 @@ pub mod pandoc {
     }
 }
 
+pub mod rss;
 pub use rss::*;
 
-pub mod rss {
-    use super::*;
-    use serde::{self, Serialize};
-    use serde_json::json;
+pub mod atom;
+pub use atom::*;
 
-    #[derive(Serialize)]
-    pub struct RssItem {
-        pub title: String,
-        pub description: String,
-        pub link: String,
-        pub last_build_date: String,
-        pub pub_date: String,
-        pub ttl: i32,
-    }
+pub mod json_feed;
+pub use json_feed::*;
 
-    const RSS_TEMPLATE: &'static str = r#"<?xml version="1.0" encoding="UTF-8" ?>
-<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
-<channel>
-  <title>{{ config.title }}</title>
-  <description><![CDATA[{{ include config.description }}]]></description>
-  <link>{{ config.link }}</link>
-  <atom:link href="{{ config.link }}/{{ path }}" rel="self" type="application/rss+xml" />
-  <pubDate> Thu, 01 Jan 1970 00:00:00 +0000 </pubDate>
- {{#each items}}
- <item>
-  <title>{{ title }}</title>
-  <description><![CDATA[{{ include description }}]]></description>
-  <link>{{ config.link }}{{ link }}</link>
-  <guid>{{ config.link }}{{ link }}</guid>
-  <pubDate>{{ pub_date }}</pubDate>
- </item>
-{{/each~}}
+pub mod gemini;
+pub use gemini::*;
 
-</channel>
-</rss>"#;
-    pub fn rss_feed(snapshot_name: String, configuration: RssItem) -> Compiler {
-        Box::new(move |state: &mut State, dest_path: &Path| {
-            if !state.snapshots.contains_key(&snapshot_name) {
-                // No posts configured/found
-                Err(format!("There are no snapshots with key `{}`, is the source rule empty (ie producing no items) or have you typed the name wrong?", &snapshot_name))?;
-            }
+#[cfg(feature = "sass")]
+pub mod sass;
+#[cfg(feature = "sass")]
+pub use sass::*;
 
-            let snapshot = &state.snapshots[&snapshot_name];
-            let mut rss_items = Vec::with_capacity(snapshot.len());
-            for artifact in snapshot.iter() {
-                let map = &state.artifacts[&artifact].metadata;
-                macro_rules! get_property {
-                    ($key:literal, $default:expr) => {
-                        map.get($key)
-                            .and_then(|t| {
-                                if let Value::String(ref var) = t {
-                                    Some(var.to_string())
-                                } else {
-                                    None
-                                }
-                            })
-                            .unwrap_or_else(|| $default)
-                    };
-                }
-                rss_items.push(RssItem {
-                    title: get_property!("title", format!("No title, uuid: {}", artifact)),
-                    description: get_property!("body", String::new()),
-                    link: format!(
-                        "{}/{}",
-                        &configuration.link,
-                        &state.artifacts[&artifact].path.display()
-                    ),
-                    last_build_date: String::new(),
-                    pub_date: get_property!("date", "Thu, 01 Jan 1970 00:00:00 +0000".to_string()),
-                    ttl: 1800,
-                });
-            }
-            let mut handlebars = Handlebars::new();
-            handlebars.register_helper("include", Box::new(include_helper));
-
-            let test = handlebars.render_template(
-                RSS_TEMPLATE,
-                &json!({ "items": rss_items, "config": configuration, "path": dest_path }),
-            )?;
-            let mut metadata_map: Map<String, Value> = Map::new();
-            metadata_map.insert("body".into(), test.into());
-            Ok(metadata_map)
-        })
-    }
-}
+#[cfg(feature = "minify")]
+pub mod minify;
+#[cfg(feature = "minify")]
+pub use minify::*;
 
 pub fn compiler_seq(compiler_a: Compiler, compiler_b: Compiler) -> Compiler {
     Box::new(move |state: &mut State, path: &Path| {
-        let mut a = compiler_a(state, &path)?;
-        let b = compiler_b(state, &path)?;
-        a.extend(b.into_iter());
+        let mut a = compiler_a(state, path)?;
+        let b = compiler_b(state, path)?;
+        a.extend(b);
         Ok(a)
     })
 }
+
+/// A compiler whose `body` is the contents of the file, eg for stylesheets and scripts.
+pub fn file_contents() -> Compiler {
+    Box::new(|_: &mut State, path: &Path| {
+        let mut metadata = Map::new();
+        metadata.insert(
+            "body".into(),
+            fs::read_to_string(path)
+                .map_err(|err| format!("Could not read {}: {}", path.display(), err))?
+                .into(),
+        );
+        Ok(metadata)
+    })
+}
+
+/// A compiler that always returns a copy of `metadata`, for pages generated entirely from data
+/// that is already known when the rule runs.
+pub fn const_compiler(metadata: Map<String, Value>) -> Compiler {
+    Box::new(move |_: &mut State, _: &Path| Ok(metadata.clone()))
+}
@@ -0,0 +1,413 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Atom 1.0 feed generation.
+//!
+//! Feed entries are built from the artifacts of a [`SnapshotQuery`](crate::SnapshotQuery) with
+//! the same metadata conventions as [`rss`](crate::compilers::rss), and additionally:
+//!
+//! - `updated`, the date of the last change, defaulting to `date` and then to the modification
+//!   time of the artifact's resource
+//! - `summary` or `description`, as the entry's `<summary>`
+//! - `author` may be a list, and each author may be written as `Name <email>`
+//!
+//! Entry ids are `urn:uuid:` URIs of the artifacts' uuids, which are derived from their source
+//! paths and thus stay the same across builds.
+
+use super::rss::{absolute_url, get_categories, get_content, get_string};
+use super::*;
+use chrono::{DateTime, FixedOffset};
+
+/// Feed configuration of an Atom feed.
+#[derive(Debug, Clone)]
+pub struct AtomConfig {
+    pub title: String,
+    pub subtitle: Option<String>,
+    /// The site's URL, eg `https://example.com`. Entry links are this URL joined with the
+    /// artifact's path.
+    pub link: String,
+    /// Permanent, unique identifier of the feed. Defaults to `link`.
+    pub id: Option<String>,
+    /// Feed authors, required unless every entry has an author.
+    pub authors: Vec<AtomPerson>,
+    pub rights: Option<String>,
+    /// Include the full HTML contents of entries as `<content type="html">`.
+    pub full_content: bool,
+    /// Use this named snapshot of each artifact's rendered contents (see
+    /// [`Renderer::Snapshot`](crate::Renderer::Snapshot)) as entry contents instead of the
+    /// compiled `body`.
+    pub content_snapshot: Option<String>,
+}
+
+impl AtomConfig {
+    pub fn new<S: Into<String>>(title: S, link: S) -> Self {
+        AtomConfig {
+            title: title.into(),
+            subtitle: None,
+            link: link.into(),
+            id: None,
+            authors: vec![],
+            rights: None,
+            full_content: true,
+            content_snapshot: None,
+        }
+    }
+}
+
+/// An author of a feed or entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtomPerson {
+    pub name: String,
+    pub email: Option<String>,
+    pub uri: Option<String>,
+}
+
+impl AtomPerson {
+    /// Parse `Name <email>`, `email (Name)` or plain `Name`.
+    pub fn parse(s: &str) -> Self {
+        let s = s.trim();
+        let (name, email) = if let (Some(l), true) = (s.find('<'), s.ends_with('>')) {
+            (s[..l].trim(), Some(s[l + 1..s.len() - 1].trim()))
+        } else if let (Some(l), true) = (s.find('('), s.ends_with(')')) {
+            (s[l + 1..s.len() - 1].trim(), Some(s[..l].trim()))
+        } else if s.contains('@') && !s.contains(' ') {
+            (s, Some(s))
+        } else {
+            (s, None)
+        };
+        AtomPerson {
+            name: name.to_string(),
+            email: email.map(str::to_string),
+            uri: None,
+        }
+    }
+}
+
+/// An entry of an Atom feed.
+#[derive(Debug, Clone)]
+pub struct AtomEntry {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub updated: Option<DateTime<FixedOffset>>,
+    pub published: Option<DateTime<FixedOffset>>,
+    pub authors: Vec<AtomPerson>,
+    /// HTML summary.
+    pub summary: Option<String>,
+    /// HTML contents.
+    pub content: Option<String>,
+    pub categories: Vec<String>,
+}
+
+impl AtomEntry {
+    /// Create an entry from an artifact's metadata, see the [module documentation](self).
+    pub fn from_artifact(artifact: &BuildArtifact, config: &AtomConfig) -> Self {
+        let published = get_string(artifact, "date").as_deref().and_then(parse_date);
+        let authors = match artifact.metadata.get("author") {
+            Some(Value::String(author)) => vec![AtomPerson::parse(author)],
+            Some(Value::Array(list)) => list
+                .iter()
+                .filter_map(Value::as_str)
+                .map(AtomPerson::parse)
+                .collect(),
+            _ => vec![],
+        };
+        AtomEntry {
+            id: format!("urn:uuid:{}", artifact.uuid),
+            title: get_string(artifact, "title")
+                .unwrap_or_else(|| format!("No title, uuid: {}", artifact.uuid)),
+            link: absolute_url(&config.link, &artifact.path),
+            updated: get_string(artifact, "updated")
+                .as_deref()
+                .and_then(parse_date)
+                .or(published)
+                .or_else(|| {
+                    fs::metadata(&artifact.resource)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .map(|mtime| DateTime::<chrono::Utc>::from(mtime).fixed_offset())
+                }),
+            published,
+            authors,
+            summary: get_string(artifact, "summary")
+                .or_else(|| get_string(artifact, "description")),
+            content: if config.full_content {
+                Some(get_content(artifact, config.content_snapshot.as_ref()))
+            } else {
+                None
+            },
+            categories: get_categories(artifact),
+        }
+    }
+}
+
+fn write_person(ret: &mut String, tag: &str, indent: &str, person: &AtomPerson) {
+    use std::fmt::Write;
+
+    let _ = writeln!(ret, "{}<{}>", indent, tag);
+    let _ = writeln!(ret, "{}  <name>{}</name>", indent, escape_xml(&person.name));
+    if let Some(ref email) = person.email {
+        let _ = writeln!(ret, "{}  <email>{}</email>", indent, escape_xml(email));
+    }
+    if let Some(ref uri) = person.uri {
+        let _ = writeln!(ret, "{}  <uri>{}</uri>", indent, escape_xml(uri));
+    }
+    let _ = writeln!(ret, "{}</{}>", indent, tag);
+}
+
+impl AtomConfig {
+    /// Render an Atom 1.0 document with `entries`. `self_link` is the feed's own URL, if known.
+    /// The feed's `updated` date is the newest entry's, or the Unix epoch if no entry has one so
+    /// that unchanged feeds render the same, and entries without an `updated` date use it as well.
+    pub fn render(&self, entries: &[AtomEntry], self_link: Option<&str>) -> String {
+        use std::fmt::Write;
+
+        let updated = entries
+            .iter()
+            .filter_map(|e| e.updated)
+            .max()
+            .unwrap_or_else(|| DateTime::UNIX_EPOCH.fixed_offset());
+        let mut ret = String::new();
+        let _ = writeln!(ret, r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        let _ = writeln!(ret, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        let _ = writeln!(
+            ret,
+            "  <id>{}</id>",
+            escape_xml(self.id.as_ref().unwrap_or(&self.link))
+        );
+        let _ = writeln!(ret, "  <title>{}</title>", escape_xml(&self.title));
+        if let Some(ref subtitle) = self.subtitle {
+            let _ = writeln!(ret, "  <subtitle>{}</subtitle>", escape_xml(subtitle));
+        }
+        let _ = writeln!(ret, "  <updated>{}</updated>", updated.to_rfc3339());
+        let _ = writeln!(
+            ret,
+            r#"  <link rel="alternate" type="text/html" href="{}"/>"#,
+            escape_xml(&self.link)
+        );
+        if let Some(self_link) = self_link {
+            let _ = writeln!(
+                ret,
+                r#"  <link rel="self" type="application/atom+xml" href="{}"/>"#,
+                escape_xml(self_link)
+            );
+        }
+        for author in self.authors.iter() {
+            write_person(&mut ret, "author", "  ", author);
+        }
+        if let Some(ref rights) = self.rights {
+            let _ = writeln!(ret, "  <rights>{}</rights>", escape_xml(rights));
+        }
+        let _ = writeln!(ret, r#"  <generator>libssg</generator>"#);
+        for entry in entries {
+            let _ = writeln!(ret, "  <entry>");
+            let _ = writeln!(ret, "    <id>{}</id>", escape_xml(&entry.id));
+            let _ = writeln!(ret, "    <title>{}</title>", escape_xml(&entry.title));
+            let _ = writeln!(
+                ret,
+                r#"    <link rel="alternate" type="text/html" href="{}"/>"#,
+                escape_xml(&entry.link)
+            );
+            let _ = writeln!(
+                ret,
+                "    <updated>{}</updated>",
+                entry.updated.unwrap_or(updated).to_rfc3339()
+            );
+            if let Some(published) = entry.published {
+                let _ = writeln!(ret, "    <published>{}</published>", published.to_rfc3339());
+            }
+            for author in entry.authors.iter() {
+                write_person(&mut ret, "author", "    ", author);
+            }
+            for category in entry.categories.iter() {
+                let _ = writeln!(ret, r#"    <category term="{}"/>"#, escape_xml(category));
+            }
+            if let Some(ref summary) = entry.summary {
+                let _ = writeln!(
+                    ret,
+                    r#"    <summary type="html">{}</summary>"#,
+                    escape_xml(summary)
+                );
+            }
+            if let Some(ref content) = entry.content {
+                let _ = writeln!(
+                    ret,
+                    r#"    <content type="html">{}</content>"#,
+                    escape_xml(content)
+                );
+            }
+            let _ = writeln!(ret, "  </entry>");
+        }
+        let _ = write!(ret, "</feed>");
+        ret
+    }
+}
+
+/// Generate an Atom feed of the artifacts of `query`, eg
+/// `atom_feed(snapshot("posts").sort_by_key("date").rev().take(10), config)`. The feed is saved
+/// in the metadata key `body`; use [`Renderer::body`](crate::Renderer::body) to output it.
+pub fn atom_feed<Q: Into<SnapshotQuery>>(query: Q, config: AtomConfig) -> Compiler {
+    let query = query.into();
+    Box::new(move |state: &mut State, dest_path: &Path| {
+        let entries = query
+            .run(state)?
+            .into_iter()
+            .map(|artifact| AtomEntry::from_artifact(artifact, &config))
+            .collect::<Vec<AtomEntry>>();
+        if config.authors.is_empty() && entries.iter().any(|e| e.authors.is_empty()) {
+            Err(format!(
+                "Atom feed {} needs feed authors: not every entry has an `author`",
+                dest_path.display()
+            ))?;
+        }
+        let self_link = absolute_url(&config.link, dest_path);
+        let mut metadata_map: Map<String, Value> = Map::new();
+        metadata_map.insert(
+            "body".into(),
+            config.render(&entries, Some(&self_link)).into(),
+        );
+        Ok(metadata_map)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atom_person() {
+        assert_eq!(
+            AtomPerson::parse("epilys <epilys@example.com>"),
+            AtomPerson {
+                name: "epilys".into(),
+                email: Some("epilys@example.com".into()),
+                uri: None
+            }
+        );
+        assert_eq!(
+            AtomPerson::parse("epilys@example.com (epilys)"),
+            AtomPerson::parse("epilys <epilys@example.com>")
+        );
+        assert_eq!(AtomPerson::parse("epilys").email, None);
+    }
+
+    #[test]
+    fn test_atom_feed() {
+        let mut config = AtomConfig::new("A & B", "https://example.com");
+        config.authors.push(AtomPerson::parse("epilys"));
+        let entry = |title: &str, date: &str| AtomEntry {
+            id: format!("urn:uuid:{}", uuid_from_path(Path::new(title))),
+            title: title.to_string(),
+            link: format!("https://example.com/{}.html", title),
+            updated: parse_date(date),
+            published: parse_date(date),
+            authors: vec![],
+            summary: Some("<b>summary</b>".into()),
+            content: Some("<p>content</p>".into()),
+            categories: vec!["rust".into()],
+        };
+        let output = config.render(
+            &[entry("first", "2019-06-15"), entry("second", "?")],
+            Some("https://example.com/atom.xml"),
+        );
+
+        let doc = roxmltree::Document::parse(&output).unwrap();
+        let feed = doc.root_element();
+        assert_eq!(feed.tag_name().name(), "feed");
+        assert_eq!(
+            feed.tag_name().namespace(),
+            Some("http://www.w3.org/2005/Atom")
+        );
+        fn child<'a, 'i>(
+            node: roxmltree::Node<'a, 'i>,
+            name: &str,
+        ) -> Option<roxmltree::Node<'a, 'i>> {
+            node.children().find(|c| c.tag_name().name() == name)
+        }
+        // Required feed elements.
+        assert_eq!(
+            child(feed, "id").unwrap().text(),
+            Some("https://example.com")
+        );
+        assert_eq!(child(feed, "title").unwrap().text(), Some("A & B"));
+        let updated = child(feed, "updated").unwrap().text().unwrap();
+        assert_eq!(updated, "2019-06-15T00:00:00+00:00");
+        assert!(DateTime::parse_from_rfc3339(updated).is_ok());
+
+        let entries = feed
+            .children()
+            .filter(|c| c.tag_name().name() == "entry")
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        for entry in entries.iter() {
+            // Required entry elements.
+            assert!(child(*entry, "id")
+                .unwrap()
+                .text()
+                .unwrap()
+                .starts_with("urn:uuid:"));
+            assert!(child(*entry, "title").is_some());
+            assert_eq!(child(*entry, "updated").unwrap().text(), Some(updated));
+            let content = child(*entry, "content").unwrap();
+            assert_eq!(content.attribute("type"), Some("html"));
+            assert_eq!(content.text(), Some("<p>content</p>"));
+            assert_eq!(
+                child(*entry, "summary").unwrap().text(),
+                Some("<b>summary</b>")
+            );
+            assert_eq!(
+                child(*entry, "category").unwrap().attribute("term"),
+                Some("rust")
+            );
+        }
+        assert!(child(entries[0], "published").is_some());
+        assert!(child(entries[1], "published").is_none());
+
+        // Feeds without dates fall back to a fixed date, not the time of the build.
+        let output = config.render(&[entry("undated", "?")], None);
+        assert!(output.contains("<updated>1970-01-01T00:00:00+00:00</updated>"));
+    }
+
+    #[test]
+    fn test_atom_entry_updated() {
+        let artifact = BuildArtifact {
+            uuid: uuid_from_path(Path::new("Cargo.toml")),
+            path: "cargo.html".into(),
+            root: None,
+            resource: "Cargo.toml".into(),
+            metadata: Map::new(),
+            contents: String::new(),
+            snapshots: Default::default(),
+        };
+        let config = AtomConfig::new("A", "https://example.com");
+        let mtime = fs::metadata("Cargo.toml").unwrap().modified().unwrap();
+        assert_eq!(
+            AtomEntry::from_artifact(&artifact, &config).updated,
+            Some(DateTime::<chrono::Utc>::from(mtime).fixed_offset())
+        );
+        let mut artifact = artifact;
+        artifact.metadata.insert("date".into(), "2019-06-15".into());
+        assert_eq!(
+            AtomEntry::from_artifact(&artifact, &config).updated,
+            parse_date("2019-06-15")
+        );
+    }
+}
@@ -0,0 +1,406 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! [Gemini](https://geminiprotocol.net/) capsule output: gemtext from the pandoc AST.
+//!
+//! ```no_run
+//! # use libssg::*;
+//! # fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+//! let mut state = State::new()?;
+//! state.add_output_root("capsule", "_capsule")?;
+//! state
+//!     .then(match_pattern(
+//!         "^posts/*",
+//!         Route::SetExtension("html"),
+//!         Renderer::LoadAndApplyTemplate("templates/post.hbs"),
+//!         pandoc(),
+//!     ))
+//!     .then(output_root(
+//!         "capsule",
+//!         match_pattern(
+//!             "^posts/*",
+//!             Route::SetExtension("gmi"),
+//!             gemtext_page(),
+//!             gemtext(),
+//!         ),
+//!     ))
+//!     .finish()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::*;
+
+/// Turns the blocks of a pandoc JSON AST into gemtext.
+///
+/// - Headers become `#`, `##` or `###` lines; deeper levels become `###`.
+/// - Bullet and ordered list items become `*` lines, flattening nested lists.
+/// - Code blocks become preformatted blocks, with the first class as alt text.
+/// - Block quotes become `>` lines.
+/// - Links and images are collected into `=>` lines after the paragraph, heading, list or quote
+///   they appear in.
+/// - Footnotes are numbered and appended at the end.
+/// - Raw blocks are kept if their format is `gemini` or `gemtext`.
+///
+/// Anything else becomes plain text.
+#[derive(Debug, Default)]
+pub struct Gemtext {
+    lines: Vec<String>,
+    links: Vec<(String, String)>,
+    notes: Vec<String>,
+}
+
+impl Gemtext {
+    /// Convert `blocks`, the `blocks` field of pandoc's JSON output.
+    pub fn from_pandoc(blocks: &Value) -> String {
+        let mut ret = Gemtext::default();
+        ret.blocks(blocks);
+        if !ret.notes.is_empty() {
+            ret.lines.push("---".to_string());
+            ret.lines.push(String::new());
+            for (i, note) in std::mem::take(&mut ret.notes).into_iter().enumerate() {
+                ret.lines.push(format!("[{}] {}", i + 1, note));
+            }
+            ret.flush_links();
+        }
+        while ret.lines.last().map(String::is_empty).unwrap_or(false) {
+            ret.lines.pop();
+        }
+        let mut text = ret.lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    fn blocks(&mut self, blocks: &Value) {
+        for block in blocks.as_array().into_iter().flatten() {
+            self.block(block);
+        }
+    }
+
+    /// Emit the links collected so far and a blank line.
+    fn flush_links(&mut self) {
+        for (url, text) in std::mem::take(&mut self.links) {
+            if text.is_empty() || text == url {
+                self.lines.push(format!("=> {}", url));
+            } else {
+                self.lines.push(format!("=> {} {}", url, text));
+            }
+        }
+        self.lines.push(String::new());
+    }
+
+    fn block(&mut self, block: &Value) {
+        let c = &block["c"];
+        match block["t"].as_str().unwrap_or_default() {
+            "Para" | "Plain" => {
+                let text = self.inlines(c);
+                self.lines.extend(text.lines().map(str::to_string));
+                self.flush_links();
+            }
+            "Header" => {
+                let level = c[0].as_u64().unwrap_or(1).clamp(1, 3) as usize;
+                let text = self.inlines(&c[2]);
+                self.lines
+                    .push(format!("{} {}", "#".repeat(level), text.replace('\n', " ")));
+                self.flush_links();
+            }
+            "CodeBlock" => {
+                let alt = c[0][1][0].as_str().unwrap_or_default();
+                self.lines.push(format!("```{}", alt));
+                self.lines.extend(
+                    c[1].as_str()
+                        .unwrap_or_default()
+                        .lines()
+                        .map(str::to_string),
+                );
+                self.lines.push("```".to_string());
+                self.lines.push(String::new());
+            }
+            "RawBlock" => {
+                if let Some("gemini") | Some("gemtext") = c[0].as_str() {
+                    self.lines.extend(
+                        c[1].as_str()
+                            .unwrap_or_default()
+                            .lines()
+                            .map(str::to_string),
+                    );
+                    self.lines.push(String::new());
+                }
+            }
+            "BulletList" | "OrderedList" => {
+                self.list_items(block);
+                self.flush_links();
+            }
+            "BlockQuote" => {
+                for line in self.nested(c) {
+                    if line.is_empty() {
+                        if self.lines.last().map(|l| l != ">").unwrap_or(false) {
+                            self.lines.push(">".to_string());
+                        }
+                    } else {
+                        self.lines.push(format!("> {}", line));
+                    }
+                }
+                if self.lines.last().map(|l| l == ">").unwrap_or(false) {
+                    self.lines.pop();
+                }
+                self.flush_links();
+            }
+            "LineBlock" => {
+                for line in c.as_array().into_iter().flatten() {
+                    let text = self.inlines(line);
+                    self.lines.push(text);
+                }
+                self.flush_links();
+            }
+            "DefinitionList" => {
+                for item in c.as_array().into_iter().flatten() {
+                    let term = self.inlines(&item[0]);
+                    self.lines.push(term);
+                    for definition in item[1].as_array().into_iter().flatten() {
+                        for line in self.nested(definition) {
+                            if !line.is_empty() {
+                                self.lines.push(line);
+                            }
+                        }
+                    }
+                }
+                self.flush_links();
+            }
+            "HorizontalRule" => {
+                self.lines.push("---".to_string());
+                self.lines.push(String::new());
+            }
+            "Div" => self.blocks(&c[1]),
+            "Figure" => {
+                self.blocks(&c[2]);
+                self.blocks(&c[1][1]);
+            }
+            "Null" => {}
+            _ => {
+                let text = stringify(c);
+                if !text.is_empty() {
+                    self.lines.push(text);
+                    self.lines.push(String::new());
+                }
+            }
+        }
+    }
+
+    /// Render `blocks` as lines without emitting their links.
+    fn nested(&mut self, blocks: &Value) -> Vec<String> {
+        let lines = std::mem::take(&mut self.lines);
+        let links = std::mem::take(&mut self.links);
+        self.blocks(blocks);
+        let ret = std::mem::replace(&mut self.lines, lines);
+        let nested_links = std::mem::replace(&mut self.links, links);
+        self.links.extend(nested_links);
+        // Links of nested blocks are emitted by the enclosing block.
+        ret.into_iter().filter(|l| !l.starts_with("=>")).collect()
+    }
+
+    fn list_items(&mut self, list: &Value) {
+        let (items, start) = match list["t"].as_str() {
+            Some("OrderedList") => (&list["c"][1], list["c"][0][0].as_u64()),
+            _ => (&list["c"], None),
+        };
+        for (i, item) in items.as_array().into_iter().flatten().enumerate() {
+            let mut first = true;
+            for block in item.as_array().into_iter().flatten() {
+                match block["t"].as_str() {
+                    Some("BulletList") | Some("OrderedList") => self.list_items(block),
+                    Some("Plain") | Some("Para") => {
+                        let text = self.inlines(&block["c"]).replace('\n', " ");
+                        self.lines.push(match start {
+                            Some(start) if first => {
+                                format!("* {}. {}", start + i as u64, text)
+                            }
+                            _ => format!("* {}", text),
+                        });
+                        first = false;
+                    }
+                    _ => {
+                        let lines = self.nested(&Value::Array(vec![block.clone()]));
+                        self.lines
+                            .extend(lines.into_iter().filter(|l| !l.is_empty()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the text of `inlines`, collecting links and footnotes.
+    fn inlines(&mut self, inlines: &Value) -> String {
+        let mut ret = String::new();
+        for inline in inlines.as_array().into_iter().flatten() {
+            let c = &inline["c"];
+            match inline["t"].as_str().unwrap_or_default() {
+                "Str" => ret.push_str(c.as_str().unwrap_or_default()),
+                "Space" | "SoftBreak" => ret.push(' '),
+                "LineBreak" => ret.push('\n'),
+                "Emph" | "Strong" | "Strikeout" | "Superscript" | "Subscript" | "SmallCaps"
+                | "Underline" => ret.push_str(&self.inlines(c)),
+                "Quoted" => {
+                    let (open, close) = match c[0]["t"].as_str() {
+                        Some("SingleQuote") => ('‘', '’'),
+                        _ => ('“', '”'),
+                    };
+                    ret.push(open);
+                    ret.push_str(&self.inlines(&c[1]));
+                    ret.push(close);
+                }
+                "Cite" => ret.push_str(&self.inlines(&c[1])),
+                "Code" | "Math" => ret.push_str(c[1].as_str().unwrap_or_default()),
+                "RawInline" => {
+                    if let Some("gemini") | Some("gemtext") = c[0].as_str() {
+                        ret.push_str(c[1].as_str().unwrap_or_default());
+                    }
+                }
+                "Link" | "Image" => {
+                    let text = self.inlines(&c[1]);
+                    ret.push_str(&text);
+                    let url = c[2][0].as_str().unwrap_or_default().to_string();
+                    let text = match c[2][1].as_str() {
+                        Some(title) if text.is_empty() && !title.is_empty() => title.to_string(),
+                        _ => text.replace('\n', " "),
+                    };
+                    self.links.push((url, text));
+                }
+                "Note" => {
+                    let note = self.nested(c).join(" ");
+                    self.notes
+                        .push(note.split_whitespace().collect::<Vec<_>>().join(" "));
+                    ret.push_str(&format!("[{}]", self.notes.len()));
+                }
+                "Span" => ret.push_str(&self.inlines(&c[1])),
+                _ => {}
+            }
+        }
+        ret
+    }
+}
+
+/// Returns the text of every `Str` in `value`, separated by spaces.
+fn stringify(value: &Value) -> String {
+    fn collect(value: &Value, out: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => match (map.get("t"), map.get("c")) {
+                (Some(Value::String(t)), Some(Value::String(s))) if t == "Str" => {
+                    out.push(s.clone())
+                }
+                _ => map.values().for_each(|v| collect(v, out)),
+            },
+            Value::Array(list) => list.iter().for_each(|v| collect(v, out)),
+            _ => {}
+        }
+    }
+    let mut out = vec![];
+    collect(value, &mut out);
+    out.join(" ")
+}
+
+/// A compiler that converts a file with `pandoc` to gemtext, see [`Gemtext`](Gemtext). Metadata
+/// is parsed like [`pandoc`](crate::compilers::pandoc::pandoc) does.
+pub fn gemtext() -> Compiler {
+    Box::new(|state: &mut State, path: &Path| {
+        let (mut metadata, blocks) = pandoc::pandoc_ast(state, path)?;
+        debug!(path = %path.display(), ?metadata, "parsed metadata");
+        metadata.insert("body".into(), Gemtext::from_pandoc(&blocks).into());
+        Ok(metadata)
+    })
+}
+
+/// A renderer for gemtext pages: the `title` as a heading, the `date` if any, and the `body`.
+/// Gemtext is not HTML, so nothing is escaped.
+pub fn gemtext_page() -> Renderer {
+    Renderer::Custom(Box::new(render_gemtext_page))
+}
+
+fn render_gemtext_page(_state: &mut State, context: &mut Map<String, Value>) -> Result<String> {
+    let mut ret = String::new();
+    if let Some(title) = context.get("title").and_then(Value::as_str) {
+        ret.push_str(&format!("# {}\n\n", title));
+    }
+    if let Some(date) = context.get("date").and_then(Value::as_str) {
+        ret.push_str(&format!("{}\n\n", date));
+    }
+    ret.push_str(
+        context
+            .get("body")
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("metadata does not contain `body`: {:#?}", &context))?,
+    );
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_gemtext() {
+        let str_ = |s: &str| json!({"t": "Str", "c": s});
+        let space = json!({"t": "Space"});
+        let link = |text: &str, url: &str| json!({"t": "Link", "c": [["", [], []], [str_(text)], [url, ""]]});
+        let blocks = json!([
+            {"t": "Header", "c": [4, ["intro", [], []], [str_("Intro")]]},
+            {"t": "Para", "c": [str_("See"), space, link("this", "https://example.com"), space,
+                str_("and"), space, link("that", "/that.gmi"),
+                {"t": "Note", "c": [{"t": "Para", "c": [str_("A"), space, str_("note.")]}]}]},
+            {"t": "BulletList", "c": [
+                [{"t": "Plain", "c": [str_("one")]}],
+                [{"t": "Plain", "c": [str_("two")]},
+                 {"t": "OrderedList", "c": [[3, {"t": "Decimal"}, {"t": "Period"}],
+                    [[{"t": "Plain", "c": [link("three", "/3")]}]]]}]
+            ]},
+            {"t": "CodeBlock", "c": [["", ["rust"], []], "fn main() {}\n"]},
+            {"t": "BlockQuote", "c": [{"t": "Para", "c": [str_("quoted")]},
+                {"t": "Para", "c": [str_("more")]}]}
+        ]);
+        assert_eq!(
+            Gemtext::from_pandoc(&blocks),
+            "### Intro
+
+See this and that[1]
+=> https://example.com this
+=> /that.gmi that
+
+* one
+* two
+* 3. three
+=> /3 three
+
+```rust
+fn main() {}
+```
+
+> quoted
+>
+> more
+
+---
+
+[1] A note.
+"
+        );
+    }
+}
@@ -0,0 +1,249 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! [JSON Feed 1.1](https://jsonfeed.org/version/1.1) generation.
+//!
+//! Feed items are built from the artifacts of a [`SnapshotQuery`](crate::SnapshotQuery) with
+//! the same metadata conventions as [`atom`](crate::compilers::atom), and additionally `image`,
+//! the URL or site-relative path of the item's main image.
+
+use super::atom::AtomPerson;
+use super::rss::{absolute_url, get_categories, get_content, get_string};
+use super::*;
+use serde::Serialize;
+
+const VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+/// Configuration of a JSON feed.
+#[derive(Debug, Clone)]
+pub struct JsonFeedConfig {
+    pub title: String,
+    pub description: Option<String>,
+    /// The site's URL, eg `https://example.com`. Item URLs are this URL joined with the
+    /// artifact's path.
+    pub home_page_url: String,
+    pub icon: Option<String>,
+    pub favicon: Option<String>,
+    pub language: Option<String>,
+    pub authors: Vec<JsonFeedAuthor>,
+    /// Use this named snapshot of each artifact's rendered contents (see
+    /// [`Renderer::Snapshot`](crate::Renderer::Snapshot)) as `content_html` instead of the
+    /// compiled `body`.
+    pub content_snapshot: Option<String>,
+}
+
+impl JsonFeedConfig {
+    pub fn new<S: Into<String>>(title: S, home_page_url: S) -> Self {
+        JsonFeedConfig {
+            title: title.into(),
+            description: None,
+            home_page_url: home_page_url.into(),
+            icon: None,
+            favicon: None,
+            language: None,
+            authors: vec![],
+            content_snapshot: None,
+        }
+    }
+}
+
+/// A JSON feed document.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonFeed {
+    pub version: &'static str,
+    pub title: String,
+    pub home_page_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feed_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<JsonFeedAuthor>,
+    pub items: Vec<JsonFeedItem>,
+}
+
+/// An author of a JSON feed or item.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct JsonFeedAuthor {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+}
+
+/// An item of a JSON feed.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonFeedItem {
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    pub content_html: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// RFC 3339 date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_published: Option<String>,
+    /// RFC 3339 date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_modified: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<JsonFeedAuthor>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl JsonFeedItem {
+    /// Create an item from an artifact's metadata, see the [module documentation](self).
+    pub fn from_artifact(artifact: &BuildArtifact, config: &JsonFeedConfig) -> Self {
+        let date = |key| {
+            get_string(artifact, key)
+                .as_deref()
+                .and_then(parse_date)
+                .map(|d| d.to_rfc3339())
+        };
+        let authors = match artifact.metadata.get("author") {
+            Some(Value::String(author)) => vec![author.as_str()],
+            Some(Value::Array(list)) => list.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        JsonFeedItem {
+            id: format!("urn:uuid:{}", artifact.uuid),
+            url: absolute_url(&config.home_page_url, &artifact.path),
+            title: get_string(artifact, "title")
+                .unwrap_or_else(|| format!("No title, uuid: {}", artifact.uuid)),
+            content_html: get_content(artifact, config.content_snapshot.as_ref()),
+            summary: get_string(artifact, "summary")
+                .or_else(|| get_string(artifact, "description")),
+            image: get_string(artifact, "image").map(|image| {
+                if image.contains("://") {
+                    image
+                } else {
+                    absolute_url(
+                        &config.home_page_url,
+                        Path::new(image.trim_start_matches('/')),
+                    )
+                }
+            }),
+            date_published: date("date"),
+            date_modified: date("updated"),
+            authors: authors
+                .into_iter()
+                .map(|author| JsonFeedAuthor {
+                    name: AtomPerson::parse(author).name,
+                    url: None,
+                    avatar: None,
+                })
+                .collect(),
+            tags: get_categories(artifact),
+        }
+    }
+}
+
+impl JsonFeedConfig {
+    /// Create a feed document with `items`. `feed_url` is the feed's own URL, if known.
+    pub fn feed(&self, items: Vec<JsonFeedItem>, feed_url: Option<String>) -> JsonFeed {
+        JsonFeed {
+            version: VERSION,
+            title: self.title.clone(),
+            home_page_url: self.home_page_url.clone(),
+            feed_url,
+            description: self.description.clone(),
+            icon: self.icon.clone(),
+            favicon: self.favicon.clone(),
+            language: self.language.clone(),
+            authors: self.authors.clone(),
+            items,
+        }
+    }
+}
+
+/// Generate a JSON feed of the artifacts of `query`, eg
+/// `json_feed(snapshot("posts").sort_by_key("date").rev().take(10), config)`. The feed is saved
+/// in the metadata key `body`; use [`Renderer::body`](crate::Renderer::body) to output it.
+pub fn json_feed<Q: Into<SnapshotQuery>>(query: Q, config: JsonFeedConfig) -> Compiler {
+    let query = query.into();
+    Box::new(move |state: &mut State, dest_path: &Path| {
+        let items = query
+            .run(state)?
+            .into_iter()
+            .map(|artifact| JsonFeedItem::from_artifact(artifact, &config))
+            .collect::<Vec<JsonFeedItem>>();
+        let feed = config.feed(items, Some(absolute_url(&config.home_page_url, dest_path)));
+        let mut metadata_map: Map<String, Value> = Map::new();
+        metadata_map.insert("body".into(), serde_json::to_string_pretty(&feed)?.into());
+        Ok(metadata_map)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_feed() {
+        let mut metadata = Map::new();
+        metadata.insert("title".into(), "first".into());
+        metadata.insert("date".into(), "June 15, 2019".into());
+        metadata.insert("author".into(), "epilys <epilys@example.com>".into());
+        metadata.insert("tags".into(), "rust, web".into());
+        metadata.insert("image".into(), "/images/a.png".into());
+        metadata.insert("body".into(), "<p>body</p>".into());
+        let artifact = BuildArtifact {
+            uuid: uuid_from_path(Path::new("posts/first.md")),
+            path: PathBuf::from("posts/first.html"),
+            root: None,
+            resource: PathBuf::from("posts/first.md"),
+            metadata,
+            contents: String::new(),
+            snapshots: Default::default(),
+        };
+        let config = JsonFeedConfig::new("A & B", "https://example.com/");
+        let item = JsonFeedItem::from_artifact(&artifact, &config);
+        let feed = config.feed(
+            vec![item],
+            Some("https://example.com/feed.json".to_string()),
+        );
+        let value = serde_json::to_value(&feed).unwrap();
+
+        assert_eq!(value["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(value["title"], "A & B");
+        assert_eq!(value["feed_url"], "https://example.com/feed.json");
+        assert!(value.get("description").is_none());
+        let item = &value["items"][0];
+        assert_eq!(item["id"], format!("urn:uuid:{}", artifact.uuid));
+        assert_eq!(item["url"], "https://example.com/posts/first.html");
+        assert_eq!(item["content_html"], "<p>body</p>");
+        assert_eq!(item["date_published"], "2019-06-15T00:00:00+00:00");
+        assert_eq!(item["image"], "https://example.com/images/a.png");
+        assert_eq!(item["authors"][0]["name"], "epilys");
+        assert_eq!(item["tags"], serde_json::json!(["rust", "web"]));
+        assert!(item.get("summary").is_none());
+    }
+}
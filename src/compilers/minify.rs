@@ -0,0 +1,154 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! CSS and JavaScript minification, enabled with the `minify` feature.
+//!
+//! The minifiers wrap another compiler and minify the `body` it returns, so they work with
+//! source files as well as compiled ones:
+//!
+//! ```no_run
+//! # use libssg::*;
+//! # fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+//! State::new()?
+//!     .then(compile(
+//!         r"^css/.*\.css$",
+//!         Route::Id,
+//!         minify_css(file_contents(), false),
+//!     ))
+//!     .then(compile(r"^js/.*\.js$", Route::Id, minify_js(file_contents())))
+//!     .finish()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::*;
+use base64::Engine;
+use lightningcss::stylesheet::{MinifyOptions, ParserOptions, PrinterOptions, StyleSheet};
+
+fn body(metadata: &Map<String, Value>, path: &Path) -> Result<String> {
+    Ok(metadata
+        .get("body")
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("Compiled {} has no `body` to minify", path.display()))?
+        .to_string())
+}
+
+/// Minify CSS, optionally with an inline source map that refers to `path`.
+pub fn minify_css_str(css: &str, path: &Path, source_map: bool) -> Result<String> {
+    let filename = path.display().to_string();
+    let mut stylesheet = StyleSheet::parse(
+        css,
+        ParserOptions {
+            filename: filename.clone(),
+            ..ParserOptions::default()
+        },
+    )
+    .map_err(|err| format!("Could not parse {}: {}", filename, err))?;
+    stylesheet
+        .minify(MinifyOptions::default())
+        .map_err(|err| format!("Could not minify {}: {}", filename, err))?;
+    let mut map = parcel_sourcemap::SourceMap::new("/");
+    let mut printer_options = PrinterOptions {
+        minify: true,
+        ..PrinterOptions::default()
+    };
+    if source_map {
+        let source = map.add_source(&filename);
+        map.set_source_content(source as usize, css)
+            .map_err(|err| format!("Could not create source map of {}: {}", filename, err))?;
+        printer_options.source_map = Some(&mut map);
+    }
+    let mut code = stylesheet
+        .to_css(printer_options)
+        .map_err(|err| format!("Could not minify {}: {}", filename, err))?
+        .code;
+    if source_map {
+        let json = map
+            .to_json(None)
+            .map_err(|err| format!("Could not create source map of {}: {}", filename, err))?;
+        code.push_str(&format!(
+            "\n/*# sourceMappingURL=data:application/json;base64,{} */",
+            base64::engine::general_purpose::STANDARD.encode(json)
+        ));
+    }
+    Ok(code)
+}
+
+/// Minify JavaScript. Files with the `.mjs` extension are parsed as modules.
+pub fn minify_js_str(js: &str, path: &Path) -> Result<String> {
+    use minify_js::{minify, Session, TopLevelMode};
+
+    let mode = match path.extension() {
+        Some(extension) if extension == "mjs" => TopLevelMode::Module,
+        _ => TopLevelMode::Global,
+    };
+    let session = Session::new();
+    let mut out = Vec::with_capacity(js.len());
+    minify(&session, mode, js.as_bytes(), &mut out)
+        .map_err(|err| format!("Could not minify {}: {:?}", path.display(), err))?;
+    Ok(String::from_utf8(out)?)
+}
+
+/// A compiler that minifies the CSS `body` of `compiler`. If `source_map` is set, an inline
+/// source map is appended; for compiled stylesheets it maps to the compiled CSS.
+pub fn minify_css(compiler: Compiler, source_map: bool) -> Compiler {
+    Box::new(move |state: &mut State, path: &Path| {
+        let mut metadata = compiler(state, path)?;
+        let css = minify_css_str(&body(&metadata, path)?, path, source_map)?;
+        metadata.insert("body".into(), css.into());
+        Ok(metadata)
+    })
+}
+
+/// A compiler that minifies the JavaScript `body` of `compiler`.
+pub fn minify_js(compiler: Compiler) -> Compiler {
+    Box::new(move |state: &mut State, path: &Path| {
+        let mut metadata = compiler(state, path)?;
+        let js = minify_js_str(&body(&metadata, path)?, path)?;
+        metadata.insert("body".into(), js.into());
+        Ok(metadata)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify() {
+        let path = Path::new("css/main.css");
+        assert_eq!(
+            minify_css_str("a {\n  color: #ff0000;\n}\n", path, false).unwrap(),
+            "a{color:red}"
+        );
+        assert!(minify_css_str("a { color: red }", path, true)
+            .unwrap()
+            .contains("sourceMappingURL=data:application/json;base64,"));
+        assert_eq!(
+            minify_js_str(
+                "const main = () => { let my_first_variable = 1; };",
+                Path::new("js/main.js")
+            )
+            .unwrap(),
+            "const main=()=>{let a=1}"
+        );
+    }
+}
@@ -0,0 +1,403 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! RSS 2.0 feed generation.
+//!
+//! Feed items are built from the artifacts of a [`SnapshotQuery`](crate::SnapshotQuery) using the
+//! following metadata keys:
+//!
+//! - `title`
+//! - `date`, parsed with [`parse_date`](crate::parse_date) and formatted as RFC 822
+//! - `author`, as `<author>` if it is an email address and as `<dc:creator>` otherwise
+//! - `tags` and `categories`, as `<category>` elements
+//! - `enclosure`, an object with `url`, `length` and `type`
+//! - `body`, or a named snapshot of the rendered contents, as the description
+
+use super::*;
+use chrono::{DateTime, FixedOffset};
+
+/// RFC 822 date format with a four digit year, as recommended by the RSS specification.
+const RFC822: &str = "%a, %d %b %Y %H:%M:%S %z";
+
+/// Channel configuration of an RSS feed.
+#[derive(Debug, Clone)]
+pub struct RssChannel {
+    pub title: String,
+    pub description: String,
+    /// The site's URL, eg `https://example.com`. Item links are this URL joined with the
+    /// artifact's path.
+    pub link: String,
+    pub language: Option<String>,
+    pub copyright: Option<String>,
+    /// Minutes a feed may be cached before refreshing.
+    pub ttl: Option<u32>,
+    /// Use this named snapshot of each artifact's rendered contents (see
+    /// [`Renderer::Snapshot`](crate::Renderer::Snapshot)) as item descriptions instead of the
+    /// compiled `body`.
+    pub content_snapshot: Option<String>,
+}
+
+impl RssChannel {
+    pub fn new<S: Into<String>>(title: S, description: S, link: S) -> Self {
+        RssChannel {
+            title: title.into(),
+            description: description.into(),
+            link: link.into(),
+            language: None,
+            copyright: None,
+            ttl: None,
+            content_snapshot: None,
+        }
+    }
+}
+
+/// An item of an RSS feed.
+#[derive(Debug, Clone)]
+pub struct RssItem {
+    pub title: String,
+    pub link: String,
+    /// HTML description.
+    pub description: String,
+    /// Permanent, unique identifier. The feed uses the item's link.
+    pub guid: String,
+    pub pub_date: Option<DateTime<FixedOffset>>,
+    pub author: Option<String>,
+    pub categories: Vec<String>,
+    pub enclosure: Option<RssEnclosure>,
+}
+
+/// A media object attached to an [`RssItem`](RssItem).
+#[derive(Debug, Clone)]
+pub struct RssEnclosure {
+    pub url: String,
+    /// Size in bytes.
+    pub length: u64,
+    pub mime_type: String,
+}
+
+/// Returns the absolute URL of `path` under `base`.
+pub(crate) fn absolute_url(base: &str, path: &Path) -> String {
+    format!("{}{}", base.trim_end_matches('/'), path_to_url(path))
+}
+
+/// Returns the string value of metadata key `key`.
+pub(crate) fn get_string(artifact: &BuildArtifact, key: &str) -> Option<String> {
+    artifact
+        .metadata
+        .get(key)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+/// Returns the artifact's HTML contents: saved snapshot `content_snapshot` if it is set, or the
+/// compiled `body` otherwise.
+pub(crate) fn get_content(artifact: &BuildArtifact, content_snapshot: Option<&String>) -> String {
+    match content_snapshot {
+        Some(name) => artifact.snapshots.get(name).cloned(),
+        None => get_string(artifact, "body"),
+    }
+    .unwrap_or_default()
+}
+
+/// Returns the terms of metadata keys `tags` and `categories`.
+pub(crate) fn get_categories(artifact: &BuildArtifact) -> Vec<String> {
+    let mut ret = vec![];
+    for key in &["tags", "categories"] {
+        for term in artifact.metadata.get(*key).map(terms).unwrap_or_default() {
+            if !ret.contains(&term) {
+                ret.push(term);
+            }
+        }
+    }
+    ret
+}
+
+impl RssItem {
+    /// Create an item from an artifact's metadata, see the [module documentation](self).
+    pub fn from_artifact(artifact: &BuildArtifact, channel: &RssChannel) -> Self {
+        let link = absolute_url(&channel.link, &artifact.path);
+        RssItem {
+            title: get_string(artifact, "title")
+                .unwrap_or_else(|| format!("No title, uuid: {}", artifact.uuid)),
+            description: get_content(artifact, channel.content_snapshot.as_ref()),
+            guid: link.clone(),
+            link,
+            pub_date: get_string(artifact, "date").as_deref().and_then(parse_date),
+            author: get_string(artifact, "author"),
+            categories: get_categories(artifact),
+            enclosure: artifact.metadata.get("enclosure").and_then(|e| {
+                Some(RssEnclosure {
+                    url: e.get("url")?.as_str()?.to_string(),
+                    length: e
+                        .get("length")
+                        .and_then(|l| l.as_u64().or_else(|| l.as_str()?.parse().ok()))
+                        .unwrap_or(0),
+                    mime_type: e.get("type")?.as_str()?.to_string(),
+                })
+            }),
+        }
+    }
+}
+
+impl RssChannel {
+    /// Render an RSS 2.0 document with `items`. `self_link` is the feed's own URL, if known.
+    /// The channel's `pubDate` and `lastBuildDate` are the newest item's date.
+    pub fn render(&self, items: &[RssItem], self_link: Option<&str>) -> String {
+        use std::fmt::Write;
+
+        let mut ret = String::new();
+        let newest = items.iter().filter_map(|i| i.pub_date).max();
+        let _ = writeln!(ret, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        let _ = writeln!(
+            ret,
+            r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/elements/1.1/">"#
+        );
+        let _ = writeln!(ret, "<channel>");
+        let _ = writeln!(ret, "  <title>{}</title>", escape_xml(&self.title));
+        let _ = writeln!(ret, "  <link>{}</link>", escape_xml(&self.link));
+        let _ = writeln!(
+            ret,
+            "  <description>{}</description>",
+            escape_xml(&self.description)
+        );
+        if let Some(self_link) = self_link {
+            let _ = writeln!(
+                ret,
+                r#"  <atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+                escape_xml(self_link)
+            );
+        }
+        if let Some(ref language) = self.language {
+            let _ = writeln!(ret, "  <language>{}</language>", escape_xml(language));
+        }
+        if let Some(ref copyright) = self.copyright {
+            let _ = writeln!(ret, "  <copyright>{}</copyright>", escape_xml(copyright));
+        }
+        if let Some(newest) = newest {
+            let _ = writeln!(ret, "  <pubDate>{}</pubDate>", newest.format(RFC822));
+            let _ = writeln!(
+                ret,
+                "  <lastBuildDate>{}</lastBuildDate>",
+                newest.format(RFC822)
+            );
+        }
+        let _ = writeln!(ret, "  <generator>libssg</generator>");
+        let _ = writeln!(
+            ret,
+            "  <docs>https://www.rssboard.org/rss-specification</docs>"
+        );
+        if let Some(ttl) = self.ttl {
+            let _ = writeln!(ret, "  <ttl>{}</ttl>", ttl);
+        }
+        for item in items {
+            let _ = writeln!(ret, "  <item>");
+            let _ = writeln!(ret, "    <title>{}</title>", escape_xml(&item.title));
+            let _ = writeln!(ret, "    <link>{}</link>", escape_xml(&item.link));
+            let _ = writeln!(
+                ret,
+                "    <description>{}</description>",
+                escape_xml(&item.description)
+            );
+            match item.author {
+                Some(ref author) if author.contains('@') => {
+                    let _ = writeln!(ret, "    <author>{}</author>", escape_xml(author));
+                }
+                Some(ref author) => {
+                    let _ = writeln!(ret, "    <dc:creator>{}</dc:creator>", escape_xml(author));
+                }
+                None => {}
+            }
+            for category in item.categories.iter() {
+                let _ = writeln!(ret, "    <category>{}</category>", escape_xml(category));
+            }
+            if let Some(ref enclosure) = item.enclosure {
+                let _ = writeln!(
+                    ret,
+                    r#"    <enclosure url="{}" length="{}" type="{}"/>"#,
+                    escape_xml(&enclosure.url),
+                    enclosure.length,
+                    escape_xml(&enclosure.mime_type)
+                );
+            }
+            let _ = writeln!(
+                ret,
+                r#"    <guid isPermaLink="true">{}</guid>"#,
+                escape_xml(&item.guid)
+            );
+            if let Some(pub_date) = item.pub_date {
+                let _ = writeln!(ret, "    <pubDate>{}</pubDate>", pub_date.format(RFC822));
+            }
+            let _ = writeln!(ret, "  </item>");
+        }
+        let _ = writeln!(ret, "</channel>");
+        let _ = write!(ret, "</rss>");
+        ret
+    }
+}
+
+/// Generate an RSS feed of the artifacts of `query`, eg
+/// `rss_feed(snapshot("posts").sort_by_key("date").rev().take(10), channel)`. The feed is saved
+/// in the metadata key `body`; use [`Renderer::body`](crate::Renderer::body) to output it.
+pub fn rss_feed<Q: Into<SnapshotQuery>>(query: Q, channel: RssChannel) -> Compiler {
+    let query = query.into();
+    Box::new(move |state: &mut State, dest_path: &Path| {
+        let items = query
+            .run(state)?
+            .into_iter()
+            .map(|artifact| RssItem::from_artifact(artifact, &channel))
+            .collect::<Vec<RssItem>>();
+        let self_link = absolute_url(&channel.link, dest_path);
+        let mut metadata_map: Map<String, Value> = Map::new();
+        metadata_map.insert(
+            "body".into(),
+            channel.render(&items, Some(&self_link)).into(),
+        );
+        Ok(metadata_map)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(title: &str, date: &str) -> RssItem {
+        let link = format!("https://example.com/posts/{}.html", slugify(title));
+        RssItem {
+            title: title.to_string(),
+            description: "<p>Tom &amp; Jerry</p>".to_string(),
+            guid: link.clone(),
+            link,
+            pub_date: parse_date(date),
+            author: None,
+            categories: vec![],
+            enclosure: None,
+        }
+    }
+
+    #[test]
+    fn test_rss_feed() {
+        let mut channel = RssChannel::new("A & B", "<example>", "https://example.com/");
+        channel.ttl = Some(60);
+        let mut first = item("first", "June 15, 2019");
+        first.author = Some("epilys".into());
+        first.categories = vec!["rust".into(), "web".into()];
+        let mut second = item("second <post>", "2020-02-09");
+        second.author = Some("epilys@example.com (epilys)".into());
+        second.enclosure = Some(RssEnclosure {
+            url: "https://example.com/a.mp3".into(),
+            length: 1024,
+            mime_type: "audio/mpeg".into(),
+        });
+        let output = channel.render(
+            &[first, second, item("undated", "?")],
+            Some("https://example.com/rss.xml"),
+        );
+
+        let doc = roxmltree::Document::parse(&output).unwrap();
+        let rss = doc.root_element();
+        assert_eq!(rss.tag_name().name(), "rss");
+        assert_eq!(rss.attribute("version"), Some("2.0"));
+        let channel = rss.first_element_child().unwrap();
+        assert_eq!(channel.tag_name().name(), "channel");
+        let child = |node: roxmltree::Node<'_, '_>, name: &str| -> Option<String> {
+            node.children()
+                .find(|c| c.tag_name().name() == name)
+                .and_then(|c| c.text())
+                .map(str::to_string)
+        };
+        // Required channel elements.
+        assert_eq!(child(channel, "title").unwrap(), "A & B");
+        assert_eq!(child(channel, "link").unwrap(), "https://example.com/");
+        assert_eq!(child(channel, "description").unwrap(), "<example>");
+        assert_eq!(child(channel, "ttl").unwrap(), "60");
+        // Channel dates are RFC 822 and equal to the newest item's.
+        let last_build_date = child(channel, "lastBuildDate").unwrap();
+        assert_eq!(last_build_date, "Sun, 09 Feb 2020 00:00:00 +0000");
+        assert!(DateTime::parse_from_rfc2822(&last_build_date).is_ok());
+        assert_eq!(child(channel, "pubDate").unwrap(), last_build_date);
+        let atom_link = channel
+            .children()
+            .find(|c| c.tag_name().name() == "link" && c.tag_name().namespace().is_some())
+            .unwrap();
+        assert_eq!(
+            atom_link.attribute("href"),
+            Some("https://example.com/rss.xml")
+        );
+        assert_eq!(atom_link.attribute("rel"), Some("self"));
+
+        let items = channel
+            .children()
+            .filter(|c| c.tag_name().name() == "item")
+            .collect::<Vec<_>>();
+        assert_eq!(items.len(), 3);
+        assert_eq!(child(items[0], "title").unwrap(), "first");
+        assert_eq!(
+            child(items[0], "link").unwrap(),
+            "https://example.com/posts/first.html"
+        );
+        assert_eq!(
+            child(items[0], "guid").unwrap(),
+            "https://example.com/posts/first.html"
+        );
+        assert_eq!(
+            child(items[0], "description").unwrap(),
+            "<p>Tom &amp; Jerry</p>"
+        );
+        assert_eq!(
+            child(items[0], "pubDate").unwrap(),
+            "Sat, 15 Jun 2019 00:00:00 +0000"
+        );
+        // `author` must be an email address, names go to `dc:creator`.
+        assert_eq!(child(items[0], "author"), None);
+        assert_eq!(child(items[0], "creator").unwrap(), "epilys");
+        assert_eq!(
+            items[0]
+                .children()
+                .filter(|c| c.tag_name().name() == "category")
+                .count(),
+            2
+        );
+        assert_eq!(child(items[1], "title").unwrap(), "second <post>");
+        assert_eq!(
+            child(items[1], "author").unwrap(),
+            "epilys@example.com (epilys)"
+        );
+        let enclosure = items[1]
+            .children()
+            .find(|c| c.tag_name().name() == "enclosure")
+            .unwrap();
+        assert_eq!(enclosure.attribute("length"), Some("1024"));
+        assert_eq!(enclosure.attribute("type"), Some("audio/mpeg"));
+        assert_eq!(child(items[2], "pubDate"), None);
+    }
+
+    #[test]
+    fn test_absolute_url() {
+        assert_eq!(
+            absolute_url("https://example.com/", Path::new("posts/a.html")),
+            "https://example.com/posts/a.html"
+        );
+        assert_eq!(
+            absolute_url("https://example.com", Path::new("tags/rust/index.html")),
+            "https://example.com/tags/rust/"
+        );
+    }
+}
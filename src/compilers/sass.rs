@@ -0,0 +1,146 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Sass and SCSS stylesheet compiler, enabled with the `sass` feature.
+//!
+//! ```no_run
+//! # use libssg::*;
+//! # fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+//! State::new()?
+//!     .then(compile(
+//!         "^css/main.scss",
+//!         Route::SetExtension("css"),
+//!         sass(vec![]),
+//!     ))
+//!     .finish()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::*;
+use std::cell::RefCell;
+
+/// A [`grass::Fs`](grass::Fs) that records the files it reads.
+#[derive(Debug, Default)]
+struct TrackingFs {
+    read: RefCell<Vec<PathBuf>>,
+}
+
+impl grass::Fs for TrackingFs {
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        self.read.borrow_mut().push(path.to_path_buf());
+        fs::read(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+}
+
+/// A compiler that compiles a Sass or SCSS file to CSS in `body`, searching `load_paths` in
+/// addition to the file's directory for imports. Imported files are recorded as dependencies
+/// (see [`State::add_dependency`](crate::State::add_dependency)), so the stylesheet is
+/// regenerated when a partial changes.
+pub fn sass(load_paths: Vec<PathBuf>) -> Compiler {
+    Box::new(move |state: &mut State, path: &Path| {
+        let tracking = TrackingFs::default();
+        let options = grass::Options::default()
+            .fs(&tracking)
+            .load_paths(&load_paths);
+        let css = grass::from_path(path, &options)
+            .map_err(|err| format!("Could not compile {}: {}", path.display(), err))?;
+        let source = fs::canonicalize(path).ok();
+        for dependency in tracking.read.into_inner() {
+            if fs::canonicalize(&dependency).ok() != source {
+                state.add_dependency(dependency);
+            }
+        }
+        let mut metadata = Map::new();
+        metadata.insert("body".into(), css.into());
+        Ok(metadata)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sass_dependencies() {
+        let mut state = crate::tests::test_state("sass", &[]);
+        let output_dir = state.output_dir().to_path_buf();
+        let root = output_dir.parent().unwrap().to_path_buf();
+        let dir = root.strip_prefix(state.current_dir()).unwrap().join("css");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("_vars.scss"), "$color: red;").unwrap();
+        fs::write(
+            dir.join("main.scss"),
+            "@use 'vars';\na { color: vars.$color; }",
+        )
+        .unwrap();
+
+        let uuid = state
+            .add_page(
+                "main.css".into(),
+                dir.join("main.scss"),
+                &sass(vec![]),
+                Renderer::body(),
+            )
+            .unwrap();
+        assert_eq!(state.dependencies(&uuid), [dir.join("_vars.scss")]);
+        state.finish().unwrap();
+        let css = fs::read_to_string(output_dir.join("main.css")).unwrap();
+        assert!(css.contains("color: red"), "{}", css);
+
+        // Touching the partial regenerates the stylesheet.
+        fs::File::options()
+            .write(true)
+            .open(dir.join("_vars.scss"))
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        let mut state = State::with_dirs(root.join("templates"), &output_dir).unwrap();
+        state.set_manifest_path(None);
+        state
+            .add_page(
+                "main.css".into(),
+                dir.join("main.scss"),
+                &sass(vec![]),
+                Renderer::body(),
+            )
+            .unwrap();
+        assert_eq!(
+            state.plan[&output_dir.join("main.css")].dirty,
+            Some(DirtyReason::Dependency {
+                path: dir.join("_vars.scss")
+            })
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
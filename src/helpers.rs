@@ -21,7 +21,12 @@
 
 //! Helper functions.
 
-use handlebars::{Context, Handlebars, Helper, JsonRender, Output, RenderContext, RenderError};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone};
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, JsonRender, Output, RenderContext, RenderError,
+};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
 
 /// Include HTML string without escaping.
 pub fn include_helper(
@@ -38,7 +43,37 @@ pub fn include_helper(
     Ok(())
 }
 
-/// Format timestamp to date with a chrono format string
+/// Parse a date in one of the formats commonly found in metadata:
+///
+/// - RFC 3339, eg `2019-06-15T10:00:00+03:00`
+/// - RFC 2822, eg `Sat, 15 Jun 2019 10:00:00 +0300`
+/// - `2019-06-15 10:00:00` and `2019-06-15`
+/// - `June 15, 2019` and `Jun 15, 2019`
+///
+/// Dates without a time zone are assumed to be in UTC and dates without a time at midnight.
+pub fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+    if let Ok(d) = DateTime::parse_from_rfc3339(date) {
+        return Some(d);
+    }
+    if let Ok(d) = DateTime::parse_from_rfc2822(date) {
+        return Some(d);
+    }
+    let utc = FixedOffset::east_opt(0)?;
+    for fmt in &["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(d) = NaiveDateTime::parse_from_str(date, fmt) {
+            return utc.from_local_datetime(&d).single();
+        }
+    }
+    for fmt in &["%Y-%m-%d", "%B %d, %Y", "%d %B %Y"] {
+        if let Ok(d) = NaiveDate::parse_from_str(date, fmt) {
+            return utc.from_local_datetime(&d.and_hms_opt(0, 0, 0)?).single();
+        }
+    }
+    None
+}
+
+/// Format timestamp or date (see [`parse_date`](parse_date)) with a chrono format string
 /// Usage: `{{ date_fmt date "%Y-%m-%d" }}`
 pub fn date_fmt(
     h: &Helper,
@@ -47,7 +82,6 @@ pub fn date_fmt(
     _: &mut RenderContext,
     out: &mut dyn Output,
 ) -> Result<(), RenderError> {
-    use chrono::TimeZone;
     let fmt_string = h
         .param(1)
         .ok_or(RenderError::new(
@@ -62,21 +96,161 @@ pub fn date_fmt(
             )
         })?;
 
-    let date_s: i64 = match h
+    let date = match h
         .param(0)
         .ok_or(RenderError::new(
             "Date as first parameter is required for date_fmt helper.",
         ))?
         .value()
     {
-        serde_json::Value::String(s) => chrono::Local
-            .datetime_from_str(&s, "%Y-%m-%d %H:%M:%S")
-            .unwrap()
-            .timestamp(),
-        serde_json::Value::Number(num) if num.as_i64().is_some() => num.as_i64().unwrap(),
-        _ => panic!(),
+        serde_json::Value::String(s) => parse_date(s)
+            .ok_or_else(|| RenderError::new("Could not parse date for date_fmt helper."))?,
+        serde_json::Value::Number(num) if num.as_i64().is_some() => chrono::Local
+            .timestamp_opt(num.as_i64().unwrap(), 0)
+            .single()
+            .ok_or_else(|| RenderError::new("Invalid timestamp for date_fmt helper."))?
+            .fixed_offset(),
+        _ => {
+            return Err(RenderError::new(
+                "Date must be a string or a timestamp for date_fmt helper.",
+            ))
+        }
     };
-    let date = chrono::Local.timestamp(date_s, 0);
     out.write(&date.format(&fmt_string).to_string())?;
     Ok(())
 }
+
+/// Returns an `asset_url` helper that resolves asset destinations to their URLs in `assets`, see
+/// [`fingerprint`](crate::rules::fingerprint).
+/// Usage: `{{ asset_url "css/main.css" }}`
+pub fn asset_url(
+    assets: Arc<RwLock<BTreeMap<String, String>>>,
+) -> Box<dyn HelperDef + Send + Sync> {
+    Box::new(
+        move |h: &Helper,
+              _: &Handlebars,
+              _: &Context,
+              _: &mut RenderContext,
+              out: &mut dyn Output|
+              -> Result<(), RenderError> {
+            let path = h
+                .param(0)
+                .and_then(|p| p.value().as_str())
+                .ok_or(RenderError::new(
+                    "Asset path as first parameter is required for asset_url helper.",
+                ))?;
+            let assets = assets.read().unwrap();
+            let url = assets
+                .get(path.trim_start_matches('/'))
+                .ok_or_else(|| RenderError::new(format!("Unknown asset `{}`.", path)))?;
+            out.write(url)?;
+            Ok(())
+        },
+    )
+}
+
+/// Turn `term` into a lowercase, URL-safe slug: `"Rust Lang"` becomes `"rust-lang"`.
+pub fn slugify(term: &str) -> String {
+    let mut ret = String::with_capacity(term.len());
+    for c in term.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            ret.push(c);
+        } else if !ret.is_empty() && !ret.ends_with('-') {
+            ret.push('-');
+        }
+    }
+    while ret.ends_with('-') {
+        ret.pop();
+    }
+    ret
+}
+
+/// Returns the root-relative URL of destination `path`, dropping a trailing `index.html`:
+/// `tags/rust/index.html` becomes `/tags/rust/`.
+pub fn path_to_url(path: &std::path::Path) -> String {
+    let path = path.display().to_string();
+    match path.strip_suffix("index.html") {
+        Some(dir) if dir.is_empty() || dir.ends_with('/') => format!("/{}", dir),
+        _ => format!("/{}", path),
+    }
+}
+
+/// Escape `&`, `<`, `>`, `"` and `'` for use in XML text and attribute values.
+pub fn escape_xml(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Returns the text of `html`: tags, comments, scripts and styles are removed, common entities
+/// are decoded and whitespace is collapsed.
+pub fn strip_html(html: &str) -> String {
+    use std::sync::OnceLock;
+    static BLOCKS: OnceLock<regex::Regex> = OnceLock::new();
+    static TAGS: OnceLock<regex::Regex> = OnceLock::new();
+    let blocks = BLOCKS.get_or_init(|| {
+        regex::Regex::new(r"(?is)<!--.*?-->|<script\b.*?</script\s*>|<style\b.*?</style\s*>")
+            .unwrap()
+    });
+    let tags = TAGS.get_or_init(|| regex::Regex::new(r"(?s)<[^>]*>").unwrap());
+    let text = blocks.replace_all(html, " ");
+    let text = tags.replace_all(&text, " ");
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        let expected = parse_date("2019-06-15T00:00:00Z").unwrap();
+        assert_eq!(parse_date("2019-06-15"), Some(expected));
+        assert_eq!(parse_date("June 15, 2019"), Some(expected));
+        assert_eq!(parse_date("Jun 15, 2019"), Some(expected));
+        assert_eq!(parse_date("15 June 2019"), Some(expected));
+        assert_eq!(
+            parse_date("Sat, 15 Jun 2019 00:00:00 +0000"),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_date("2019-06-15 10:30:00").unwrap(),
+            expected + chrono::Duration::minutes(630)
+        );
+        assert_eq!(parse_date("2019-06-15T03:00:00+03:00").unwrap(), expected);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_strip_html() {
+        assert_eq!(
+            strip_html(
+                "<h1 id=\"a\">Tom &amp; Jerry</h1>\n<!-- c --><script>x < y</script><p>a<br/>b</p>"
+            ),
+            "Tom & Jerry a b"
+        );
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Rust Lang"), "rust-lang");
+        assert_eq!(slugify("  C++ & you! "), "c-you");
+    }
+}
@@ -64,6 +64,8 @@
 //!
 //!fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!    let mut state = State::new()?;
+//!#   #[cfg(feature = "subscriber")]
+//!    init_tracing(state.verbosity());
 //!    state
 //!        .then(match_pattern(
 //!            "^posts/*",
@@ -93,22 +95,58 @@
 //! `libssg` uses some environment variables for configuration but you can also customise this in
 //! your binary. By default the following variables are read:
 //! - `FORCE` if set forces rendering of all resources even if they are cached.
-//! - `VERBOSITY` gets values from `0` up to `5` to change output verbosity.
+//! - `VERBOSITY` gets values from `0` up to `5` to change output verbosity, see
+//!   [`State::set_verbosity`](State::set_verbosity).
+//! - `RUST_LOG` overrides `VERBOSITY` with a `tracing` filter, eg `RUST_LOG=libssg::rules=debug`.
 //!
+//! Diagnostics are emitted with `tracing`. `libssg` doesn't install a subscriber by itself: call
+//! [`init_tracing`](init_tracing) to print them, or install your own.
+//!
+//!
+//! ## Cargo features
+//! - `subscriber`, enabled by default, enables [`init_tracing`](init_tracing).
+//! - `sass` enables the [`sass`](compilers::sass) stylesheet compiler.
+//! - `images` enables the [`images`](rules::images) responsive image pipeline, and `webp` and
+//!   `avif` its WebP and AVIF output.
+//! - `gallery` enables the [`gallery`](rules::gallery) rule, which implies `images`.
+//! - `epub` enables the [`epub`](rules::epub) rule.
+//! - `precompress` enables [`State::set_precompress`](State::set_precompress).
+//! - `serve` enables [`State::serve`](State::serve), a development server with live reload.
+//! - `watch` enables [`State::watch`](State::watch), which rebuilds the site incrementally when
+//!   sources change.
+//! - `minify` enables the [`minify_css`](compilers::minify::minify_css) and
+//!   [`minify_js`](compilers::minify::minify_js) compilers.
 //!
 //! ## Snapshots
 //! Rendered content can be saved in named snapshots. This allows you reusing rendered content in
 //! later steps, for example generating an RSS feed with generated post content.
+//!
+//! Pages are rendered as soon as they are added by a rule, so rules run after it can use an
+//! artifact's final [`contents`](BuildArtifact::contents). To save an intermediate stage of a
+//! [`Renderer::Pipeline`](Renderer::Pipeline), eg the post's HTML before it is wrapped in the
+//! site layout, add a [`Renderer::Snapshot`](Renderer::Snapshot) stage:
+//!
+//!```no_run
+//!# use libssg::*;
+//!Renderer::Pipeline(vec![
+//!    Renderer::LoadAndApplyTemplate("templates/post.hbs"),
+//!    Renderer::Snapshot("content"),
+//!    Renderer::LoadAndApplyTemplate("templates/default.hbs"),
+//!]);
+//!```
+//!
+//! The artifact is then added to the snapshot `content` and the stage's output is available in
+//! [`BuildArtifact::snapshots`](BuildArtifact::snapshots) for later rules.
 pub use chrono;
-use handlebars;
 use handlebars::Handlebars;
-use regex;
 pub use serde_json::{Map, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::{env, fs};
+use tracing::{debug, debug_span, info, info_span, trace, warn};
 pub use uuid::Uuid;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -131,12 +169,300 @@ pub use compilers::*;
 pub mod renderers;
 pub use renderers::*;
 
+pub mod query;
+pub use query::*;
+
+pub mod plan;
+pub use plan::*;
+
+#[cfg(feature = "precompress")]
+pub mod precompress;
+#[cfg(feature = "precompress")]
+pub use precompress::*;
+
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(feature = "serve")]
+pub use serve::*;
+
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "watch")]
+pub use watch::*;
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    /// Returns a state whose templates, `templates` by name and contents, output directory and
+    /// manifest are in `target/tests/<name>`.
+    pub(crate) fn test_state(name: &str, templates: &[(&str, &str)]) -> super::State {
+        use std::fs;
+        let dir =
+            std::path::Path::new("target/tests").join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("templates")).unwrap();
+        for (name, contents) in templates {
+            fs::write(dir.join("templates").join(name), contents).unwrap();
+        }
+        let mut state = super::State::with_dirs(dir.join("templates"), &dir.join("_site")).unwrap();
+        state.set_manifest_path(Some(dir.join(".cache/outputs.json")));
+        state
+    }
+
+    #[test]
+    fn test_render_order_and_snapshots() {
+        use super::*;
+        let mut state = test_state(
+            "render",
+            &[
+                ("post.hbs", "<h1>{{title}}</h1>"),
+                ("default.hbs", "<html>{{{body}}}</html>"),
+                (
+                    "list.hbs",
+                    "{{#each posts}}{{{snapshots.content}}}|{{{contents}}}{{/each}}",
+                ),
+            ],
+        );
+        let mut metadata = Map::new();
+        metadata.insert("title".into(), "First".into());
+        state
+            .then(create(
+                "first.html".into(),
+                Renderer::Pipeline(vec![
+                    Renderer::LoadAndApplyTemplate("templates/post.hbs"),
+                    Renderer::Snapshot("content"),
+                    Renderer::LoadAndApplyTemplate("templates/default.hbs"),
+                ]),
+                const_compiler(metadata),
+            ))
+            .then(create(
+                "list.html".into(),
+                Renderer::LoadAndApplyTemplate("templates/list.hbs"),
+                Box::new(|state: &mut State, _: &Path| {
+                    let mut metadata = Map::new();
+                    let posts = state.snapshot("content").contexts()?;
+                    metadata.insert("posts".into(), Value::Array(posts));
+                    Ok(metadata)
+                }),
+            ));
+        assert!(state.err.is_none());
+        let list = uuid_from_path(Path::new("list.html"));
+        assert_eq!(
+            state.artifacts[&list].contents,
+            "<h1>First</h1>|<html><h1>First</h1></html>"
+        );
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+
+        // Rules run in order, so a snapshot is not available to rules added before it.
+        let mut state = test_state("render-order", &[("list.hbs", "")]);
+        state.then(create(
+            "list.html".into(),
+            Renderer::LoadAndApplyTemplate("templates/list.hbs"),
+            Box::new(|state: &mut State, _: &Path| {
+                state.snapshot("content").contexts()?;
+                Ok(Map::new())
+            }),
+        ));
+        assert!(state.err.is_some());
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_seed_and_swap_staging_dir() {
+        use super::*;
+        use std::os::unix::fs::MetadataExt;
+        let root = Path::new("target/tests").join(format!("staging-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (dir, staging) = (root.join("out"), root.join(".out.staging"));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("sub/b.txt"), "b").unwrap();
+        std::os::unix::fs::symlink("a.txt", dir.join("link")).unwrap();
+        fs::write(staging.join("stale.txt"), "stale").unwrap();
+
+        // Staging directories are recreated with links to the previous output.
+        seed_staging_dir(&dir, &staging).unwrap();
+        assert!(!staging.join("stale.txt").exists());
+        assert_eq!(
+            fs::metadata(staging.join("a.txt")).unwrap().ino(),
+            fs::metadata(dir.join("a.txt")).unwrap().ino()
+        );
+        assert_eq!(fs::read(staging.join("sub/b.txt")).unwrap(), b"b");
+        assert_eq!(
+            fs::read_link(staging.join("link")).unwrap(),
+            Path::new("a.txt")
+        );
+
+        replace_file(&staging.join("a.txt"), b"new").unwrap();
+        swap_staging_dir(&dir, &staging).unwrap();
+        assert_eq!(fs::read(dir.join("a.txt")).unwrap(), b"new");
+        assert_eq!(fs::read(staging.join("a.txt")).unwrap(), b"a");
+
+        // A failed swap undoes the swaps before it.
+        let missing = (root.join("missing"), root.join(".missing.staging"));
+        assert!(swap_staging_dirs(&[(dir.clone(), staging.clone()), missing]).is_err());
+        assert_eq!(fs::read(dir.join("a.txt")).unwrap(), b"new");
+        assert_eq!(fs::read(staging.join("a.txt")).unwrap(), b"a");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_atomic_finish() {
+        use super::*;
+        let state = test_state("atomic", &[]);
+        let output_dir = state.output_dir().to_path_buf();
+        let root = output_dir.parent().unwrap().to_path_buf();
+        let (templates, manifest) = (root.join("templates"), root.join("manifest.json"));
+        let staging = root.join("._site.staging");
+        let page = |state: &mut State, dest: &str| {
+            let mut metadata = Map::new();
+            metadata.insert("body".into(), dest.into());
+            state
+                .add_page(
+                    dest.into(),
+                    dest.into(),
+                    &const_compiler(metadata),
+                    Renderer::body(),
+                )
+                .unwrap();
+        };
+
+        let mut state = State::with_dirs(templates.clone(), &output_dir).unwrap();
+        state
+            .set_atomic(true)
+            .set_manifest_path(Some(manifest.clone()));
+        page(&mut state, "a.html");
+        state.finish().unwrap();
+        assert_eq!(fs::read(output_dir.join("a.html")).unwrap(), b"a.html");
+        assert!(!staging.exists());
+        let written = fs::read_to_string(&manifest).unwrap();
+        assert!(written.contains("a.html"), "{}", written);
+
+        // A failed build leaves the output and manifest untouched and removes the staging
+        // directory.
+        let mut state = State::with_dirs(templates, &output_dir).unwrap();
+        state
+            .set_atomic(true)
+            .set_manifest_path(Some(manifest.clone()));
+        page(&mut state, "b.html");
+        state
+            .copy_page(root.join("missing.txt"), "c.txt".into())
+            .unwrap();
+        assert!(state.finish().is_err());
+        assert_eq!(fs::read(output_dir.join("a.html")).unwrap(), b"a.html");
+        assert!(!output_dir.join("b.html").exists());
+        assert!(!staging.exists());
+        assert_eq!(fs::read_to_string(&manifest).unwrap(), written);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_add_output_root() {
+        use super::*;
+        let mut state = test_state("output-root", &[]);
+        let root = state.output_dir().parent().unwrap().to_path_buf();
+        let capsule = root
+            .strip_prefix(state.current_dir())
+            .unwrap()
+            .join("_capsule");
+        state.set_dry_run(true);
+        state.add_output_root("capsule", &capsule).unwrap();
+        assert!(!capsule.exists());
+        assert_eq!(
+            state.output_root("capsule"),
+            Some(state.current_dir().join(&capsule).as_path())
+        );
+        state.set_dry_run(false);
+        state.add_output_root("capsule", &capsule).unwrap();
+        assert!(capsule.is_dir());
+        assert_eq!(
+            state.output_root("capsule"),
+            Some(root.join("_capsule").as_path())
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_verbosity_level() {
+        use tracing::level_filters::LevelFilter;
+        assert_eq!(super::verbosity_level(0), LevelFilter::WARN);
+        assert_eq!(super::verbosity_level(1), LevelFilter::INFO);
+        assert_eq!(super::verbosity_level(5), LevelFilter::TRACE);
+    }
+}
+
+/// Returns the `tracing` level filter of a `VERBOSITY` value: `0` only shows warnings and
+/// errors, `1` what is built, `2` also what is cached and `3` and above everything, including
+/// metadata.
+pub fn verbosity_level(verbosity: u8) -> tracing::level_filters::LevelFilter {
+    use tracing::level_filters::LevelFilter;
+    match verbosity {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Sets the level of the subscriber installed by [`init_tracing`](init_tracing).
+#[cfg(feature = "subscriber")]
+type SetLevel = Box<dyn Fn(tracing::level_filters::LevelFilter) + Send + Sync>;
+
+#[cfg(feature = "subscriber")]
+static SET_LEVEL: std::sync::OnceLock<SetLevel> = std::sync::OnceLock::new();
+
+/// Install a `tracing` subscriber that prints diagnostics to standard output, unless one is
+/// already installed, eg `init_tracing(state.verbosity())`.
+///
+/// The filter is read from `RUST_LOG` if it is set, eg `RUST_LOG=libssg::rules=debug`, and
+/// otherwise from `verbosity`, see [`verbosity_level`](verbosity_level). In that case
+/// [`State::set_verbosity`](State::set_verbosity) changes it afterwards.
+#[cfg(feature = "subscriber")]
+pub fn init_tracing(verbosity: u8) {
+    use tracing_subscriber::EnvFilter;
+    if let Ok(filter) = EnvFilter::try_from_default_env() {
+        let _ = tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .without_time()
+            .with_target(false)
+            .try_init();
+        return;
+    }
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::default().add_directive(verbosity_level(verbosity).into()))
+        .without_time()
+        .with_target(false)
+        .with_filter_reloading();
+    let handle = builder.reload_handle();
+    if builder.try_init().is_ok() {
+        let _ = SET_LEVEL.set(Box::new(move |level| {
+            let _ = handle.reload(EnvFilter::default().add_directive(level.into()));
+        }));
+    }
+}
+
+/// Register the templates of directory `dir` and the built-in helpers, which read `assets` and
+/// `images` as they are added to.
+fn load_templates(
+    dir: &Path,
+    assets: &Arc<RwLock<BTreeMap<String, String>>>,
+    #[cfg(feature = "images")] images: &Arc<RwLock<BTreeMap<String, ResponsiveImage>>>,
+) -> Result<Handlebars<'static>> {
+    let mut templates = Handlebars::new();
+    templates
+        .register_templates_directory("", dir)
+        .map_err(|_| "Could not find templates/ dir")?;
+    templates.register_helper("include", Box::new(include_helper));
+    templates.register_helper("date_fmt", Box::new(date_fmt));
+    templates.register_helper("asset_url", asset_url(assets.clone()));
+    #[cfg(feature = "images")]
+    templates.register_helper("img", img_helper(images.clone()));
+    Ok(templates)
 }
 
 ///The state of site render.
@@ -144,11 +470,63 @@ mod tests {
 pub struct State {
     snapshots: HashMap<String, Vec<Uuid>>,
     artifacts: HashMap<Uuid, BuildArtifact>,
+    /// Build actions by output path.
     build_actions: HashMap<PathBuf, BuildAction>,
+    /// Output paths and the artifacts they are generated from.
+    routes: HashMap<PathBuf, Uuid>,
     templates: Handlebars<'static>,
+    /// Reloaded before every build of [`State::watch`](State::watch).
+    #[cfg(feature = "watch")]
+    templates_dir: PathBuf,
     output_dir: PathBuf,
+    /// Additional output directories by name, see
+    /// [`State::add_output_root`](State::add_output_root).
+    output_roots: HashMap<String, PathBuf>,
+    /// Output root of the rule being run, see [`output_root`](crate::rules::output_root).
+    root: Option<String>,
     current_dir: PathBuf,
 
+    /// Files other than their resource that artifacts were compiled from.
+    dependencies: HashMap<Uuid, Vec<PathBuf>>,
+    /// Dependencies recorded by the compiler being run, see
+    /// [`State::add_dependency`](State::add_dependency).
+    pending_dependencies: Vec<PathBuf>,
+    /// Asset destinations and their URLs, see [`State::add_asset`](State::add_asset).
+    assets: Arc<RwLock<BTreeMap<String, String>>>,
+    /// Responsive images by route, see [`images`](crate::rules::images).
+    #[cfg(feature = "images")]
+    images: Arc<RwLock<BTreeMap<String, ResponsiveImage>>>,
+    /// Whether an asset's URL changed since the last build, so that rendered pages must be
+    /// regenerated.
+    assets_changed: bool,
+
+    /// Path of the output manifest relative to `current_dir`, see
+    /// [`State::set_manifest_path`](State::set_manifest_path).
+    manifest_path: Option<PathBuf>,
+    /// See [`State::set_atomic`](State::set_atomic).
+    atomic: bool,
+    /// See [`State::set_dry_run`](State::set_dry_run).
+    dry_run: bool,
+    /// Destinations added so far by output path, see [`BuildPlan`](BuildPlan).
+    plan: HashMap<PathBuf, PlannedOutput>,
+    /// See [`BuildReport`](BuildReport).
+    started: std::time::Instant,
+    timings: Vec<ResourceTiming>,
+    commands: BTreeMap<String, CommandTiming>,
+    /// Number of rules passed to [`State::then`](State::then), to tell their tracing spans apart.
+    rules_run: usize,
+    /// See [`State::set_precompress`](State::set_precompress).
+    #[cfg(feature = "precompress")]
+    precompress: Option<Precompress>,
+    /// See [`State::serve`](State::serve).
+    #[cfg(feature = "serve")]
+    server: Option<Server>,
+    /// Snapshots read by the rule being run, see [`State::watch`](State::watch).
+    #[cfg(feature = "watch")]
+    reads: std::cell::RefCell<watch::Reads>,
+
+    /// Artifact currently being rendered in [`State::add_page`](State::add_page).
+    rendering: Option<Uuid>,
     err: Option<Box<dyn std::error::Error>>,
     force_generate: bool,
     verbosity: u8,
@@ -157,33 +535,70 @@ pub struct State {
 impl State {
     /// Create new state.
     pub fn new() -> Result<Self> {
-        let mut templates = Handlebars::new();
-        templates
-            .register_templates_directory("", "./templates")
-            .map_err(|_| "Could not find templates/ dir")?;
-        templates.register_helper("include", Box::new(include_helper));
-        templates.register_helper("date_fmt", Box::new(date_fmt));
-        match fs::create_dir(&Path::new("./_site/")) {
+        Self::with_dirs(PathBuf::from("./templates"), Path::new("./_site/"))
+    }
+
+    /// Create new state with the templates of `templates_dir` and output directory `output_dir`.
+    pub(crate) fn with_dirs(templates_dir: PathBuf, output_dir: &Path) -> Result<Self> {
+        let assets = Arc::new(RwLock::new(BTreeMap::new()));
+        #[cfg(feature = "images")]
+        let images = Arc::new(RwLock::new(BTreeMap::new()));
+        let templates = load_templates(
+            &templates_dir,
+            &assets,
+            #[cfg(feature = "images")]
+            &images,
+        )?;
+        match fs::create_dir(output_dir) {
             Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
             err => err?,
         }
-        let output_dir = PathBuf::from("./_site/").canonicalize()?;
+        let output_dir = output_dir.canonicalize()?;
         let current_dir = env::current_dir()?;
+        let verbosity = env::var("VERBOSITY")
+            .ok()
+            .as_ref()
+            .and_then(|v| v.parse::<u8>().ok())
+            .unwrap_or(1);
         Ok(State {
             templates,
+            #[cfg(feature = "watch")]
+            templates_dir,
             output_dir,
+            output_roots: Default::default(),
+            root: None,
             current_dir,
             artifacts: Default::default(),
             build_actions: Default::default(),
+            routes: Default::default(),
+
+            dependencies: Default::default(),
+            pending_dependencies: vec![],
+            assets,
+            assets_changed: false,
+            #[cfg(feature = "images")]
+            images,
+
+            manifest_path: Some(PathBuf::from(".cache/outputs.json")),
+            atomic: false,
+            dry_run: false,
+            plan: Default::default(),
+            started: std::time::Instant::now(),
+            timings: vec![],
+            commands: Default::default(),
+            rules_run: 0,
+            #[cfg(feature = "precompress")]
+            precompress: None,
+            #[cfg(feature = "serve")]
+            server: None,
+            #[cfg(feature = "watch")]
+            reads: Default::default(),
 
+            rendering: None,
             err: None,
             snapshots: Default::default(),
             force_generate: env::var("FORCE").is_ok(),
-            verbosity: env::var("VERBOSITY")
-                .ok()
-                .as_ref()
-                .and_then(|v| u8::from_str_radix(v, 10).ok())
-                .unwrap_or(1),
+            verbosity,
         })
     }
 
@@ -193,12 +608,94 @@ impl State {
         self
     }
 
-    /// Sets `verbosity` option.
+    /// Sets `verbosity` option, and the level of the subscriber installed by
+    /// [`init_tracing`](init_tracing) to its [`verbosity_level`](verbosity_level).
     pub fn set_verbosity(&mut self, verbosity: u8) -> &mut Self {
         self.verbosity = verbosity;
+        #[cfg(feature = "subscriber")]
+        if let Some(set_level) = SET_LEVEL.get() {
+            set_level(verbosity_level(verbosity));
+        }
         self
     }
 
+    /// Sets the path of the output manifest, relative to the current directory, or disables it
+    /// with `None`. By default it is `.cache/outputs.json`.
+    ///
+    /// [`State::finish`](State::finish) records every output of the build in the manifest and
+    /// removes the outputs recorded by the previous build that are no longer generated, eg the
+    /// page of a deleted post.
+    pub fn set_manifest_path(&mut self, manifest_path: Option<PathBuf>) -> &mut Self {
+        self.manifest_path = manifest_path;
+        self
+    }
+
+    /// Sets whether [`State::finish`](State::finish) builds into staging directories and swaps
+    /// them with the output directories once every action has succeeded, so that a failed build
+    /// leaves the previous output untouched and servers never see a partial one.
+    ///
+    /// The staging directory of `_site` is `._site.staging` next to it, seeded with hard links to
+    /// the previous output so that cached outputs are kept. On Linux the directories are swapped
+    /// atomically with `renameat2(RENAME_EXCHANGE)`; elsewhere, or on file systems without
+    /// support for it, with renames. If swapping any of the output roots fails, the ones already
+    /// swapped are swapped back, and the output manifest is only written once all of them are.
+    pub fn set_atomic(&mut self, atomic: bool) -> &mut Self {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Sets whether [`State::finish`](State::finish) only returns the [`BuildPlan`](BuildPlan)
+    /// without writing or removing anything.
+    pub fn set_dry_run(&mut self, dry_run: bool) -> &mut Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Returns `dry_run` option.
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Sets the configuration of pre-compressed outputs, see [`Precompress`](Precompress).
+    #[cfg(feature = "precompress")]
+    pub fn set_precompress(&mut self, precompress: Option<Precompress>) -> &mut Self {
+        self.precompress = precompress;
+        self
+    }
+
+    /// Starts a development server for the output directory, see [`Serve`](Serve). Pages open in
+    /// browsers are reloaded after every [`State::finish`](State::finish) that writes outputs.
+    ///
+    /// The server runs in a background thread until the `State` is dropped, so binaries that
+    /// build once should call [`State::serve_forever`](State::serve_forever) after `finish`.
+    #[cfg(feature = "serve")]
+    pub fn serve(&mut self, options: Serve) -> Result<&mut Self> {
+        let server = Server::start(self.output_dir.clone(), options)?;
+        info!(
+            "serving {} at http://{}/",
+            self.output_dir.display(),
+            server.address()
+        );
+        self.server = Some(server);
+        Ok(self)
+    }
+
+    /// Returns the development server started with [`State::serve`](State::serve).
+    #[cfg(feature = "serve")]
+    pub fn server(&self) -> Option<&Server> {
+        self.server.as_ref()
+    }
+
+    /// Blocks until the development server started with [`State::serve`](State::serve) stops.
+    #[cfg(feature = "serve")]
+    pub fn serve_forever(&mut self) -> Result<()> {
+        self.server
+            .as_mut()
+            .ok_or("No development server was started with State::serve")?
+            .wait();
+        Ok(())
+    }
+
     /// Returns `verbosity` option.
     pub fn verbosity(&self) -> u8 {
         self.verbosity
@@ -206,96 +703,203 @@ impl State {
 
     /// Returns current state of build artifacts.
     pub fn artifacts(&self) -> &HashMap<Uuid, BuildArtifact> {
+        self.record_read(None);
         &self.artifacts
     }
 
     /// Returns current state of snapshots.
     pub fn snapshots(&self) -> &HashMap<String, Vec<Uuid>> {
+        self.record_read(None);
         &self.snapshots
     }
 
+    /// Record that the rule being run reads snapshot `name`, or any artifact with `None`, so that
+    /// [`State::watch`](State::watch) runs it again when they change.
+    #[cfg_attr(not(feature = "watch"), allow(unused_variables))]
+    pub(crate) fn record_read(&self, name: Option<&str>) {
+        #[cfg(feature = "watch")]
+        self.reads.borrow_mut().add(name);
+    }
+
+    /// Returns a query over the artifacts of snapshot `name`, eg
+    /// `state.snapshot("posts").sort_by_key("date").rev().take(10).artifacts()?`.
+    pub fn snapshot<S: Into<String>>(&self, name: S) -> Snapshot<'_> {
+        Snapshot::new(self, snapshot(name))
+    }
+
     /// Adds an artifact to a snapshot.
     pub fn add_to_snapshot(&mut self, key: String, artifact: Uuid) {
         self.snapshots.entry(key).or_default().push(artifact)
     }
 
-    /// Check if `dest`'s mtime is older than `resource`'s.
+    /// Check if `dest`'s mtime is older than `resource`'s. `dest` is relative to the output
+    /// directory, unless it is absolute.
     pub fn check_mtime(&mut self, dest: &Path, resource: &Path) -> bool {
+        self.mtime_reason(dest, resource).is_some()
+    }
+
+    /// Returns why `dest` must be regenerated from `resource`, if it must, see
+    /// [`State::check_mtime`](State::check_mtime).
+    pub fn mtime_reason(&self, dest: &Path, resource: &Path) -> Option<DirtyReason> {
         let resource = self.current_dir.as_path().join(resource);
         if self.force_generate {
-            return true;
-        }
-        let fs_depth = self.output_dir.components().count();
-        self.output_dir.push(&dest);
-        if self.verbosity > 1 {
-            print!(
-                "Checking resource {} against destination path {}... ",
-                resource.display(),
-                self.output_dir.display()
-            );
+            return Some(DirtyReason::Forced);
         }
-        let mut ret = true;
-        if self.output_dir.exists() {
-            if let Ok(out_mtime) = fs::metadata(&self.output_dir).and_then(|mdata| mdata.modified())
-            {
-                if let Ok(src_mtime) = fs::metadata(&resource).and_then(|mdata| mdata.modified()) {
-                    if src_mtime <= out_mtime {
-                        ret = false;
-                    }
+        let dest = self.output_dir.join(dest);
+        let mut ret = Some(DirtyReason::Missing);
+        if dest.exists() {
+            ret = Some(DirtyReason::Modified);
+            if let Ok(out_mtime) = fs::metadata(&dest).and_then(|mdata| mdata.modified()) {
+                match fs::metadata(&resource).and_then(|mdata| mdata.modified()) {
+                    Ok(src_mtime) if src_mtime <= out_mtime => ret = None,
+                    Ok(_) => {}
+                    Err(_) => ret = Some(DirtyReason::Generated),
                 }
             }
         }
-        /* Cleanup */
-        for _ in fs_depth..self.output_dir.components().count() {
-            self.output_dir.pop();
-        }
-        if self.verbosity > 1 {
-            println!("returning {}", ret);
-        }
+        trace!(
+            resource = %resource.display(),
+            dest = %dest.display(),
+            dirty = ?ret,
+            "checked mtime"
+        );
         ret
     }
 
-    /// Adds a build action of copying a resource to a destination, unchanged.
-    pub fn copy_page(&mut self, resource: PathBuf, dest: PathBuf) -> Uuid {
-        let uuid = uuid_from_path(&resource);
-        if self.check_mtime(&dest, &resource) {
-            if self.verbosity > 0 {
-                println!(
-                    "Will copy {} to _site/{}",
-                    resource.display(),
-                    dest.display()
+    /// Add an output directory `dir` named `name`, in addition to `_site`. Rules wrapped in
+    /// [`output_root`](crate::rules::output_root) write their destinations under it, eg
+    /// `state.add_output_root("capsule", "_capsule")?`. The directory is created unless in a dry
+    /// run, see [`State::set_dry_run`](State::set_dry_run).
+    pub fn add_output_root<S: Into<String>, P: AsRef<Path>>(
+        &mut self,
+        name: S,
+        dir: P,
+    ) -> Result<&mut Self> {
+        let dir = self.current_dir.join(dir);
+        if !self.dry_run {
+            fs::create_dir_all(&dir)?;
+        }
+        let dir = dir.canonicalize().unwrap_or(dir);
+        self.output_roots.insert(name.into(), dir);
+        Ok(self)
+    }
+
+    /// Returns the directory of output root `name`.
+    pub fn output_root(&self, name: &str) -> Option<&Path> {
+        self.output_roots.get(name).map(PathBuf::as_path)
+    }
+
+    /// Returns the output path of destination `dest` in the current output root, and the uuid
+    /// of the artifact generated from `resource` in it.
+    fn output_path(&self, dest: &Path, resource: &Path) -> Result<(PathBuf, Uuid)> {
+        let dest = dest.strip_prefix(&self.current_dir).unwrap_or(dest);
+        match self.root {
+            None => Ok((self.output_dir.join(dest), uuid_from_path(resource))),
+            Some(ref root) => {
+                let dir = self
+                    .output_roots
+                    .get(root)
+                    .ok_or_else(|| format!("Output root `{}` has not been added", root))?;
+                let uuid = Uuid::new_v3(
+                    &Uuid::NAMESPACE_OID,
+                    format!("{}:{}", root, resource.display()).as_bytes(),
                 );
+                Ok((dir.join(dest), uuid))
             }
+        }
+    }
+
+    /// Adds a build action of copying a resource to a destination, unchanged.
+    pub fn copy_page(&mut self, resource: PathBuf, dest: PathBuf) -> Result<Uuid> {
+        let _span = debug_span!("resource", resource = %resource.display()).entered();
+        let (output_path, uuid) = self.output_path(&dest, &resource)?;
+        self.register_route(&output_path, uuid, &resource)?;
+        let dirty = self.mtime_reason(&output_path, &resource);
+        self.plan_output(&output_path, &dest, &resource, vec!["copy".into()], &dirty);
+        if let Some(ref reason) = dirty {
+            info!(output = %output_path.display(), ?reason, "will copy");
             self.build_actions.insert(
-                dest.clone(),
+                output_path,
                 BuildAction {
-                    src: uuid.clone(),
+                    src: uuid,
                     to: Renderer::None,
+                    bytes: None,
                 },
             );
-            self.artifacts.insert(
-                uuid.clone(),
-                BuildArtifact {
-                    uuid: uuid.clone(),
-                    path: dest.clone(),
-                    resource,
-                    metadata: Default::default(),
-                    contents: String::new(),
-                },
-            );
-        } else {
-            self.artifacts.insert(
-                uuid.clone(),
-                BuildArtifact {
-                    uuid: uuid.clone(),
-                    path: dest.clone(),
-                    resource: dest,
-                    metadata: Default::default(),
-                    contents: String::new(),
-                },
-            );
         }
-        uuid
+        self.artifacts.insert(
+            uuid,
+            BuildArtifact {
+                uuid,
+                path: dest,
+                root: self.root.clone(),
+                resource,
+                metadata: Default::default(),
+                contents: String::new(),
+                snapshots: Default::default(),
+            },
+        );
+        Ok(uuid)
+    }
+
+    /// Run `command` and return its output, recording its duration in the
+    /// [`BuildReport`](BuildReport) under its program name.
+    pub fn run_command(&mut self, command: &mut Command) -> Result<std::process::Output> {
+        let program = Path::new(command.get_program())
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let start = std::time::Instant::now();
+        let output = command
+            .output()
+            .map_err(|err| format!("failed to execute {}: {}", program, err))?;
+        let timing = self.commands.entry(program).or_default();
+        timing.runs += 1;
+        timing.total_ms += start.elapsed().as_secs_f64() * 1000.0;
+        Ok(output)
+    }
+
+    /// Add output path `output_path` to the build plan.
+    fn plan_output(
+        &mut self,
+        output_path: &Path,
+        dest: &Path,
+        resource: &Path,
+        renderers: Vec<String>,
+        dirty: &Option<DirtyReason>,
+    ) {
+        self.plan.insert(
+            output_path.to_path_buf(),
+            PlannedOutput {
+                output: output_path.to_path_buf(),
+                path: dest.to_path_buf(),
+                root: self.root.clone(),
+                resource: resource.to_path_buf(),
+                renderers,
+                dirty: dirty.clone(),
+            },
+        );
+    }
+
+    /// Record that output path `dest` is generated from artifact `uuid`, or return an error if
+    /// another artifact already generates it.
+    fn register_route(&mut self, dest: &Path, uuid: Uuid, resource: &Path) -> Result<()> {
+        match self.routes.get(dest) {
+            Some(other) if *other != uuid => Err(format!(
+                "Destination {} of {} is already generated from {}",
+                dest.strip_prefix(&self.current_dir)
+                    .unwrap_or(dest)
+                    .display(),
+                resource.display(),
+                self.artifacts[other].resource.display()
+            )
+            .into()),
+            _ => {
+                self.routes.insert(dest.to_path_buf(), uuid);
+                Ok(())
+            }
+        }
     }
 
     /// Adds a build action with a custom [`Compiler`](crate::compilers::Compiler).
@@ -307,62 +911,218 @@ impl State {
         renderer: Renderer,
     ) -> Result<Uuid> {
         let resource = resource
-            .strip_prefix(&self.output_dir().parent().unwrap())
+            .strip_prefix(self.output_dir().parent().unwrap())
             .unwrap_or(&resource)
             .to_path_buf();
-        let uuid = uuid_from_path(&resource);
-        let metadata = compiler(self, &resource)?;
-        if self.check_mtime(&dest, &resource) || renderer.check_mtime(self, &dest) {
-            if self.verbosity > 0 {
-                print!(
-                    "Will create {} from resource {} with artifact uuid {}",
-                    dest.display(),
-                    resource.display(),
-                    uuid,
-                );
-                if self.verbosity > 3 {
-                    print!(" and metadata {:#?}", &metadata,);
-                }
-                println!("");
-            }
-            self.artifacts.insert(
-                uuid.clone(),
-                BuildArtifact {
-                    uuid: uuid.clone(),
-                    path: dest.clone(),
-                    resource,
-                    metadata,
-                    contents: String::new(),
-                },
-            );
+        let _span = debug_span!("resource", resource = %resource.display()).entered();
+        let (output_path, uuid) = self.output_path(&dest, &resource)?;
+        self.register_route(&output_path, uuid, &resource)?;
+        let outer_dependencies = std::mem::take(&mut self.pending_dependencies);
+        let compile_start = std::time::Instant::now();
+        let metadata = compiler(self, &resource);
+        let compile_ms = compile_start.elapsed().as_secs_f64() * 1000.0;
+        let dependencies = std::mem::replace(&mut self.pending_dependencies, outer_dependencies);
+        let metadata = metadata?;
+        let reason = self
+            .mtime_reason(&output_path, &resource)
+            .or((self.assets_changed && !matches!(renderer, Renderer::None))
+                .then_some(DirtyReason::AssetsChanged))
+            .or_else(|| {
+                dependencies.iter().find_map(|dependency| {
+                    self.mtime_reason(&output_path, dependency)
+                        .map(|reason| match reason {
+                            DirtyReason::Modified | DirtyReason::Generated => {
+                                DirtyReason::Dependency {
+                                    path: dependency.clone(),
+                                }
+                            }
+                            reason => reason,
+                        })
+                })
+            })
+            .or_else(|| renderer.dirty_reason(self, &output_path));
+        let dirty = reason.is_some();
+        self.plan_output(&output_path, &dest, &resource, renderer.stages(), &reason);
+        self.dependencies.insert(uuid, dependencies);
+        match reason {
+            Some(ref reason) => info!(dest = %dest.display(), %uuid, ?reason, "will create"),
+            None => debug!(output = %output_path.display(), "using cached"),
+        }
+        trace!(?metadata);
+        self.artifacts.insert(
+            uuid,
+            BuildArtifact {
+                uuid,
+                path: dest,
+                root: self.root.clone(),
+                resource,
+                metadata,
+                contents: String::new(),
+                snapshots: Default::default(),
+            },
+        );
+        let render_start = std::time::Instant::now();
+        let contents = self.render_artifact(uuid, &renderer)?;
+        self.timings.push(ResourceTiming {
+            resource: self.artifacts[&uuid].resource.clone(),
+            path: self.artifacts[&uuid].path.clone(),
+            root: self.root.clone(),
+            compile_ms,
+            render_ms: render_start.elapsed().as_secs_f64() * 1000.0,
+        });
+        self.artifacts.get_mut(&uuid).unwrap().contents = contents;
+        if dirty {
             self.build_actions.insert(
-                dest.clone(),
+                output_path,
                 BuildAction {
-                    src: uuid.clone(),
+                    src: uuid,
                     to: renderer,
+                    bytes: None,
                 },
             );
+        }
+        Ok(uuid)
+    }
+
+    /// Adds a build action of writing `bytes`, binary contents generated from `resource`, to
+    /// destination `dest`. Destinations that already contain `bytes` are left untouched.
+    ///
+    /// Unless `dest` is `resource`, the artifact is identified by both, so that several
+    /// destinations can be generated from one resource, eg the variants of an image.
+    pub fn add_bytes(
+        &mut self,
+        dest: PathBuf,
+        resource: PathBuf,
+        metadata: Map<String, Value>,
+        bytes: Vec<u8>,
+    ) -> Result<Uuid> {
+        let _span = debug_span!("resource", resource = %resource.display()).entered();
+        let (output_path, uuid) = self.output_path(&dest, &resource)?;
+        let uuid = if dest == resource {
+            uuid
+        } else {
+            Uuid::new_v3(&uuid, dest.display().to_string().as_bytes())
+        };
+        self.register_route(&output_path, uuid, &resource)?;
+        let reason = if self.force_generate {
+            Some(DirtyReason::Forced)
         } else {
-            if self.verbosity > 0 {
-                println!("Using cached _site/{}", dest.display());
+            match fs::read(&output_path) {
+                Ok(existing) if existing == bytes => None,
+                Ok(_) => Some(DirtyReason::Contents),
+                Err(_) => Some(DirtyReason::Missing),
             }
-            self.artifacts.insert(
-                uuid.clone(),
-                BuildArtifact {
-                    uuid: uuid.clone(),
-                    path: dest.clone(),
-                    resource,
-                    metadata,
-                    contents: String::new(),
+        };
+        self.plan_output(
+            &output_path,
+            &dest,
+            &resource,
+            vec!["bytes".into()],
+            &reason,
+        );
+        let dirty = reason.is_some();
+        match reason {
+            Some(ref reason) => info!(dest = %dest.display(), %uuid, ?reason, "will create"),
+            None => debug!(output = %output_path.display(), "using cached"),
+        }
+        self.artifacts.insert(
+            uuid,
+            BuildArtifact {
+                uuid,
+                path: dest,
+                root: self.root.clone(),
+                resource,
+                metadata,
+                contents: String::new(),
+                snapshots: Default::default(),
+            },
+        );
+        if dirty {
+            self.build_actions.insert(
+                output_path,
+                BuildAction {
+                    src: uuid,
+                    to: Renderer::None,
+                    bytes: Some(bytes),
                 },
             );
         }
         Ok(uuid)
     }
 
+    /// Render artifact `uuid` with `renderer` using its metadata as context. Cached artifacts are
+    /// rendered as well, so that their contents are available to later rules.
+    fn render_artifact(&mut self, uuid: Uuid, renderer: &Renderer) -> Result<String> {
+        if let Renderer::None = renderer {
+            return Ok(String::new());
+        }
+        let mut context = self.artifacts[&uuid].metadata.clone();
+        let previous = self.rendering.replace(uuid);
+        let ret = renderer.render(self, &mut context);
+        self.rendering = previous;
+        ret
+    }
+
+    /// Save `contents` as the named snapshot `name` of the artifact currently being rendered and
+    /// add the artifact to snapshot `name`. This is what
+    /// [`Renderer::Snapshot`](crate::Renderer::Snapshot) does.
+    pub fn save_snapshot(&mut self, name: &str, contents: String) -> Result<()> {
+        let uuid = self.rendering.ok_or_else(|| {
+            format!(
+                "Cannot save snapshot `{}`: no artifact is being rendered",
+                name
+            )
+        })?;
+        self.artifacts
+            .get_mut(&uuid)
+            .unwrap()
+            .snapshots
+            .insert(name.to_string(), contents);
+        let members = self.snapshots.entry(name.to_string()).or_default();
+        if !members.contains(&uuid) {
+            members.push(uuid);
+        }
+        Ok(())
+    }
+
+    /// Record that the resource being compiled also depends on file `path`, so that its
+    /// destination is regenerated when `path` changes. Compilers that read other files, eg Sass
+    /// imports, should call this for each of them.
+    pub fn add_dependency<P: Into<PathBuf>>(&mut self, path: P) {
+        let path = path.into();
+        let path = path
+            .strip_prefix(&self.current_dir)
+            .map(Path::to_path_buf)
+            .unwrap_or(path);
+        if !self.pending_dependencies.contains(&path) {
+            self.pending_dependencies.push(path);
+        }
+    }
+
+    /// Returns the files other than its resource that artifact `uuid` was compiled from.
+    pub fn dependencies(&self, uuid: &Uuid) -> &[PathBuf] {
+        self.dependencies
+            .get(uuid)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns asset destinations and their URLs.
+    pub fn assets(&self) -> RwLockReadGuard<'_, BTreeMap<String, String>> {
+        self.assets.read().unwrap()
+    }
+
+    /// Publish asset destination `path`, eg `css/main.css`, at `url`, eg `/css/main.1a2b3c4d.css`,
+    /// for the `asset_url` template helper.
+    pub fn add_asset(&mut self, path: String, url: String) {
+        self.assets.write().unwrap().insert(path, url);
+    }
+
     /// Add a new [`Rule`](Rule).
     pub fn then(&mut self, rule: Rule) -> &mut Self {
+        self.rules_run += 1;
         if self.err.is_none() {
+            let _span = info_span!("rule", n = self.rules_run).entered();
             if let Err(err) = rule(self) {
                 self.err = Some(err);
             }
@@ -388,70 +1148,238 @@ impl State {
             })
     }
 
-    /// Perform all build actions.
-    pub fn finish(&mut self) -> Result<()> {
+    /// Perform all build actions and return the [`BuildReport`](BuildReport) of the build.
+    ///
+    /// With [`State::set_atomic`](State::set_atomic), outputs are written to staging copies of the
+    /// output directories, which replace them only after every action has succeeded. With
+    /// [`State::set_dry_run`](State::set_dry_run), nothing is written.
+    pub fn finish(&mut self) -> Result<BuildReport> {
         if let Some(err) = self.err.take() {
             Err(err)?;
         }
+        let _span = info_span!("finish").entered();
+        let start = std::time::Instant::now();
+
+        if self.dry_run {
+            self.build_actions.clear();
+            let outputs = self.outputs(&[])?;
+            let removed = self.remove_stale_outputs(&outputs, &[])?;
+            return Ok(self.build_report(removed, start));
+        }
 
         if self.build_actions.is_empty() {
-            println!(
+            warn!(
                 r#"Nothing to be generated. This might happen if:
 - You haven't added any rules.
 - You either haven't made any changes to your source files or they weren't detected (might be a bug). Rerun with $FORCE environmental variable set to ignore mtimes and force generation. Set $VERBOSITY to greater than 1 to get more messages."#
             );
-            return Ok(());
+        } else {
+            info!(output_dir = %self.output_dir.display(), "writing outputs");
+        }
+        if !self.atomic {
+            let outputs = self.write_outputs(&[])?;
+            let removed = self.remove_stale_outputs(&outputs, &[])?;
+            self.write_manifest(&outputs)?;
+            self.reload_server();
+            return Ok(self.build_report(removed, start));
+        }
+
+        let staging = std::iter::once(&self.output_dir)
+            .chain(self.output_roots.values())
+            .map(|dir| {
+                let name = dir.file_name().unwrap_or_default().to_string_lossy();
+                (
+                    dir.clone(),
+                    dir.with_file_name(format!(".{}.staging", name)),
+                )
+            })
+            .collect::<Vec<(PathBuf, PathBuf)>>();
+        let result = staging
+            .iter()
+            .try_for_each(|(dir, staging)| {
+                info!(dir = %dir.display(), staging = %staging.display(), "staging");
+                seed_staging_dir(dir, staging)
+            })
+            .and_then(|()| self.write_outputs(&staging))
+            .and_then(|outputs| {
+                let removed = self.remove_stale_outputs(&outputs, &staging)?;
+                swap_staging_dirs(&staging)?;
+                Ok((outputs, removed))
+            });
+        // The staging directories now contain either the failed build or the previous output.
+        for (_, staging) in staging.iter() {
+            if staging.exists() {
+                let _ = fs::remove_dir_all(staging);
+            }
         }
-        let fs_depth = self.output_dir.components().count();
-        if self.verbosity > 0 {
-            println!("Output directory is {}", self.output_dir.display());
+        let (outputs, removed) = result?;
+        self.write_manifest(&outputs)?;
+        self.reload_server();
+        Ok(self.build_report(removed, start))
+    }
+
+    /// Reload the pages open in browsers of the development server, if any.
+    fn reload_server(&self) {
+        #[cfg(feature = "serve")]
+        if let Some(ref server) = self.server {
+            server.reload();
         }
+    }
+
+    /// Returns the report of the destinations added so far and the `removed` outputs, for a
+    /// `finish` that started at `start`.
+    fn build_report(&self, removed: Vec<PathBuf>, start: std::time::Instant) -> BuildReport {
+        let mut outputs = self.plan.values().cloned().collect::<Vec<PlannedOutput>>();
+        outputs.sort_by(|a, b| a.output.cmp(&b.output));
+        let mut report = BuildReport::new(
+            BuildPlan { outputs, removed },
+            self.timings.clone(),
+            self.commands.clone(),
+        );
+        report.finish_ms = start.elapsed().as_secs_f64() * 1000.0;
+        report.total_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+        info!(
+            built = report.built,
+            copied = report.copied,
+            cached = report.cached,
+            pruned = report.pruned,
+            total_ms = report.total_ms,
+            "finished"
+        );
+        report
+    }
+
+    /// Perform all build actions and write pre-compressed siblings, and return every output path.
+    /// Outputs in the output directories of `staging` are written to their staging directories
+    /// instead.
+    fn write_outputs(&mut self, staging: &[(PathBuf, PathBuf)]) -> Result<Vec<PathBuf>> {
         let actions = self.build_actions.drain().collect::<Vec<(_, _)>>();
-        for (mut path, action) in actions {
+        for (output_path, action) in actions {
+            let output_path = staged_path(&output_path, staging);
             let artifact = &self.artifacts[&action.src];
-            let mut metadata = artifact.metadata.clone();
-            let contents = match action.to {
-                Renderer::None => None,
-                renderer => Some(renderer.render(self, &mut metadata)?),
+            let contents = match (action.bytes, action.to) {
+                (Some(bytes), _) => Some(bytes),
+                (None, Renderer::None) => None,
+                (None, _) => Some(artifact.contents.clone().into_bytes()),
             };
-            if path.is_absolute() {
-                path = path.strip_prefix(&self.current_dir)?.to_path_buf();
-            }
 
-            self.output_dir.push(&path);
-            match fs::create_dir_all(self.output_dir.parent().unwrap()) {
-                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
-                err => err?,
-            }
+            fs::create_dir_all(output_path.parent().unwrap())?;
             if let Some(contents) = contents {
-                use std::io::prelude::*;
-
-                if self.verbosity > 0 {
-                    print!("{}: creating {}", path.display(), self.output_dir.display());
-                    if self.verbosity > 3 {
-                        print!(" and metadata {:#?}", &metadata,);
-                    }
-                    println!("");
-                }
-                let mut file = fs::File::create(&self.output_dir)?;
-                file.write_all(contents.as_bytes())?;
+                info!(path = %artifact.path.display(), output = %output_path.display(), "creating");
+                replace_file(&output_path, &contents)?;
             } else {
-                let src_path = &self.artifacts[&action.src].resource;
-                if self.verbosity > 0 {
-                    println!(
-                        "{}: copying to {}",
-                        src_path.display(),
-                        self.output_dir.display()
-                    );
-                }
-                assert!(src_path != &self.output_dir);
+                let src_path = &artifact.resource;
+                info!(resource = %src_path.display(), output = %output_path.display(), "copying");
+                assert!(src_path != &output_path);
+
+                remove_file_if_exists(&output_path)?;
+                fs::copy(src_path, &output_path)?;
+            }
+        }
+
+        self.outputs(staging)
+    }
+
+    /// Returns every output path, including pre-compressed siblings, which are written unless in
+    /// a dry run.
+    #[cfg_attr(not(feature = "precompress"), allow(unused_variables))]
+    fn outputs(&self, staging: &[(PathBuf, PathBuf)]) -> Result<Vec<PathBuf>> {
+        #[allow(unused_mut)]
+        let mut outputs = self.routes.keys().cloned().collect::<Vec<PathBuf>>();
+        #[cfg(feature = "precompress")]
+        if let Some(ref precompress) = self.precompress {
+            let siblings = precompress.compress(self, &outputs, staging)?;
+            outputs.extend(siblings);
+        }
+        Ok(outputs)
+    }
+
+    /// Returns the path of the output manifest, see
+    /// [`State::set_manifest_path`](State::set_manifest_path), and the outputs it recorded in the
+    /// previous build.
+    fn read_manifest(&self) -> Result<Option<(PathBuf, Vec<PathBuf>)>> {
+        let manifest_path = match self.manifest_path {
+            Some(ref path) => self.current_dir.join(path),
+            None => return Ok(None),
+        };
+        let previous = match fs::read(&manifest_path) {
+            Ok(previous) => previous,
+            Err(_) => return Ok(Some((manifest_path, vec![]))),
+        };
+        let previous: OutputManifest = serde_json::from_slice(&previous).map_err(|err| {
+            format!(
+                "Could not parse output manifest {}: {}",
+                manifest_path.display(),
+                err
+            )
+        })?;
+        Ok(Some((manifest_path, previous.outputs)))
+    }
 
-                fs::copy(src_path, &self.output_dir)?;
+    /// Returns `outputs` relative to `current_dir`, sorted and without duplicates, as recorded in
+    /// the manifest.
+    fn manifest_outputs(&self, outputs: &[PathBuf]) -> Vec<PathBuf> {
+        let mut outputs = outputs
+            .iter()
+            .map(|p| p.strip_prefix(&self.current_dir).unwrap_or(p).to_path_buf())
+            .collect::<Vec<PathBuf>>();
+        outputs.sort();
+        outputs.dedup();
+        outputs
+    }
+
+    /// Remove the outputs of the previous build that are not in `outputs` and return them. Outputs
+    /// in the output directories of `staging` are removed from their staging directories
+    /// instead. In a dry run, only return the outputs that would be removed.
+    fn remove_stale_outputs(
+        &self,
+        outputs: &[PathBuf],
+        staging: &[(PathBuf, PathBuf)],
+    ) -> Result<Vec<PathBuf>> {
+        let previous = match self.read_manifest()? {
+            Some((_, previous)) => previous,
+            None => return Ok(vec![]),
+        };
+        let outputs = self.manifest_outputs(outputs);
+        let mut removed = vec![];
+        for relative_path in previous {
+            if outputs.binary_search(&relative_path).is_ok() {
+                continue;
             }
-            for _ in fs_depth..self.output_dir.components().count() {
-                self.output_dir.pop();
+            let path = self.current_dir.join(&relative_path);
+            // Never remove files outside of the output directories.
+            let in_output = std::iter::once(&self.output_dir)
+                .chain(self.output_roots.values())
+                .any(|dir| path.starts_with(dir));
+            let path = staged_path(&path, staging);
+            if !in_output || !path.is_file() {
+                continue;
             }
+            removed.push(relative_path);
+            if self.dry_run {
+                continue;
+            }
+            info!(output = %path.display(), "removing stale output");
+            fs::remove_file(&path)?;
+            // Remove the parent directory if this left it empty.
+            let _ = fs::remove_dir(path.parent().unwrap());
         }
+        Ok(removed)
+    }
+
+    /// Record `outputs` in the manifest, see
+    /// [`State::set_manifest_path`](State::set_manifest_path).
+    fn write_manifest(&self, outputs: &[PathBuf]) -> Result<()> {
+        let manifest_path = match self.manifest_path {
+            Some(ref path) => self.current_dir.join(path),
+            None => return Ok(()),
+        };
+        let outputs = self.manifest_outputs(outputs);
+        fs::create_dir_all(manifest_path.parent().unwrap())?;
+        fs::write(
+            &manifest_path,
+            serde_json::to_string_pretty(&OutputManifest { outputs })?,
+        )?;
         Ok(())
     }
 
@@ -466,33 +1394,193 @@ impl State {
     }
 }
 
+/// Outputs of a build, recorded to remove the stale ones in the next build.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct OutputManifest {
+    outputs: Vec<PathBuf>,
+}
+
+/// Returns `path` in the staging directory of its output directory in `staging`, if any.
+pub(crate) fn staged_path(path: &Path, staging: &[(PathBuf, PathBuf)]) -> PathBuf {
+    staging
+        .iter()
+        .filter(|(dir, _)| path.starts_with(dir))
+        .max_by_key(|(dir, _)| dir.as_os_str().len())
+        .map(|(dir, staging)| staging.join(path.strip_prefix(dir).unwrap()))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+fn remove_file_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Write `contents` to a new file at `path`. Outputs are replaced instead of truncated, since in
+/// staging directories they are hard links to the live ones.
+pub(crate) fn replace_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    remove_file_if_exists(path)?;
+    fs::write(path, contents)
+}
+
+/// Recreate `staging` with hard links to the files of `dir`, or copies where links fail.
+fn seed_staging_dir(dir: &Path, staging: &Path) -> Result<()> {
+    if staging.exists() {
+        fs::remove_dir_all(staging)?;
+    }
+    fs::create_dir_all(staging)?;
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let (path, target) = (entry.path(), staging.join(entry.file_name()));
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            seed_staging_dir(&path, &target)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&path)?, &target)?;
+        } else if fs::hard_link(&path, &target).is_err() {
+            fs::copy(&path, &target)?;
+            fs::File::options()
+                .write(true)
+                .open(&target)?
+                .set_modified(entry.metadata()?.modified()?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Atomically exchange directories `a` and `b`.
+#[cfg(target_os = "linux")]
+fn exchange_dirs(a: &Path, b: &Path) -> std::io::Result<()> {
+    let a = std::ffi::CString::new(a.as_os_str().as_bytes())?;
+    let b = std::ffi::CString::new(b.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid NUL terminated strings.
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Exchange `dir` and `staging`, so that `staging` contains the previous output, if any.
+fn swap_staging_dir(dir: &Path, staging: &Path) -> Result<()> {
+    if !dir.exists() {
+        fs::rename(staging, dir)?;
+        return Ok(());
+    }
+    if !staging.exists() {
+        fs::rename(dir, staging)?;
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    if exchange_dirs(dir, staging).is_ok() {
+        return Ok(());
+    }
+    // Three renames, which leave `dir` missing in between.
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    let old = dir.with_file_name(format!(".{}.old", name));
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    fs::rename(dir, &old)?;
+    if let Err(err) = fs::rename(staging, dir) {
+        let _ = fs::rename(&old, dir);
+        Err(err)?;
+    }
+    fs::rename(&old, staging)?;
+    Ok(())
+}
+
+/// Swap every output directory with its staging directory in `staging`, or none: if a swap fails,
+/// the ones done so far are undone.
+fn swap_staging_dirs(staging: &[(PathBuf, PathBuf)]) -> Result<()> {
+    for (i, (dir, staging_dir)) in staging.iter().enumerate() {
+        if let Err(err) = swap_staging_dir(dir, staging_dir) {
+            for (dir, staging_dir) in staging[..i].iter().rev() {
+                let _ = swap_staging_dir(dir, staging_dir);
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
 /// An artifact generated during the build process.
+#[derive(Clone)]
 pub struct BuildArtifact {
     pub uuid: Uuid,
+    /// Destination, relative to the artifact's output root.
     pub path: PathBuf,
+    /// Name of the output root of `path`, or `None` for the site's output directory.
+    pub root: Option<String>,
     pub resource: PathBuf,
     pub metadata: Map<String, Value>,
+    /// Rendered contents.
     pub contents: String,
+    /// Intermediate rendered contents saved by [`Renderer::Snapshot`](crate::Renderer::Snapshot)
+    /// stages, by snapshot name.
+    pub snapshots: HashMap<String, String>,
 }
 
 impl std::fmt::Debug for BuildArtifact {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             fmt,
-            "BuildArtifact {{ uuid: {}, resource: {}, metadata: {:?}, contents: \"{:.15}...\" }}",
+            "BuildArtifact {{ uuid: {}, resource: {}, metadata: {:?}, contents: \"{:.15}...\", snapshots: {:?} }}",
             self.uuid,
             self.resource.display(),
             &self.metadata,
-            &self.contents
+            &self.contents,
+            self.snapshots.keys().collect::<Vec<_>>()
         )
     }
 }
 
+impl BuildArtifact {
+    /// Returns the artifact's metadata along with its destination `path` and root-relative `url`,
+    /// suitable for use as a template context.
+    /// Rendered `contents` and saved `snapshots` are included if not empty.
+    pub fn context(&self) -> Map<String, Value> {
+        let mut ret = self.metadata.clone();
+        if !self.contents.is_empty() {
+            ret.insert("contents".into(), self.contents.clone().into());
+        }
+        if !self.snapshots.is_empty() {
+            ret.insert(
+                "snapshots".into(),
+                Value::Object(
+                    self.snapshots
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.clone().into()))
+                        .collect(),
+                ),
+            );
+        }
+        ret.insert("path".into(), self.path.display().to_string().into());
+        ret.insert("url".into(), path_to_url(&self.path).into());
+        ret
+    }
+}
+
 /// Build actions to be performed in the finish stage.
 #[derive(Debug)]
 pub struct BuildAction {
     src: Uuid,
     to: Renderer,
+    /// Binary contents to write instead of the artifact's rendered contents.
+    bytes: Option<Vec<u8>>,
 }
 
 /// Create an [`Uuid`](uuid::Uuid) from a [Path] using
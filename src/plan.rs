@@ -0,0 +1,260 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Build plans and reports: what [`State::finish`](crate::State::finish) writes, why, and how
+//! long it took.
+
+use super::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Why a destination is regenerated instead of cached.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum DirtyReason {
+    /// Generation is forced with `FORCE` or
+    /// [`State::set_force_generate`](crate::State::set_force_generate).
+    Forced,
+    /// The destination does not exist.
+    Missing,
+    /// The resource is newer than the destination.
+    Modified,
+    /// The resource is not a file, eg the page of a taxonomy term, so the destination is always
+    /// regenerated.
+    Generated,
+    /// A dependency of the resource, eg a Sass partial, is newer than the destination.
+    Dependency { path: PathBuf },
+    /// A template of the renderer is newer than the destination.
+    Template { path: PathBuf },
+    /// The renderer cannot tell whether its output changed, eg a custom renderer.
+    Renderer,
+    /// An asset URL changed, see [`fingerprint`](crate::rules::fingerprint).
+    AssetsChanged,
+    /// The generated contents differ from the destination's.
+    Contents,
+}
+
+/// A destination of a [`BuildPlan`](BuildPlan).
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedOutput {
+    /// Absolute output path.
+    pub output: PathBuf,
+    /// Destination, relative to the output root.
+    pub path: PathBuf,
+    /// Name of the output root, or `None` for the site's output directory.
+    pub root: Option<String>,
+    pub resource: PathBuf,
+    /// Renderer stages, eg `["template templates/post.hbs", "snapshot content"]`. Copies are
+    /// `["copy"]` and generated files, eg images, `["bytes"]`.
+    pub renderers: Vec<String>,
+    /// Why the destination is written, or `None` if it is cached.
+    pub dirty: Option<DirtyReason>,
+}
+
+/// The destinations of a build, see [`BuildReport::plan`](BuildReport::plan). With
+/// [`State::set_dry_run`](crate::State::set_dry_run), nothing is written and the plan describes
+/// what the build would do.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BuildPlan {
+    /// Every destination, ordered by output path.
+    pub outputs: Vec<PlannedOutput>,
+    /// Outputs of the previous build, relative to the current directory, that are removed
+    /// because they are no longer generated. See
+    /// [`State::set_manifest_path`](crate::State::set_manifest_path).
+    pub removed: Vec<PathBuf>,
+}
+
+impl BuildPlan {
+    /// Returns the destinations that are written.
+    pub fn dirty(&self) -> impl Iterator<Item = &PlannedOutput> {
+        self.outputs.iter().filter(|o| o.dirty.is_some())
+    }
+
+    /// Returns the destinations that are cached.
+    pub fn cached(&self) -> impl Iterator<Item = &PlannedOutput> {
+        self.outputs.iter().filter(|o| o.dirty.is_none())
+    }
+
+    /// Returns the plan as pretty-printed JSON, eg for review bots.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Compile and render durations of a destination added with
+/// [`State::add_page`](crate::State::add_page).
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceTiming {
+    pub resource: PathBuf,
+    /// Destination, relative to the output root.
+    pub path: PathBuf,
+    pub root: Option<String>,
+    pub compile_ms: f64,
+    pub render_ms: f64,
+}
+
+impl ResourceTiming {
+    /// Returns the sum of the compile and render durations.
+    pub fn total_ms(&self) -> f64 {
+        self.compile_ms + self.render_ms
+    }
+}
+
+/// Runs and total duration of an external command, see
+/// [`State::run_command`](crate::State::run_command).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommandTiming {
+    pub runs: usize,
+    pub total_ms: f64,
+}
+
+/// Summary of a build, returned by [`State::finish`](crate::State::finish). Serialize it, eg
+/// with [`BuildReport::to_json`](BuildReport::to_json), to track build times across builds.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BuildReport {
+    /// Destinations written, other than copies.
+    pub built: usize,
+    /// Destinations that were up to date.
+    pub cached: usize,
+    /// Destinations copied from their resource.
+    pub copied: usize,
+    /// Outputs of the previous build that were removed.
+    pub pruned: usize,
+    /// Duration from [`State::new`](crate::State::new) to the end of
+    /// [`State::finish`](crate::State::finish).
+    pub total_ms: f64,
+    /// Duration of [`State::finish`](crate::State::finish) itself.
+    pub finish_ms: f64,
+    /// Compile and render durations of every page, in the order they were added.
+    pub timings: Vec<ResourceTiming>,
+    /// The [`BuildReport::SLOWEST`](BuildReport::SLOWEST) pages with the longest total
+    /// duration, slowest first.
+    pub slowest: Vec<ResourceTiming>,
+    /// External commands by program name, eg `pandoc`.
+    pub commands: BTreeMap<String, CommandTiming>,
+    /// What was written and why. It is not part of the report's JSON; see
+    /// [`BuildPlan::to_json`](BuildPlan::to_json).
+    #[serde(skip)]
+    pub plan: BuildPlan,
+}
+
+impl BuildReport {
+    /// Number of pages in [`BuildReport::slowest`](BuildReport::slowest).
+    pub const SLOWEST: usize = 10;
+
+    /// Create a report of `plan` with `timings` and `commands`.
+    pub fn new(
+        plan: BuildPlan,
+        timings: Vec<ResourceTiming>,
+        commands: BTreeMap<String, CommandTiming>,
+    ) -> Self {
+        let mut report = BuildReport {
+            pruned: plan.removed.len(),
+            ..Default::default()
+        };
+        for output in plan.outputs.iter() {
+            match output.dirty {
+                None => report.cached += 1,
+                Some(_) if output.renderers == ["copy"] => report.copied += 1,
+                Some(_) => report.built += 1,
+            }
+        }
+        let mut slowest = timings.clone();
+        slowest.sort_by(|a, b| b.total_ms().total_cmp(&a.total_ms()));
+        slowest.truncate(Self::SLOWEST);
+        report.slowest = slowest;
+        report.timings = timings;
+        report.commands = commands;
+        report.plan = plan;
+        report
+    }
+
+    /// Returns the report as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_plan_json() {
+        let plan = BuildPlan {
+            outputs: vec![PlannedOutput {
+                output: "/site/_site/css/style.css".into(),
+                path: "css/style.css".into(),
+                root: None,
+                resource: "scss/style.scss".into(),
+                renderers: vec!["body".into()],
+                dirty: Some(DirtyReason::Dependency {
+                    path: "scss/_colors.scss".into(),
+                }),
+            }],
+            removed: vec!["_site/old.html".into()],
+        };
+        assert_eq!(plan.dirty().count(), 1);
+        let json: Value = serde_json::from_str(&plan.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json["outputs"][0]["dirty"],
+            serde_json::json!({"reason": "dependency", "path": "scss/_colors.scss"})
+        );
+        assert_eq!(json["removed"][0], "_site/old.html");
+    }
+
+    #[test]
+    fn test_build_report() {
+        let output = |path: &str, renderer: &str, dirty: Option<DirtyReason>| PlannedOutput {
+            output: Path::new("/site/_site").join(path),
+            path: path.into(),
+            root: None,
+            resource: path.into(),
+            renderers: vec![renderer.into()],
+            dirty,
+        };
+        let timing = |path: &str, compile_ms: f64, render_ms: f64| ResourceTiming {
+            resource: path.into(),
+            path: path.into(),
+            root: None,
+            compile_ms,
+            render_ms,
+        };
+        let plan = BuildPlan {
+            outputs: vec![
+                output("a.html", "body", Some(DirtyReason::Forced)),
+                output("b.html", "body", None),
+                output("c.png", "copy", Some(DirtyReason::Missing)),
+            ],
+            removed: vec!["_site/old.html".into()],
+        };
+        let report = BuildReport::new(
+            plan,
+            vec![timing("a.html", 1.0, 2.0), timing("b.html", 4.0, 0.5)],
+            Default::default(),
+        );
+        assert_eq!(
+            (report.built, report.cached, report.copied, report.pruned),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(report.slowest[0].path, Path::new("b.html"));
+    }
+}
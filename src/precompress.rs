@@ -0,0 +1,249 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Pre-compressed `.gz` and `.br` siblings of outputs, enabled with the `precompress` feature, for
+//! servers that serve them directly, eg nginx with `gzip_static` and `brotli_static`.
+
+use super::*;
+use std::io::Write;
+
+/// Configuration of pre-compressed outputs, see
+/// [`State::set_precompress`](crate::State::set_precompress).
+///
+/// [`State::finish`](crate::State::finish) writes a `.gz` and a `.br` sibling of every output
+/// whose extension is in `extensions` and whose size is at least `min_size`, eg `index.html.gz`.
+/// Siblings are rewritten when they are older than their output, or with `FORCE`, so changing
+/// the levels requires a forced build to take effect on unchanged outputs. They are recorded in
+/// the output manifest and removed with their output.
+#[derive(Debug, Clone)]
+pub struct Precompress {
+    /// gzip level from 0 to 9, or `None` for no `.gz` files.
+    pub gzip: Option<u32>,
+    /// Brotli quality from 0 to 11, or `None` for no `.br` files.
+    pub brotli: Option<u32>,
+    /// Minimum size in bytes of outputs to compress.
+    pub min_size: u64,
+    /// Extensions of outputs to compress, without the dot.
+    pub extensions: Vec<String>,
+}
+
+impl Precompress {
+    /// Maximum levels for text outputs of at least 1 KiB.
+    pub fn new() -> Self {
+        Precompress {
+            gzip: Some(9),
+            brotli: Some(11),
+            min_size: 1024,
+            extensions: [
+                "html",
+                "htm",
+                "css",
+                "js",
+                "mjs",
+                "json",
+                "xml",
+                "svg",
+                "txt",
+                "map",
+                "webmanifest",
+            ]
+            .iter()
+            .map(|e| e.to_string())
+            .collect(),
+        }
+    }
+
+    /// Write the siblings of `outputs` that are missing or stale, unless in a dry run, and return
+    /// the paths of all siblings they should have. Outputs are read from and siblings written to
+    /// their staging directories in `staging`, if any.
+    pub(crate) fn compress(
+        &self,
+        state: &State,
+        outputs: &[PathBuf],
+        staging: &[(PathBuf, PathBuf)],
+    ) -> Result<Vec<PathBuf>> {
+        let mut siblings = vec![];
+        for output in outputs {
+            let extension = output
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            if !self
+                .extensions
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(&extension))
+            {
+                continue;
+            }
+            let staged_output = staged_path(output, staging);
+            let mtime = match fs::metadata(&staged_output) {
+                Ok(metadata) if metadata.is_file() && metadata.len() >= self.min_size => {
+                    metadata.modified()?
+                }
+                _ => continue,
+            };
+            let mut contents = None;
+            for (suffix, level) in [("gz", self.gzip), ("br", self.brotli)].iter() {
+                let level = match level {
+                    Some(level) => *level,
+                    None => continue,
+                };
+                let mut sibling = output.clone().into_os_string();
+                sibling.push(".");
+                sibling.push(suffix);
+                let sibling = PathBuf::from(sibling);
+                let staged_sibling = staged_path(&sibling, staging);
+                let stale = state.force_generate
+                    || fs::metadata(&staged_sibling)
+                        .and_then(|m| m.modified())
+                        .map(|sibling_mtime| sibling_mtime < mtime)
+                        .unwrap_or(true);
+                if stale && !state.dry_run() {
+                    if contents.is_none() {
+                        contents = Some(fs::read(&staged_output)?);
+                    }
+                    let contents = contents.as_ref().unwrap();
+                    info!(output = %staged_sibling.display(), "compressing");
+                    let compressed = match *suffix {
+                        "gz" => gzip(contents, level)?,
+                        _ => brotli(contents, level)?,
+                    };
+                    replace_file(&staged_sibling, &compressed)?;
+                }
+                siblings.push(sibling);
+            }
+        }
+        Ok(siblings)
+    }
+}
+
+impl Default for Precompress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compress `bytes` with gzip at `level`.
+pub fn gzip(bytes: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder =
+        flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level.min(9)));
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+/// Compress `bytes` with Brotli at quality `level`.
+pub fn brotli(bytes: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, level.min(11), 22);
+    encoder.write_all(bytes)?;
+    encoder.flush()?;
+    Ok(encoder.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_compress() {
+        let text = "<p>Lorem ipsum dolor sit amet.</p>\n".repeat(100);
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&gzip(text.as_bytes(), 9).unwrap()[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+
+        let compressed = brotli(text.as_bytes(), 11).unwrap();
+        assert!(compressed.len() < text.len() / 10);
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&compressed[..], 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[test]
+    fn test_precompress_finish() {
+        let state = crate::tests::test_state("precompress", &[]);
+        let output_dir = state.output_dir().to_path_buf();
+        let root = output_dir.parent().unwrap().to_path_buf();
+        let (templates, manifest) = (root.join("templates"), root.join(".cache/outputs.json"));
+        let text = "<p>Lorem ipsum dolor sit amet.</p>\n".repeat(10);
+        let build = |pages: &[&str]| {
+            let mut state = State::with_dirs(templates.clone(), &output_dir).unwrap();
+            state
+                .set_manifest_path(Some(manifest.clone()))
+                .set_precompress(Some(Precompress {
+                    min_size: 100,
+                    ..Precompress::new()
+                }));
+            for dest in pages {
+                let mut metadata = Map::new();
+                let body = if *dest == "small.html" {
+                    "small"
+                } else {
+                    &text
+                };
+                metadata.insert("body".into(), body.into());
+                state
+                    .add_page(
+                        dest.into(),
+                        dest.into(),
+                        &const_compiler(metadata),
+                        Renderer::body(),
+                    )
+                    .unwrap();
+            }
+            state.finish().unwrap();
+        };
+        let exists = |path: &str| output_dir.join(path).exists();
+
+        build(&["index.html", "small.html", "data.bin", "style.css"]);
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&fs::read(output_dir.join("index.html.gz")).unwrap()[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+        assert!(exists("index.html.br"));
+        assert!(exists("style.css.gz") && exists("style.css.br"));
+        // Outputs smaller than `min_size` or with other extensions are not compressed.
+        assert!(!exists("small.html.gz") && !exists("small.html.br"));
+        assert!(!exists("data.bin.gz") && !exists("data.bin.br"));
+
+        // Siblings newer than their output are kept, and the siblings of outputs that are no
+        // longer produced are removed with them.
+        let sibling = output_dir.join("index.html.gz");
+        fs::write(&sibling, "fresh").unwrap();
+        let future = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&sibling)
+            .unwrap()
+            .set_modified(future)
+            .unwrap();
+        build(&["index.html", "small.html", "data.bin"]);
+        assert_eq!(fs::read(&sibling).unwrap(), b"fresh");
+        assert!(exists("index.html.br"));
+        assert!(!exists("style.css") && !exists("style.css.gz") && !exists("style.css.br"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Sorting, filtering and limiting the artifacts of snapshots.
+
+use super::*;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// Query step operation.
+#[derive(Clone)]
+enum QueryOp {
+    SortByKey(String),
+    Rev,
+    Filter(Rc<dyn Fn(&BuildArtifact) -> bool>),
+    Skip(usize),
+    Take(usize),
+}
+
+/// A query over the artifacts of a named snapshot, evaluated lazily against a
+/// [`State`](crate::State). Operations are applied in the order they were added, like iterator
+/// adaptors:
+///
+/// ```no_run
+/// # use libssg::*;
+/// let recent_posts = snapshot("posts")
+///     .sort_by_key("date")
+///     .rev()
+///     .filter(|artifact| artifact.metadata.get("draft") != Some(&Value::Bool(true)))
+///     .take(10);
+/// ```
+///
+/// A bare snapshot name converts into a query that returns the artifacts in insertion order.
+#[derive(Clone)]
+pub struct SnapshotQuery {
+    name: String,
+    ops: Vec<QueryOp>,
+}
+
+/// Create a query over the artifacts of snapshot `name`.
+pub fn snapshot<S: Into<String>>(name: S) -> SnapshotQuery {
+    SnapshotQuery {
+        name: name.into(),
+        ops: vec![],
+    }
+}
+
+impl From<&str> for SnapshotQuery {
+    fn from(name: &str) -> Self {
+        snapshot(name)
+    }
+}
+
+impl From<String> for SnapshotQuery {
+    fn from(name: String) -> Self {
+        snapshot(name)
+    }
+}
+
+impl std::fmt::Debug for SnapshotQuery {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "snapshot({:?})", self.name)?;
+        for op in self.ops.iter() {
+            match op {
+                QueryOp::SortByKey(key) => write!(fmt, ".sort_by_key({:?})", key)?,
+                QueryOp::Rev => write!(fmt, ".rev()")?,
+                QueryOp::Filter(_) => write!(fmt, ".filter(_)")?,
+                QueryOp::Skip(n) => write!(fmt, ".skip({})", n)?,
+                QueryOp::Take(n) => write!(fmt, ".take({})", n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotQuery {
+    /// Returns the snapshot's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sort by metadata key `key` in ascending order, comparing dates chronologically (see
+    /// [`parse_date`](crate::parse_date)). Artifacts without the key are placed last.
+    pub fn sort_by_key<S: Into<String>>(mut self, key: S) -> Self {
+        self.ops.push(QueryOp::SortByKey(key.into()));
+        self
+    }
+
+    /// Reverse the current order. After [`sort_by_key`](SnapshotQuery::sort_by_key), artifacts
+    /// without the key stay last, so that `sort_by_key("date").rev()` returns the newest first.
+    /// If some values of the key are dates, strings that aren't, eg `date: draft`, also stay last,
+    /// before the artifacts without the key.
+    pub fn rev(mut self) -> Self {
+        self.ops.push(QueryOp::Rev);
+        self
+    }
+
+    /// Keep only artifacts for which `predicate` returns `true`.
+    pub fn filter<F: Fn(&BuildArtifact) -> bool + 'static>(mut self, predicate: F) -> Self {
+        self.ops.push(QueryOp::Filter(Rc::new(predicate)));
+        self
+    }
+
+    /// Skip the first `n` artifacts.
+    pub fn skip(mut self, n: usize) -> Self {
+        self.ops.push(QueryOp::Skip(n));
+        self
+    }
+
+    /// Keep at most the first `n` artifacts.
+    pub fn take(mut self, n: usize) -> Self {
+        self.ops.push(QueryOp::Take(n));
+        self
+    }
+
+    /// Evaluate the query and return the uuids of the matching artifacts.
+    pub fn uuids(&self, state: &State) -> Result<Vec<Uuid>> {
+        Ok(self.run(state)?.into_iter().map(|a| a.uuid).collect())
+    }
+
+    /// Evaluate the query and return the matching artifacts.
+    pub fn run<'s>(&self, state: &'s State) -> Result<Vec<&'s BuildArtifact>> {
+        state.record_read(Some(&self.name));
+        let members = state.snapshots.get(&self.name).ok_or_else(|| format!("There are no snapshots with key `{}`, is the source rule empty (ie producing no items) or have you typed the name wrong?", &self.name))?;
+        let mut ret = members
+            .iter()
+            .map(|uuid| &state.artifacts[uuid])
+            .collect::<Vec<&BuildArtifact>>();
+        let mut sort_key = None;
+        for op in self.ops.iter() {
+            match op {
+                QueryOp::SortByKey(key) => {
+                    ret.sort_by(|a, b| cmp_metadata(a.metadata.get(key), b.metadata.get(key)));
+                    sort_key = Some(key);
+                }
+                QueryOp::Rev => {
+                    ret.reverse();
+                    if let Some(key) = sort_key {
+                        let is_date = |a: &BuildArtifact| {
+                            a.metadata
+                                .get(key)
+                                .and_then(Value::as_str)
+                                .and_then(parse_date)
+                                .is_some()
+                        };
+                        let dated = ret.iter().any(|a| is_date(a));
+                        // Stable, so the rest keeps its reversed order.
+                        ret.sort_by_key(|a| match a.metadata.get(key) {
+                            None => 2,
+                            Some(Value::String(_)) if dated && !is_date(a) => 1,
+                            _ => 0,
+                        });
+                    }
+                }
+                QueryOp::Filter(predicate) => ret.retain(|a| predicate(a)),
+                QueryOp::Skip(n) => {
+                    ret.drain(..std::cmp::min(*n, ret.len()));
+                }
+                QueryOp::Take(n) => ret.truncate(*n),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// A [`SnapshotQuery`](SnapshotQuery) bound to a [`State`](crate::State), returned by
+/// [`State::snapshot`](crate::State::snapshot).
+pub struct Snapshot<'s> {
+    state: &'s State,
+    query: SnapshotQuery,
+}
+
+impl<'s> Snapshot<'s> {
+    pub(crate) fn new(state: &'s State, query: SnapshotQuery) -> Self {
+        Snapshot { state, query }
+    }
+
+    /// See [`SnapshotQuery::sort_by_key`](SnapshotQuery::sort_by_key).
+    pub fn sort_by_key<S: Into<String>>(self, key: S) -> Self {
+        Snapshot::new(self.state, self.query.sort_by_key(key))
+    }
+
+    /// See [`SnapshotQuery::rev`](SnapshotQuery::rev).
+    pub fn rev(self) -> Self {
+        Snapshot::new(self.state, self.query.rev())
+    }
+
+    /// See [`SnapshotQuery::filter`](SnapshotQuery::filter).
+    pub fn filter<F: Fn(&BuildArtifact) -> bool + 'static>(self, predicate: F) -> Self {
+        Snapshot::new(self.state, self.query.filter(predicate))
+    }
+
+    /// See [`SnapshotQuery::skip`](SnapshotQuery::skip).
+    pub fn skip(self, n: usize) -> Self {
+        Snapshot::new(self.state, self.query.skip(n))
+    }
+
+    /// See [`SnapshotQuery::take`](SnapshotQuery::take).
+    pub fn take(self, n: usize) -> Self {
+        Snapshot::new(self.state, self.query.take(n))
+    }
+
+    /// Evaluate the query and return the matching artifacts.
+    pub fn artifacts(&self) -> Result<Vec<&'s BuildArtifact>> {
+        self.query.run(self.state)
+    }
+
+    /// Evaluate the query and return the matching artifacts' template contexts (see
+    /// [`BuildArtifact::context`](crate::BuildArtifact::context)).
+    pub fn contexts(&self) -> Result<Vec<Value>> {
+        Ok(self
+            .artifacts()?
+            .into_iter()
+            .map(|a| Value::Object(a.context()))
+            .collect())
+    }
+
+    /// Returns the unbound query.
+    pub fn into_query(self) -> SnapshotQuery {
+        self.query
+    }
+}
+
+/// Compare two metadata values. Numbers sort before strings, which sort before other values.
+/// Strings that are dates (see [`parse_date`](crate::parse_date)) are compared chronologically
+/// and sort before other strings, which are compared lexically. Missing values are greater than
+/// everything else.
+pub fn cmp_metadata(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    fn kind(value: &Value) -> u8 {
+        match value {
+            Value::Number(_) => 0,
+            Value::String(_) => 1,
+            _ => 2,
+        }
+    }
+    fn string_key(s: &str) -> (bool, Option<chrono::DateTime<chrono::FixedOffset>>, &str) {
+        let date = parse_date(s);
+        (date.is_none(), date, s)
+    }
+    match (a, b) {
+        (Some(a), Some(b)) => kind(a).cmp(&kind(b)).then_with(|| match (a, b) {
+            (Value::Number(a), Value::Number(b)) => a
+                .as_f64()
+                .partial_cmp(&b.as_f64())
+                .unwrap_or(Ordering::Equal),
+            (Value::String(a), Value::String(b)) => string_key(a).cmp(&string_key(b)),
+            (a, b) => a.to_string().cmp(&b.to_string()),
+        }),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cmp_metadata() {
+        let values = [
+            json!("not a date"),
+            json!("2020-03-01"),
+            json!(2),
+            json!("2020-01-01T10:00:00+02:00"),
+            json!("another"),
+            json!(10),
+            json!(true),
+            json!("2020-01-01"),
+        ];
+        let mut sorted = values.iter().map(Some).collect::<Vec<_>>();
+        sorted.push(None);
+        sorted.sort_by(|a, b| cmp_metadata(*a, *b));
+        assert_eq!(
+            sorted,
+            vec![
+                Some(&json!(2)),
+                Some(&json!(10)),
+                Some(&json!("2020-01-01")),
+                Some(&json!("2020-01-01T10:00:00+02:00")),
+                Some(&json!("2020-03-01")),
+                Some(&json!("another")),
+                Some(&json!("not a date")),
+                Some(&json!(true)),
+                None,
+            ]
+        );
+        for a in sorted.iter() {
+            for b in sorted.iter() {
+                assert_eq!(cmp_metadata(*a, *b), cmp_metadata(*b, *a).reverse());
+            }
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let mut state = crate::tests::test_state("query", &[]);
+        for (name, date) in [
+            ("a", Some("2020-02-01")),
+            ("b", None),
+            ("c", Some("2020-01-01")),
+            ("d", Some("2020-03-01")),
+            ("e", Some("draft")),
+        ] {
+            let uuid = uuid_from_path(Path::new(name));
+            let mut metadata = Map::new();
+            metadata.insert("title".into(), name.into());
+            if let Some(date) = date {
+                metadata.insert("date".into(), date.into());
+            }
+            state.artifacts.insert(
+                uuid,
+                BuildArtifact {
+                    uuid,
+                    path: PathBuf::from(name),
+                    root: None,
+                    resource: PathBuf::from(name),
+                    metadata,
+                    contents: String::new(),
+                    snapshots: Default::default(),
+                },
+            );
+            state.add_to_snapshot("posts".into(), uuid);
+        }
+        let titles = |query: SnapshotQuery| {
+            query
+                .run(&state)
+                .unwrap()
+                .into_iter()
+                .map(|a| a.metadata["title"].as_str().unwrap())
+                .collect::<String>()
+        };
+
+        assert_eq!(titles(snapshot("posts")), "abcde");
+        assert_eq!(titles(snapshot("posts").rev()), "edcba");
+        assert_eq!(titles(snapshot("posts").sort_by_key("date")), "cadeb");
+        assert_eq!(titles(snapshot("posts").sort_by_key("date").rev()), "daceb");
+        assert_eq!(
+            titles(snapshot("posts").sort_by_key("title").rev()),
+            "edcba"
+        );
+        assert_eq!(
+            titles(
+                snapshot("posts")
+                    .filter(|a| a
+                        .metadata
+                        .get("date")
+                        .and_then(Value::as_str)
+                        .and_then(parse_date)
+                        .is_some())
+                    .sort_by_key("date")
+                    .rev()
+            ),
+            "dac"
+        );
+        assert_eq!(
+            titles(snapshot("posts").sort_by_key("date").skip(1).take(2)),
+            "ad"
+        );
+        assert_eq!(titles(snapshot("posts").skip(10)), "");
+        assert_eq!(titles(snapshot("posts").take(10)), "abcde");
+        assert!(snapshot("pages").run(&state).is_err());
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+}
@@ -21,10 +21,13 @@
 
 //![Renderer]s are template rendering pipelines used by [Compiler](crate::compilers::Compiler)
 
-use super::{Result, State};
+use super::{DirtyReason, Result, State};
 use serde_json::{Map, Value};
 use std::path::Path;
 
+pub mod minify_html;
+pub use minify_html::*;
+
 /// Simple trait to clone boxed closures.
 pub trait BFn: Fn(&mut State, &mut Map<String, Value>) -> Result<String> {
     fn clone_boxed(&self) -> Box<dyn BFn>;
@@ -50,6 +53,14 @@ impl Clone for Box<dyn BFn> {
 pub enum Renderer {
     LoadAndApplyTemplate(&'static str),
     Pipeline(Vec<Renderer>),
+    /// Pass the current `body` through unchanged, saving it as a named snapshot of the artifact
+    /// being rendered. See [`State::save_snapshot`](crate::State::save_snapshot).
+    Snapshot(&'static str),
+    /// Output the compiled `body` unchanged, see [`Renderer::body`](Renderer::body).
+    Body,
+    /// Minify the HTML `body`, usually as the last stage of a pipeline. See
+    /// [`MinifyHtml`](MinifyHtml).
+    MinifyHtml(MinifyHtml),
     Custom(Box<dyn BFn>),
     None,
 }
@@ -60,6 +71,9 @@ impl std::fmt::Debug for Renderer {
         match self {
             LoadAndApplyTemplate(ref t) => write!(fmt, "Renderer::LoadAndApplyTemplate({})", t),
             Pipeline(ref list) => write!(fmt, "Renderer::Pipeline({:?})", list),
+            Snapshot(ref name) => write!(fmt, "Renderer::Snapshot({})", name),
+            Body => write!(fmt, "Renderer::Body"),
+            MinifyHtml(ref options) => write!(fmt, "Renderer::MinifyHtml({:?})", options),
             Custom(_) => write!(fmt, "Renderer::Custom(_)"),
             None => write!(fmt, "Renderer::None"),
         }
@@ -70,20 +84,49 @@ impl Renderer {
     /// check if we should overwrite `dest_path` by checking if the template's mtime is newer
     /// than the destination.
     pub fn check_mtime(&self, state: &mut State, dest_path: &Path) -> bool {
+        self.dirty_reason(state, dest_path).is_some()
+    }
+
+    /// Returns why `dest_path` must be regenerated because of this renderer, if it must, see
+    /// [`Renderer::check_mtime`](Renderer::check_mtime).
+    pub fn dirty_reason(&self, state: &State, dest_path: &Path) -> Option<DirtyReason> {
         match self {
-            Renderer::LoadAndApplyTemplate(ref path) => {
-                state.check_mtime(dest_path, &Path::new(path))
+            Renderer::LoadAndApplyTemplate(ref path) => state
+                .mtime_reason(dest_path, Path::new(path))
+                .map(|reason| match reason {
+                    DirtyReason::Modified => DirtyReason::Template { path: path.into() },
+                    reason => reason,
+                }),
+            Renderer::Pipeline(ref list) => {
+                list.iter().find_map(|el| el.dirty_reason(state, dest_path))
             }
-            Renderer::Pipeline(ref list) => list
-                .iter()
-                .fold(false, |acc, el| acc || el.check_mtime(state, dest_path)),
-            Renderer::None | Renderer::Custom(_) => true,
+            Renderer::Snapshot(_) | Renderer::Body | Renderer::MinifyHtml(_) => None,
+            Renderer::None | Renderer::Custom(_) => Some(DirtyReason::Renderer),
         }
     }
 
+    /// Returns a description of each stage, see [`PlannedOutput`](crate::PlannedOutput).
+    pub fn stages(&self) -> Vec<String> {
+        match self {
+            Renderer::LoadAndApplyTemplate(ref path) => vec![format!("template {}", path)],
+            Renderer::Pipeline(ref list) => list.iter().flat_map(Renderer::stages).collect(),
+            Renderer::Snapshot(ref name) => vec![format!("snapshot {}", name)],
+            Renderer::Body => vec!["body".into()],
+            Renderer::MinifyHtml(_) => vec!["minify_html".into()],
+            Renderer::Custom(_) => vec!["custom".into()],
+            Renderer::None => vec!["copy".into()],
+        }
+    }
+
+    /// A renderer that outputs the compiled `body` unchanged, for example for feeds. Its
+    /// destination is only regenerated when its resource or dependencies change.
+    pub fn body() -> Self {
+        Renderer::Body
+    }
+
     pub fn render(&self, state: &mut State, context: &mut Map<String, Value>) -> Result<String> {
         Ok(match self {
-            Renderer::LoadAndApplyTemplate(ref path) => state.templates_render(path, context)?,
+            Renderer::LoadAndApplyTemplate(path) => state.templates_render(path, context)?,
             Renderer::Pipeline(ref list) => {
                 let mut iter = list.iter().peekable();
                 while let Some(stage) = iter.next() {
@@ -96,8 +139,27 @@ impl Renderer {
                 }
                 String::new()
             }
+            Renderer::Snapshot(name) => {
+                let body = context
+                    .get("body")
+                    .and_then(|b| b.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                state.save_snapshot(name, body.clone())?;
+                body
+            }
+            Renderer::Body => render_body(state, context)?,
+            Renderer::MinifyHtml(ref options) => options.minify(&render_body(state, context)?)?,
             Renderer::Custom(ref c) => c(state, context)?,
             Renderer::None => String::new(),
         })
     }
 }
+
+fn render_body(_state: &mut State, context: &mut Map<String, Value>) -> Result<String> {
+    Ok(context
+        .get("body")
+        .and_then(|b| b.as_str())
+        .ok_or_else(|| format!("metadata does not contain `body`: {:#?}", &context))?
+        .to_string())
+}
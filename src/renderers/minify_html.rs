@@ -0,0 +1,351 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! HTML minification stage of a [`Renderer::Pipeline`](crate::Renderer::Pipeline).
+
+use super::*;
+
+/// Configuration of a [`Renderer::MinifyHtml`](crate::Renderer::MinifyHtml) stage.
+///
+/// Runs of whitespace are collapsed into a single space and removed entirely next to block-level
+/// elements such as `<p>` or `<div>`. The contents of `<pre>`, `<textarea>`, `<script>` and
+/// `<style>` elements are left untouched, unless inline CSS or JS minification is enabled.
+/// Comments are removed, except for conditional comments (`<!--[if IE]>`).
+#[derive(Debug, Clone, Default)]
+pub struct MinifyHtml {
+    /// Keep comments.
+    pub keep_comments: bool,
+    /// Minify the contents of `<style>` elements. Requires the `minify` feature.
+    pub minify_css: bool,
+    /// Minify the contents of JavaScript `<script>` elements. Requires the `minify` feature.
+    pub minify_js: bool,
+}
+
+/// Elements whose surrounding whitespace is not rendered.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "!doctype",
+    "address",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "body",
+    "br",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "noscript",
+    "ol",
+    "option",
+    "p",
+    "pre",
+    "script",
+    "section",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// Elements whose contents are copied verbatim or handed to a minifier.
+const RAW_ELEMENTS: &[&str] = &["pre", "textarea", "script", "style"];
+
+/// Returns the end of the tag starting at `start`, after its `>`, skipping quoted attribute
+/// values.
+fn tag_end(html: &str, start: usize) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return Some(start + i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Collapse whitespace outside of quoted attribute values in `tag` and remove it before `>`.
+fn minify_tag(tag: &str) -> String {
+    let mut ret = String::with_capacity(tag.len());
+    let mut quote = None;
+    let mut space = false;
+    for c in tag.chars() {
+        match quote {
+            Some(q) => {
+                ret.push(c);
+                if c == q {
+                    quote = None;
+                }
+            }
+            None if c.is_ascii_whitespace() => space = true,
+            None => {
+                if space && c != '>' {
+                    ret.push(' ');
+                }
+                space = false;
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+                ret.push(c);
+            }
+        }
+    }
+    ret
+}
+
+/// Returns the lowercase name of tag `tag`, with a leading `/` for end tags.
+fn tag_name(tag: &str) -> String {
+    let (prefix, rest) = match tag[1..].strip_prefix('/') {
+        Some(rest) => ("/", rest),
+        None => ("", &tag[1..]),
+    };
+    let name = rest
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '!' || *c == '-' || *c == ':')
+        .map(|c| c.to_ascii_lowercase())
+        .collect::<String>();
+    format!("{}{}", prefix, name)
+}
+
+fn is_block(name: &str) -> bool {
+    BLOCK_ELEMENTS.contains(&name.trim_start_matches('/'))
+}
+
+impl MinifyHtml {
+    /// Create a configuration that removes comments and leaves inline CSS and JS untouched.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Minify `html`.
+    pub fn minify(&self, html: &str) -> Result<String> {
+        if (self.minify_css || self.minify_js) && !cfg!(feature = "minify") {
+            Err("Inline CSS and JS minification requires the `minify` feature")?;
+        }
+        let lowercase = html.to_ascii_lowercase();
+        let mut ret = String::with_capacity(html.len());
+        // Whether whitespace was skipped since the last output, and whether the last output was
+        // a block-level element.
+        let mut space = false;
+        let mut after_block = true;
+        let mut i = 0;
+        while i < html.len() {
+            let rest = &html[i..];
+            if rest.starts_with("<!--") {
+                let end = lowercase[i..]
+                    .find("-->")
+                    .map(|e| i + e + 3)
+                    .unwrap_or(html.len());
+                if self.keep_comments || rest.starts_with("<!--[if") {
+                    if space && !after_block {
+                        ret.push(' ');
+                    }
+                    space = false;
+                    ret.push_str(&html[i..end]);
+                }
+                i = end;
+                continue;
+            }
+            let is_tag = rest.starts_with('<')
+                && rest[1..]
+                    .chars()
+                    .next()
+                    .map(|c| c.is_ascii_alphabetic() || c == '/' || c == '!')
+                    .unwrap_or(false);
+            if is_tag {
+                if let Some(end) = tag_end(html, i) {
+                    let tag = &html[i..end];
+                    let name = tag_name(tag);
+                    let block = is_block(&name);
+                    if space && !after_block && !block {
+                        ret.push(' ');
+                    }
+                    space = false;
+                    ret.push_str(&minify_tag(tag));
+                    i = end;
+                    after_block = block;
+                    if RAW_ELEMENTS.contains(&name.as_str()) && !tag.ends_with("/>") {
+                        let close = format!("</{}", name);
+                        let content_end = lowercase[i..]
+                            .find(&close)
+                            .map(|e| i + e)
+                            .unwrap_or(html.len());
+                        ret.push_str(&self.raw_contents(&name, tag, &html[i..content_end]));
+                        i = content_end;
+                    }
+                    continue;
+                }
+            }
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let text_end = rest[first..]
+                .find('<')
+                .map(|e| i + first + e)
+                .unwrap_or(html.len());
+            for (j, word) in html[i..text_end]
+                .split(|c: char| c.is_ascii_whitespace())
+                .enumerate()
+            {
+                if j > 0 {
+                    space = true;
+                }
+                if word.is_empty() {
+                    continue;
+                }
+                if space && !after_block {
+                    ret.push(' ');
+                }
+                space = false;
+                after_block = false;
+                ret.push_str(word);
+            }
+            i = text_end;
+        }
+        Ok(ret)
+    }
+
+    /// Returns the contents of raw element `name` with start tag `tag`, minified if enabled.
+    /// Contents that fail to minify are kept unchanged.
+    #[allow(unused_variables)]
+    fn raw_contents(&self, name: &str, tag: &str, contents: &str) -> String {
+        #[cfg(feature = "minify")]
+        {
+            use crate::compilers::minify::{minify_css_str, minify_js_str};
+            let tag = tag.to_ascii_lowercase();
+            let minified = match name {
+                "style" if self.minify_css => {
+                    minify_css_str(contents, Path::new("inline.css"), false).ok()
+                }
+                "script" if self.minify_js => {
+                    static TYPE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+                    let kind = TYPE
+                        .get_or_init(|| {
+                            regex::Regex::new(r#"\btype\s*=\s*["']?([^"'\s>]+)"#).unwrap()
+                        })
+                        .captures(&tag)
+                        .map(|c| c[1].to_string());
+                    match kind.as_deref() {
+                        None | Some("text/javascript") | Some("application/javascript") => {
+                            minify_js_str(contents, Path::new("inline.js")).ok()
+                        }
+                        Some("module") => minify_js_str(contents, Path::new("inline.mjs")).ok(),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if let Some(minified) = minified {
+                return minified;
+            }
+        }
+        contents.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify_html() {
+        let html = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title> Title </title>
+    <!-- comment -->
+    <meta   charset="utf-8" >
+  </head>
+  <body>
+    <p class="a  b">Some   <em>emphasized</em>
+      text&nbsp;here.</p>
+    <pre>
+  keep   this
+</pre>
+    <textarea>  and  this </textarea>
+    <script>  if (a < b) { x(); }  </script>
+    <br />
+  </body>
+</html>
+"#;
+        assert_eq!(
+            MinifyHtml::new().minify(html).unwrap(),
+            "<!DOCTYPE html><html><head><title>Title</title><meta charset=\"utf-8\"></head><body><p class=\"a  b\">Some <em>emphasized</em> text&nbsp;here.</p><pre>\n  keep   this\n</pre><textarea>  and  this </textarea><script>  if (a < b) { x(); }  </script><br /></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_minify_html_non_ascii() {
+        assert_eq!(
+            MinifyHtml::new()
+                .minify("<p>émigré   text</p>\n<p>ü</p>")
+                .unwrap(),
+            "<p>émigré text</p><p>ü</p>"
+        );
+    }
+
+    #[cfg(feature = "minify")]
+    #[test]
+    fn test_minify_inline() {
+        let options = MinifyHtml {
+            minify_css: true,
+            minify_js: true,
+            ..MinifyHtml::new()
+        };
+        assert_eq!(
+            options
+                .minify("<style> a { color: #ff0000; } </style><script>let  x = 1;</script><script type=\"application/ld+json\"> {} </script>")
+                .unwrap(),
+            "<style>a{color:red}</style><script>let x=1</script><script type=\"application/ld+json\"> {} </script>"
+        );
+    }
+}
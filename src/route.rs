@@ -33,3 +33,19 @@ pub enum Route {
     SetExtension(&'static str),
     Custom(Box<dyn Fn(&Path) -> PathBuf>),
 }
+
+impl Route {
+    /// Map the relative file system path `path` to its destination.
+    pub fn apply(&self, path: &Path) -> PathBuf {
+        match self {
+            Route::Id => path.to_path_buf(),
+            Route::Const(ref s) => PathBuf::from(s),
+            Route::SetExtension(extension) => {
+                let mut path = path.to_path_buf();
+                path.set_extension(extension);
+                path
+            }
+            Route::Custom(ref cl) => cl(path),
+        }
+    }
+}
@@ -25,6 +25,42 @@
 use super::*;
 use std::env;
 
+pub mod archive;
+pub use archive::*;
+
+#[cfg(feature = "epub")]
+pub mod epub;
+#[cfg(feature = "epub")]
+pub use epub::*;
+
+pub mod fingerprint;
+pub use fingerprint::*;
+
+#[cfg(feature = "gallery")]
+pub mod gallery;
+#[cfg(feature = "gallery")]
+pub use gallery::*;
+
+#[cfg(feature = "images")]
+pub mod images;
+#[cfg(feature = "images")]
+pub use images::*;
+
+pub mod paginate;
+pub use paginate::*;
+
+pub mod redirects;
+pub use redirects::*;
+
+pub mod search;
+pub use search::*;
+
+pub mod sitemap;
+pub use sitemap::*;
+
+pub mod taxonomy;
+pub use taxonomy::*;
+
 /// [`Rule`](Rule)s are generation steps, that is, separate steps in the generation process. They can
 /// alter [`State`](State) however they like.
 pub type Rule = Box<dyn FnOnce(&mut State) -> Result<()>>;
@@ -48,18 +84,8 @@ pub fn match_pattern<P: Into<MatchPattern>>(
                     continue;
                 };
                 if extension == "markdown" || extension == "md" {
-                    let mut dest_path = resource
-                        .strip_prefix(env::current_dir().unwrap())?
-                        .to_path_buf();
-                    let dest_path = match route {
-                        Route::Id => dest_path,
-                        Route::Const(ref s) => PathBuf::from(s),
-                        Route::SetExtension(extension) => {
-                            dest_path.set_extension(extension);
-                            dest_path
-                        }
-                        Route::Custom(ref cl) => cl(&dest_path),
-                    };
+                    let dest_path =
+                        route.apply(resource.strip_prefix(env::current_dir().unwrap())?);
                     state.add_page(
                         dest_path.clone(),
                         resource.clone(),
@@ -89,23 +115,206 @@ pub fn copy<P: Into<MatchPattern>>(pattern: P, route: Route) -> Rule {
             for entry in pattern.list() {
                 let rel_path = entry
                     .path()
-                    .strip_prefix(&state.current_dir())?
+                    .strip_prefix(state.current_dir())?
+                    .to_path_buf();
+                let dest_path = route.apply(&rel_path);
+                state.copy_page(rel_path, dest_path)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Compile every file that matches `pattern` with `compiler` and write the `body` it returns to
+/// destinations according to `route`. Unlike [`match_pattern`](match_pattern) any file type is
+/// compiled, eg stylesheets:
+/// `compile("^css/[^_][^/]*\\.scss$", Route::SetExtension("css"), sass(vec![]))`.
+pub fn compile<P: Into<MatchPattern>>(pattern: P, route: Route, compiler: Compiler) -> Rule {
+    let patterns = pattern.into();
+    Box::new(move |state: &mut State| {
+        for pattern in patterns {
+            for entry in pattern.list() {
+                let rel_path = entry
+                    .path()
+                    .strip_prefix(state.current_dir())?
                     .to_path_buf();
-                state.copy_page(
-                    rel_path.clone(),
-                    match route {
-                        Route::Id => rel_path,
-                        Route::Const(ref s) => PathBuf::from(s),
-                        Route::SetExtension(extension) => {
-                            let mut path = rel_path;
-                            path.set_extension(extension);
-                            path
-                        }
-                        Route::Custom(ref cl) => cl(&rel_path),
-                    },
-                );
+                let dest_path = route.apply(&rel_path);
+                state.add_page(dest_path, rel_path, &compiler, Renderer::body())?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Run `rule` with its destinations in output root `root` instead of the site's output directory,
+/// eg `output_root("capsule", copy("^images/*", Route::Id))`. The root must have been added with
+/// [`State::add_output_root`](crate::State::add_output_root).
+///
+/// Artifacts generated in an output root are distinct from artifacts of the same resource in other
+/// roots, so one resource can be rendered to both. Rules that collect artifacts by destination,
+/// such as [`sitemap`](crate::sitemap), only see those of the root they run in.
+pub fn output_root(root: &'static str, rule: Rule) -> Rule {
+    Box::new(move |state: &mut State| {
+        let previous = state.root.replace(root.to_string());
+        let ret = rule(state);
+        state.root = previous;
+        ret
+    })
+}
+
+/// Create the custom `404.html` error page from `resource`. The page is excluded from the
+/// [`sitemap`](crate::sitemap).
+pub fn not_found_page(resource: PathBuf, renderer: Renderer, compiler: Compiler) -> Rule {
+    Box::new(move |state: &mut State| {
+        let mut metadata = Map::new();
+        metadata.insert("sitemap".into(), false.into());
+        state.add_page(
+            PathBuf::from("404.html"),
+            resource,
+            &compiler_seq(compiler, const_compiler(metadata)),
+            renderer,
+        )?;
+        Ok(())
+    })
+}
+
+/// A group of `robots.txt` directives for some user agents.
+#[derive(Debug, Clone)]
+pub struct RobotsGroup {
+    pub user_agents: Vec<String>,
+    pub allow: Vec<String>,
+    pub disallow: Vec<String>,
+}
+
+/// Contents of `robots.txt`.
+#[derive(Debug, Clone)]
+pub struct Robots {
+    pub groups: Vec<RobotsGroup>,
+    /// Absolute URLs of sitemaps, eg `https://example.com/sitemap.xml`.
+    pub sitemaps: Vec<String>,
+}
+
+impl Robots {
+    /// Allow every user agent everywhere and reference sitemap `sitemap_url`.
+    pub fn allow_all<S: Into<String>>(sitemap_url: S) -> Self {
+        Robots {
+            groups: vec![RobotsGroup {
+                user_agents: vec!["*".to_string()],
+                allow: vec![],
+                disallow: vec![],
+            }],
+            sitemaps: vec![sitemap_url.into()],
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut ret = String::new();
+        for group in self.groups.iter() {
+            for user_agent in group.user_agents.iter() {
+                ret.push_str(&format!("User-agent: {}\n", user_agent));
+            }
+            for path in group.allow.iter() {
+                ret.push_str(&format!("Allow: {}\n", path));
+            }
+            if group.disallow.is_empty() && group.allow.is_empty() {
+                ret.push_str("Disallow:\n");
             }
+            for path in group.disallow.iter() {
+                ret.push_str(&format!("Disallow: {}\n", path));
+            }
+            ret.push('\n');
+        }
+        for sitemap in self.sitemaps.iter() {
+            ret.push_str(&format!("Sitemap: {}\n", sitemap));
         }
+        ret
+    }
+}
+
+/// Create `robots.txt`.
+pub fn robots_txt(robots: Robots) -> Rule {
+    Box::new(move |state: &mut State| {
+        let path = PathBuf::from("robots.txt");
+        let mut metadata = Map::new();
+        metadata.insert("body".into(), robots.render().into());
+        state.add_page(
+            path.clone(),
+            path,
+            &const_compiler(metadata),
+            Renderer::body(),
+        )?;
         Ok(())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_robots_txt() {
+        let robots = Robots {
+            groups: vec![
+                RobotsGroup {
+                    user_agents: vec!["*".into()],
+                    allow: vec![],
+                    disallow: vec![],
+                },
+                RobotsGroup {
+                    user_agents: vec!["a".into(), "b".into()],
+                    allow: vec!["/public/".into()],
+                    disallow: vec!["/".into()],
+                },
+            ],
+            sitemaps: vec!["https://example.com/sitemap.xml".into()],
+        };
+        let mut state = crate::tests::test_state("robots", &[]);
+        state.then(robots_txt(robots));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        assert_eq!(
+            state.artifacts[&uuid_from_path(Path::new("robots.txt"))].contents,
+            "User-agent: *\nDisallow:\n\nUser-agent: a\nUser-agent: b\nAllow: /public/\nDisallow: /\n\nSitemap: https://example.com/sitemap.xml\n"
+        );
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_not_found_page() {
+        let mut state = crate::tests::test_state("not-found", &[]);
+        let mut metadata = Map::new();
+        metadata.insert("body".into(), "Not found".into());
+        state.then(not_found_page(
+            "404.md".into(),
+            Renderer::body(),
+            const_compiler(metadata),
+        ));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        let uuid = uuid_from_path(Path::new("404.md"));
+        assert_eq!(state.routes[&state.output_dir().join("404.html")], uuid);
+        let artifact = &state.artifacts[&uuid];
+        assert_eq!(artifact.contents, "Not found");
+        assert_eq!(artifact.metadata["sitemap"], false);
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_route_collision() {
+        let mut state = crate::tests::test_state("collision", &[]);
+        let mut metadata = Map::new();
+        metadata.insert("body".into(), "a".into());
+        state.then(not_found_page(
+            "404.md".into(),
+            Renderer::body(),
+            const_compiler(metadata),
+        ));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        state.then(Box::new(|state: &mut State| {
+            state.copy_page("b.md".into(), "404.html".into())?;
+            Ok(())
+        }));
+        let err = state.err.take().unwrap().to_string();
+        assert!(err.contains("404.html"), "{}", err);
+        assert!(err.contains("404.md"), "{}", err);
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+}
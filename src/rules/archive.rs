@@ -0,0 +1,275 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Date-based archive pages grouping a snapshot's artifacts by year and month.
+
+use super::*;
+use chrono::Datelike;
+use std::collections::BTreeMap;
+
+/// Configuration of an [`archive`](archive) rule.
+///
+/// Artifacts are grouped by the date in their metadata key `date_key`, parsed with
+/// [`parse_date`](crate::parse_date). Artifacts without a parseable date are skipped.
+///
+/// The grouped structure is a list of `years`, newest first. Each year has `year`, `url`,
+/// `count`, `items` and a list of `months`, each with `year`, `month`, `month_name`, `url`,
+/// `count` and `items`. Items are the artifacts' metadata (see
+/// [`BuildArtifact::context`](crate::BuildArtifact::context)), newest first. `url` is `null` if
+/// the corresponding pages are disabled.
+///
+/// Year pages get the year's object as context, month pages get the month's object and the full
+/// archive page gets `years`.
+pub struct Archive {
+    /// Metadata key to read dates from.
+    pub date_key: String,
+    /// Maps `<year>` to the year page's destination, if any.
+    pub year_route: Option<Route>,
+    pub year_renderer: Renderer,
+    /// Maps `<year>/<month>` to the month page's destination, if any.
+    pub month_route: Option<Route>,
+    pub month_renderer: Renderer,
+    /// Destination of the full archive page, if any.
+    pub archive_path: Option<PathBuf>,
+    pub archive_renderer: Renderer,
+}
+
+impl Archive {
+    /// Create an archive of metadata key `date` with pages at `2019/index.html`,
+    /// `2019/06/index.html` and `archive/index.html`.
+    pub fn new(
+        year_renderer: Renderer,
+        month_renderer: Renderer,
+        archive_renderer: Renderer,
+    ) -> Self {
+        Archive {
+            date_key: "date".to_string(),
+            year_route: Some(Route::Custom(Box::new(|path| path.join("index.html")))),
+            year_renderer,
+            month_route: Some(Route::Custom(Box::new(|path| path.join("index.html")))),
+            month_renderer,
+            archive_path: Some(PathBuf::from("archive/index.html")),
+            archive_renderer,
+        }
+    }
+}
+
+/// Group the artifacts of `query` by year and month and create archive pages.
+pub fn archive<Q: Into<SnapshotQuery>>(query: Q, archive: Archive) -> Rule {
+    let query = query.into();
+    Box::new(move |state: &mut State| {
+        // year -> month -> [(date, member)]
+        let mut groups: BTreeMap<i32, BTreeMap<u32, Vec<_>>> = BTreeMap::new();
+        for uuid in query.uuids(state)?.iter() {
+            let artifact = &state.artifacts[uuid];
+            let date = match artifact
+                .metadata
+                .get(&archive.date_key)
+                .and_then(Value::as_str)
+                .and_then(parse_date)
+            {
+                Some(date) => date,
+                None => {
+                    warn!(
+                        path = %artifact.path.display(),
+                        "skipping from archive: no parseable `{}` in metadata",
+                        &archive.date_key
+                    );
+                    continue;
+                }
+            };
+            groups
+                .entry(date.year())
+                .or_default()
+                .entry(date.month())
+                .or_default()
+                .push((date, *uuid));
+        }
+
+        let url = |route: &Option<Route>, path: &Path| -> Value {
+            route
+                .as_ref()
+                .map(|route| path_to_url(&route.apply(path)).into())
+                .unwrap_or(Value::Null)
+        };
+        let mut years = Vec::with_capacity(groups.len());
+        for (year, months) in groups.into_iter().rev() {
+            let year_base = PathBuf::from(format!("{:04}", year));
+            let mut year_items = vec![];
+            let mut year_months = Vec::with_capacity(months.len());
+            for (month, mut members) in months.into_iter().rev() {
+                let month_base = year_base.join(format!("{:02}", month));
+                members.sort_by_key(|(date, _)| std::cmp::Reverse(*date));
+                let items = members
+                    .iter()
+                    .map(|(_, uuid)| Value::Object(state.artifacts[uuid].context()))
+                    .collect::<Vec<Value>>();
+                let month_name = chrono::NaiveDate::from_ymd_opt(year, month, 1)
+                    .map(|d| d.format("%B").to_string())
+                    .unwrap_or_default();
+                let mut month_context = Map::new();
+                month_context.insert("title".into(), format!("{} {}", month_name, year).into());
+                month_context.insert("year".into(), year.into());
+                month_context.insert("month".into(), month.into());
+                month_context.insert("month_name".into(), month_name.into());
+                month_context.insert("url".into(), url(&archive.month_route, &month_base));
+                month_context.insert("count".into(), items.len().into());
+                year_items.extend(items.iter().cloned());
+                month_context.insert("items".into(), Value::Array(items));
+                if let Some(ref route) = archive.month_route {
+                    let dest = route.apply(&month_base);
+                    state.add_page(
+                        dest.clone(),
+                        dest,
+                        &const_compiler(month_context.clone()),
+                        archive.month_renderer.clone(),
+                    )?;
+                }
+                year_months.push(Value::Object(month_context));
+            }
+
+            let mut year_context = Map::new();
+            year_context.insert("title".into(), year.to_string().into());
+            year_context.insert("year".into(), year.into());
+            year_context.insert("url".into(), url(&archive.year_route, &year_base));
+            year_context.insert("count".into(), year_items.len().into());
+            year_context.insert("items".into(), Value::Array(year_items));
+            year_context.insert("months".into(), Value::Array(year_months));
+            if let Some(ref route) = archive.year_route {
+                let dest = route.apply(&year_base);
+                state.add_page(
+                    dest.clone(),
+                    dest,
+                    &const_compiler(year_context.clone()),
+                    archive.year_renderer.clone(),
+                )?;
+            }
+            years.push(Value::Object(year_context));
+        }
+
+        if let Some(ref archive_path) = archive.archive_path {
+            let mut metadata = Map::new();
+            metadata.insert("title".into(), "Archive".into());
+            metadata.insert("years".into(), Value::Array(years));
+            state.add_page(
+                archive_path.clone(),
+                archive_path.clone(),
+                &const_compiler(metadata),
+                archive.archive_renderer,
+            )?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_archive_state(name: &str) -> State {
+        let mut state = crate::tests::test_state(
+            name,
+            &[
+                (
+                    "year.hbs",
+                    "{{year}}:{{count}}:{{#each months}}{{month_name}}={{url}},{{/each}}|{{#each items}}{{title}},{{/each}}",
+                ),
+                ("month.hbs", "{{title}}:{{#each items}}{{title}},{{/each}}"),
+                (
+                    "archive.hbs",
+                    "{{#each years}}{{year}} {{url}}[{{#each months}}{{month}} {{url}};{{/each}}]{{/each}}",
+                ),
+            ],
+        );
+        let posts = &[
+            ("a", "2019-06-15"),
+            ("b", "2019-06-20"),
+            ("c", "2019-01-02"),
+            ("d", "2020-02-09"),
+            ("e", "someday"),
+        ];
+        for (title, date) in posts {
+            let mut metadata = Map::new();
+            metadata.insert("title".into(), title.to_string().into());
+            metadata.insert("body".into(), title.to_string().into());
+            metadata.insert("date".into(), date.to_string().into());
+            let path = PathBuf::from(format!("{}.html", title));
+            let uuid = state
+                .add_page(
+                    path.clone(),
+                    path,
+                    &const_compiler(metadata),
+                    Renderer::body(),
+                )
+                .unwrap();
+            state.add_to_snapshot("posts".into(), uuid);
+        }
+        state
+    }
+
+    fn test_archive_options() -> Archive {
+        Archive::new(
+            Renderer::LoadAndApplyTemplate("templates/year.hbs"),
+            Renderer::LoadAndApplyTemplate("templates/month.hbs"),
+            Renderer::LoadAndApplyTemplate("templates/archive.hbs"),
+        )
+    }
+
+    #[test]
+    fn test_archive() {
+        let mut state = test_archive_state("archive");
+        state.then(archive("posts", test_archive_options()));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        let contents = |dest: &str| {
+            state.artifacts[&uuid_from_path(Path::new(dest))]
+                .contents
+                .clone()
+        };
+        // Years, months and items are newest first and undated artifacts are skipped.
+        assert_eq!(
+            contents("2019/index.html"),
+            "2019:3:June=/2019/06/,January=/2019/01/,|b,a,c,"
+        );
+        assert_eq!(contents("2019/06/index.html"), "June 2019:b,a,");
+        assert_eq!(contents("2019/01/index.html"), "January 2019:c,");
+        assert_eq!(contents("2020/index.html"), "2020:1:February=/2020/02/,|d,");
+        assert_eq!(
+            contents("archive/index.html"),
+            "2020 /2020/[2 /2020/02/;]2019 /2019/[6 /2019/06/;1 /2019/01/;]"
+        );
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+
+        // Disabled pages are not created and have no URL.
+        let mut state = test_archive_state("archive-no-months");
+        let mut options = test_archive_options();
+        options.month_route = None;
+        state.then(archive("posts", options));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        assert!(!state
+            .routes
+            .contains_key(&state.output_dir().join("2019/06/index.html")));
+        assert_eq!(
+            state.artifacts[&uuid_from_path(Path::new("archive/index.html"))].contents,
+            "2020 /2020/[2 ;]2019 /2019/[6 ;1 ;]"
+        );
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+}
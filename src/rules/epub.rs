@@ -0,0 +1,717 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! EPUB 3 export of a snapshot's rendered artifacts.
+
+use super::*;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Default stylesheet of [`Epub`](Epub).
+pub const EPUB_CSS: &str = "body { margin: 0 5%; line-height: 1.5; text-align: justify; }
+h1, h2, h3, h4, h5, h6 { text-align: left; line-height: 1.2; }
+img { max-width: 100%; }
+pre { white-space: pre-wrap; font-size: 0.9em; }
+blockquote { margin: 1em 2em; font-style: italic; }
+div.cover { text-align: center; }
+div.cover img { max-height: 95vh; }
+";
+
+/// Configuration of an [`epub`](epub) rule.
+///
+/// Each artifact of the query becomes a chapter, in the query's order, titled with its `title`
+/// metadata. The chapter text is the artifact's rendered
+/// [`contents`](crate::BuildArtifact::contents), or if `content_snapshot` is set its
+/// [snapshot](crate::Renderer::Snapshot) of that name; if it is a whole HTML document only its
+/// `<body>` is used. Images referenced by `<img>` elements are copied into the book and links
+/// between chapters are rewritten to point inside the book.
+pub struct Epub {
+    /// Destination of the `.epub` file.
+    pub path: PathBuf,
+    pub title: String,
+    /// Language tag of the book, eg `en`.
+    pub language: String,
+    pub authors: Vec<String>,
+    /// Unique identifier of the book. By default a `urn:uuid:` derived from the title.
+    pub identifier: Option<String>,
+    pub publisher: Option<String>,
+    pub description: Option<String>,
+    /// Cover image resource.
+    pub cover: Option<PathBuf>,
+    /// Stylesheet of every chapter.
+    pub css: String,
+    /// Use this named snapshot of each artifact's rendered contents instead of its final
+    /// contents, eg the post without the site's layout.
+    pub content_snapshot: Option<String>,
+}
+
+impl Epub {
+    /// Create a configuration for an English book with the default stylesheet and no cover.
+    pub fn new<S: Into<String>>(path: PathBuf, title: S) -> Self {
+        Epub {
+            path,
+            title: title.into(),
+            language: "en".to_string(),
+            authors: vec![],
+            identifier: None,
+            publisher: None,
+            description: None,
+            cover: None,
+            css: EPUB_CSS.to_string(),
+            content_snapshot: None,
+        }
+    }
+}
+
+/// A chapter of the book.
+struct Chapter {
+    file: String,
+    title: String,
+    body: String,
+}
+
+/// A file referenced from the package document.
+struct Resource {
+    id: String,
+    file: String,
+    bytes: Vec<u8>,
+}
+
+fn media_type(file: &str) -> Result<&'static str> {
+    let extension = Path::new(file)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    Ok(match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        _ => Err(format!("Unsupported EPUB image type: {}", file))?,
+    })
+}
+
+/// Resolve `url`, found in the page at destination `page`, to a destination path. Returns `None`
+/// for external URLs.
+fn resolve_url(page: &Path, url: &str) -> Option<PathBuf> {
+    if url.is_empty() || url.starts_with('#') || url.starts_with("//") || url.contains(':') {
+        return None;
+    }
+    let url = url.split(['#', '?']).next().unwrap_or_default();
+    let joined = match url.strip_prefix('/') {
+        Some(absolute) => PathBuf::from(absolute),
+        None => page.parent().unwrap_or_else(|| Path::new("")).join(url),
+    };
+    let mut ret = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                ret.pop();
+            }
+            std::path::Component::Normal(c) => ret.push(c),
+            _ => {}
+        }
+    }
+    if url.ends_with('/') {
+        ret.push("index.html");
+    }
+    Some(ret)
+}
+
+/// Returns the `<body>` of `html` as XHTML: tag and attribute names are lowercased, attribute
+/// values are quoted, void elements are closed and HTML entities that XML doesn't define are
+/// replaced with numeric character references.
+fn to_xhtml(html: &str) -> String {
+    use std::fmt::Write;
+    use std::sync::OnceLock;
+    const VOID: &[&str] = &[
+        "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source",
+        "track", "wbr",
+    ];
+    static BODY: OnceLock<regex::Regex> = OnceLock::new();
+    static TAG: OnceLock<regex::Regex> = OnceLock::new();
+    static ATTRIBUTE: OnceLock<regex::Regex> = OnceLock::new();
+    static ENTITY: OnceLock<regex::Regex> = OnceLock::new();
+    static NAMED: OnceLock<HashMap<&'static str, &'static entities::Codepoints>> = OnceLock::new();
+    let body = BODY
+        .get_or_init(|| regex::Regex::new(r"(?is)<body\b[^>]*>(.*)</body\s*>").unwrap())
+        .captures(html)
+        .map(|c| c.get(1).unwrap().as_str())
+        .unwrap_or(html);
+    let attribute = ATTRIBUTE.get_or_init(|| {
+        regex::Regex::new(r#"([^\s"'>/=]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'<>`]+)))?"#)
+            .unwrap()
+    });
+    let body = TAG
+        .get_or_init(|| {
+            regex::Regex::new(r#"<(/?)([A-Za-z][A-Za-z0-9-]*)((?:[^>"']|"[^"]*"|'[^']*')*)>"#)
+                .unwrap()
+        })
+        .replace_all(body, |tag: &regex::Captures| {
+            let name = tag[2].to_ascii_lowercase();
+            if !tag[1].is_empty() {
+                return format!("</{}>", name);
+            }
+            let mut ret = format!("<{}", name);
+            let mut names = vec![];
+            for a in attribute.captures_iter(&tag[3]) {
+                let attribute_name = a[1].to_ascii_lowercase();
+                // HTML keeps the first of duplicate attributes, XML rejects them.
+                if names.contains(&attribute_name) {
+                    continue;
+                }
+                // A boolean attribute's value is its name, eg `disabled="disabled"`.
+                let value = (2..=4)
+                    .find_map(|i| a.get(i))
+                    .map(|v| v.as_str())
+                    .unwrap_or(&attribute_name);
+                let _ = write!(
+                    ret,
+                    r#" {}="{}""#,
+                    attribute_name,
+                    value.replace('"', "&quot;").replace('<', "&lt;")
+                );
+                names.push(attribute_name);
+            }
+            ret.push_str(if VOID.contains(&name.as_str()) {
+                "/>"
+            } else {
+                ">"
+            });
+            ret
+        });
+    let named = NAMED.get_or_init(|| {
+        entities::ENTITIES
+            .iter()
+            .map(|e| (e.entity, &e.codepoints))
+            .collect()
+    });
+    ENTITY
+        .get_or_init(|| {
+            regex::Regex::new(r"&(?:#[0-9]+;|#[xX][0-9a-fA-F]+;|([A-Za-z][A-Za-z0-9]*;?))?")
+                .unwrap()
+        })
+        .replace_all(body.trim(), |entity: &regex::Captures| {
+            let name = match entity.get(1) {
+                Some(name) => name.as_str(),
+                None if entity[0].len() > 1 => return entity[0].to_string(),
+                None => return "&amp;".to_string(),
+            };
+            if ["amp;", "lt;", "gt;", "quot;", "apos;"].contains(&name) {
+                return entity[0].to_string();
+            }
+            match named.get(&entity[0]) {
+                Some(entities::Codepoints::Single(c)) => format!("&#{};", c),
+                Some(entities::Codepoints::Double(c, d)) => format!("&#{};&#{};", c, d),
+                None => format!("&amp;{}", name),
+            }
+        })
+        .into_owned()
+}
+
+fn xhtml_document(language: &str, title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="{lang}" lang="{lang}">
+<head>
+<meta charset="UTF-8"/>
+<title>{title}</title>
+<link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+{body}
+</body>
+</html>
+"#,
+        lang = escape_xml(language),
+        title = escape_xml(title),
+        body = body
+    )
+}
+
+impl Epub {
+    /// Returns the book's identifier.
+    fn identifier(&self) -> String {
+        self.identifier.clone().unwrap_or_else(|| {
+            format!(
+                "urn:uuid:{}",
+                Uuid::new_v3(&Uuid::NAMESPACE_OID, self.title.as_bytes())
+            )
+        })
+    }
+
+    /// Returns the package document.
+    fn opf(
+        &self,
+        chapters: &[Chapter],
+        images: &[Resource],
+        cover: Option<&Resource>,
+        modified: &str,
+    ) -> Result<String> {
+        let mut metadata = format!(
+            "    <dc:identifier id=\"book-id\">{}</dc:identifier>\n    <dc:title>{}</dc:title>\n    <dc:language>{}</dc:language>\n",
+            escape_xml(&self.identifier()),
+            escape_xml(&self.title),
+            escape_xml(&self.language)
+        );
+        for author in self.authors.iter() {
+            metadata.push_str(&format!(
+                "    <dc:creator>{}</dc:creator>\n",
+                escape_xml(author)
+            ));
+        }
+        if let Some(ref publisher) = self.publisher {
+            metadata.push_str(&format!(
+                "    <dc:publisher>{}</dc:publisher>\n",
+                escape_xml(publisher)
+            ));
+        }
+        if let Some(ref description) = self.description {
+            metadata.push_str(&format!(
+                "    <dc:description>{}</dc:description>\n",
+                escape_xml(description)
+            ));
+        }
+        metadata.push_str(&format!(
+            "    <meta property=\"dcterms:modified\">{}</meta>\n",
+            modified
+        ));
+
+        let mut manifest = String::from(
+            "    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n    <item id=\"css\" href=\"style.css\" media-type=\"text/css\"/>\n",
+        );
+        let mut spine = String::new();
+        if let Some(cover) = cover {
+            metadata.push_str("    <meta name=\"cover\" content=\"cover-image\"/>\n");
+            manifest.push_str(&format!(
+                "    <item id=\"cover-image\" href=\"{}\" media-type=\"{}\" properties=\"cover-image\"/>\n    <item id=\"cover\" href=\"cover.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+                escape_xml(&cover.file),
+                media_type(&cover.file)?
+            ));
+            spine.push_str("    <itemref idref=\"cover\"/>\n");
+        }
+        for (i, chapter) in chapters.iter().enumerate() {
+            manifest.push_str(&format!(
+                "    <item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+                i + 1,
+                chapter.file
+            ));
+            spine.push_str(&format!("    <itemref idref=\"chapter-{}\"/>\n", i + 1));
+        }
+        for image in images.iter() {
+            manifest.push_str(&format!(
+                "    <item id=\"{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                image.id,
+                escape_xml(&image.file),
+                media_type(&image.file)?
+            ));
+        }
+        Ok(format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" xml:lang="{}">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+{}  </metadata>
+  <manifest>
+{}  </manifest>
+  <spine>
+{}  </spine>
+</package>
+"#,
+            escape_xml(&self.language),
+            metadata,
+            manifest,
+            spine
+        ))
+    }
+
+    /// Returns the navigation document.
+    fn nav(&self, chapters: &[Chapter]) -> String {
+        let mut toc = String::new();
+        for chapter in chapters.iter() {
+            toc.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                chapter.file,
+                escape_xml(&chapter.title)
+            ));
+        }
+        xhtml_document(
+            &self.language,
+            &self.title,
+            &format!(
+                "<nav epub:type=\"toc\" id=\"toc\">\n<h1>{}</h1>\n<ol>\n{}</ol>\n</nav>",
+                escape_xml(&self.title),
+                toc
+            ),
+        )
+    }
+
+    /// Returns the EPUB archive.
+    fn package(
+        &self,
+        chapters: &[Chapter],
+        images: &[Resource],
+        cover: Option<&Resource>,
+        modified: &str,
+    ) -> Result<Vec<u8>> {
+        use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+        let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        // The `mimetype` file must come first and be stored uncompressed.
+        zip.start_file(
+            "mimetype",
+            SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(b"application/epub+zip")?;
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        let mut add = |name: &str, bytes: &[u8]| -> Result<()> {
+            zip.start_file(name, options)?;
+            zip.write_all(bytes)?;
+            Ok(())
+        };
+        add(
+            "META-INF/container.xml",
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#,
+        )?;
+        add(
+            "OEBPS/content.opf",
+            self.opf(chapters, images, cover, modified)?.as_bytes(),
+        )?;
+        add("OEBPS/nav.xhtml", self.nav(chapters).as_bytes())?;
+        add("OEBPS/style.css", self.css.as_bytes())?;
+        if let Some(cover) = cover {
+            add(&format!("OEBPS/{}", cover.file), &cover.bytes)?;
+            add(
+                "OEBPS/cover.xhtml",
+                xhtml_document(
+                    &self.language,
+                    &self.title,
+                    &format!(
+                        "<div class=\"cover\"><img src=\"{}\" alt=\"{}\"/></div>",
+                        escape_xml(&cover.file),
+                        escape_xml(&self.title)
+                    ),
+                )
+                .as_bytes(),
+            )?;
+        }
+        for chapter in chapters.iter() {
+            add(
+                &format!("OEBPS/{}", chapter.file),
+                xhtml_document(&self.language, &chapter.title, &chapter.body).as_bytes(),
+            )?;
+        }
+        for image in images.iter() {
+            add(&format!("OEBPS/{}", image.file), &image.bytes)?;
+        }
+        Ok(zip.finish()?.into_inner())
+    }
+}
+
+/// Returns the contents of the file generated at destination `dest`: the bytes or rendered
+/// contents it is about to be written with, the resource it is copied from, the output file if it
+/// is cached or, failing that, the file `dest` in the current directory.
+fn read_destination(state: &State, dest: &Path) -> Result<Vec<u8>> {
+    let output_path = state.output_dir().join(dest);
+    let source = match (
+        state.routes.get(&output_path),
+        state.build_actions.get(&output_path),
+    ) {
+        (
+            _,
+            Some(BuildAction {
+                bytes: Some(bytes), ..
+            }),
+        ) => return Ok(bytes.clone()),
+        (
+            Some(uuid),
+            Some(BuildAction {
+                to: Renderer::None, ..
+            }),
+        ) => state.current_dir().join(&state.artifacts[uuid].resource),
+        (Some(uuid), Some(_)) => return Ok(state.artifacts[uuid].contents.clone().into_bytes()),
+        (Some(uuid), None) if !output_path.exists() => {
+            state.current_dir().join(&state.artifacts[uuid].resource)
+        }
+        _ if output_path.exists() => output_path,
+        _ => state.current_dir().join(dest),
+    };
+    fs::read(&source).map_err(|err| format!("Could not read {}: {}", source.display(), err).into())
+}
+
+/// Package the artifacts of `query` into an EPUB 3 book, see [`Epub`](Epub).
+pub fn epub<Q: Into<SnapshotQuery>>(query: Q, epub: Epub) -> Rule {
+    let query = query.into();
+    Box::new(move |state: &mut State| {
+        use std::sync::OnceLock;
+        static IMG: OnceLock<regex::Regex> = OnceLock::new();
+        static HREF: OnceLock<regex::Regex> = OnceLock::new();
+        let img = IMG.get_or_init(|| {
+            regex::Regex::new(r#"(?i)(<img\b[^>]*?\bsrc\s*=\s*)(?:"([^"]*)"|'([^']*)')"#).unwrap()
+        });
+        let href = HREF.get_or_init(|| {
+            regex::Regex::new(r#"(?i)(<a\b[^>]*?\bhref\s*=\s*)(?:"([^"]*)"|'([^']*)')"#).unwrap()
+        });
+
+        let artifacts = query.run(state)?;
+        if artifacts.is_empty() {
+            Err(format!(
+                "EPUB {} has no chapters: {:?} is empty",
+                epub.path.display(),
+                query
+            ))?;
+        }
+        let files = artifacts
+            .iter()
+            .enumerate()
+            .map(|(i, a)| (path_to_url(&a.path), format!("chapter-{:03}.xhtml", i + 1)))
+            .collect::<HashMap<String, String>>();
+
+        let mut chapters = Vec::with_capacity(artifacts.len());
+        let mut images: Vec<Resource> = vec![];
+        let mut image_files: HashMap<PathBuf, String> = HashMap::new();
+        let mut modified = None;
+        for artifact in artifacts.iter() {
+            let html = match epub.content_snapshot {
+                Some(ref name) => artifact.snapshots.get(name),
+                None => Some(&artifact.contents),
+            }
+            .filter(|html| !html.is_empty())
+            .ok_or_else(|| {
+                format!(
+                    "{} has no rendered contents for EPUB {}",
+                    artifact.resource.display(),
+                    epub.path.display()
+                )
+            })?;
+
+            let mut err = None;
+            let body = img.replace_all(html, |c: &regex::Captures| {
+                let url = c.get(2).or_else(|| c.get(3)).unwrap().as_str();
+                let dest = match resolve_url(&artifact.path, url) {
+                    Some(dest) => dest,
+                    None => return c[0].to_string(),
+                };
+                if !image_files.contains_key(&dest) {
+                    let file = format!("images/{}", dest.display());
+                    match media_type(&file)
+                        .and_then(|_| read_destination(state, &dest))
+                        .map(|bytes| Resource {
+                            id: format!("image-{}", images.len() + 1),
+                            file: file.clone(),
+                            bytes,
+                        }) {
+                        Ok(image) => images.push(image),
+                        Err(e) => {
+                            err.get_or_insert(e);
+                            return c[0].to_string();
+                        }
+                    }
+                    image_files.insert(dest.clone(), file);
+                }
+                format!("{}\"{}\"", &c[1], escape_xml(&image_files[&dest]))
+            });
+            if let Some(err) = err {
+                return Err(err);
+            }
+            let body = href.replace_all(&body, |c: &regex::Captures| {
+                let url = c.get(2).or_else(|| c.get(3)).unwrap().as_str();
+                let chapter = resolve_url(&artifact.path, url)
+                    .and_then(|dest| files.get(&path_to_url(&dest)));
+                match chapter {
+                    Some(file) => {
+                        let fragment = url.find('#').map(|i| &url[i..]).unwrap_or_default();
+                        format!("{}\"{}{}\"", &c[1], file, fragment)
+                    }
+                    None => c[0].to_string(),
+                }
+            });
+
+            let date = ["updated", "date"]
+                .iter()
+                .filter_map(|key| artifact.metadata.get(*key).and_then(Value::as_str))
+                .find_map(parse_date)
+                .map(|d| d.with_timezone(&chrono::Utc))
+                .or_else(|| {
+                    fs::metadata(state.current_dir().join(&artifact.resource))
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .map(chrono::DateTime::<chrono::Utc>::from)
+                });
+            modified = modified.max(date);
+
+            chapters.push(Chapter {
+                file: files[&path_to_url(&artifact.path)].clone(),
+                title: artifact
+                    .metadata
+                    .get("title")
+                    .and_then(Value::as_str)
+                    .map(String::from)
+                    .unwrap_or_else(|| artifact.path.display().to_string()),
+                body: to_xhtml(&body),
+            });
+        }
+
+        let cover = match epub.cover {
+            Some(ref cover) => {
+                let file = format!(
+                    "cover.{}",
+                    cover
+                        .extension()
+                        .map(|e| e.to_string_lossy().to_lowercase())
+                        .unwrap_or_default()
+                );
+                media_type(&file)?;
+                Some(Resource {
+                    id: "cover-image".to_string(),
+                    file,
+                    bytes: fs::read(state.current_dir().join(cover)).map_err(|err| {
+                        format!("Could not read cover {}: {}", cover.display(), err)
+                    })?,
+                })
+            }
+            None => None,
+        };
+        let modified = modified
+            .unwrap_or_default()
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string();
+        let bytes = epub.package(&chapters, &images, cover.as_ref(), &modified)?;
+        state.add_bytes(epub.path.clone(), epub.path.clone(), Map::new(), bytes)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_epub() {
+        assert_eq!(
+            resolve_url(Path::new("posts/a.html"), "../images/b.png#x"),
+            Some(PathBuf::from("images/b.png"))
+        );
+        assert_eq!(
+            resolve_url(Path::new("posts/a.html"), "/posts/"),
+            Some(PathBuf::from("posts/index.html"))
+        );
+        assert_eq!(resolve_url(Path::new("a.html"), "https://x.org/"), None);
+
+        let body = to_xhtml(
+            "<html><head><title>x</title></head><body class=\"a\"><p>a<br>b&nbsp;c</p><img src=\"x.png\" alt=\"\"></body></html>",
+        );
+        assert_eq!(body, "<p>a<br/>b&#160;c</p><img src=\"x.png\" alt=\"\"/>");
+        assert_eq!(
+            to_xhtml(
+                "&eacute;&hellip;&NotEqualTilde; &copy 2020 &#8212;&#x2014;&amp;&lt;&bogus; & ;"
+            ),
+            "&#233;&#8230;&#8770;&#824; &#169; 2020 &#8212;&#x2014;&amp;&lt;&amp;bogus; &amp; ;"
+        );
+        assert_eq!(
+            to_xhtml(
+                r#"<INPUT Type=checkbox disabled checked=''><A HREF=/a/b?x=1&y=2 title='say "hi"' title=x>a</A><br/>"#
+            ),
+            r#"<input type="checkbox" disabled="disabled" checked=""/><a href="/a/b?x=1&amp;y=2" title="say &quot;hi&quot;">a</a><br/>"#
+        );
+
+        let mut config = Epub::new(PathBuf::from("book.epub"), "A & B");
+        config.authors.push("epilys".into());
+        let chapters = [Chapter {
+            file: "chapter-001.xhtml".into(),
+            title: "One".into(),
+            body,
+        }];
+        let images = [Resource {
+            id: "image-1".into(),
+            file: "images/x.png".into(),
+            bytes: vec![0; 4],
+        }];
+        let bytes = config
+            .package(&chapters, &images, None, "2020-01-01T00:00:00Z")
+            .unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+        for name in &[
+            "META-INF/container.xml",
+            "OEBPS/content.opf",
+            "OEBPS/nav.xhtml",
+            "OEBPS/chapter-001.xhtml",
+        ] {
+            let mut text = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            let options = roxmltree::ParsingOptions {
+                allow_dtd: true,
+                ..Default::default()
+            };
+            roxmltree::Document::parse_with_options(&text, options).unwrap();
+        }
+        assert!(archive.by_name("OEBPS/images/x.png").is_ok());
+    }
+
+    #[test]
+    fn test_read_destination() {
+        let mut state = crate::tests::test_state("epub", &[("page.hbs", "<p>{{title}}</p>")]);
+        let root = state.output_dir().parent().unwrap().to_path_buf();
+        let source = root
+            .strip_prefix(state.current_dir())
+            .unwrap()
+            .join("style.scss");
+        fs::write(&source, "a { b: c }").unwrap();
+        let mut metadata = Map::new();
+        metadata.insert("title".into(), "Page".into());
+        state
+            .add_page(
+                "page.html".into(),
+                "page.md".into(),
+                &const_compiler(metadata),
+                Renderer::LoadAndApplyTemplate("templates/page.hbs"),
+            )
+            .unwrap();
+        state
+            .add_bytes(
+                "style.css".into(),
+                source.clone(),
+                Map::new(),
+                b"a{b:c}".to_vec(),
+            )
+            .unwrap();
+        state.copy_page(source, "style.scss".into()).unwrap();
+
+        let read = |dest: &str| read_destination(&state, Path::new(dest)).unwrap();
+        assert_eq!(read("page.html"), b"<p>Page</p>");
+        assert_eq!(read("style.css"), b"a{b:c}");
+        assert_eq!(read("style.scss"), b"a { b: c }");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
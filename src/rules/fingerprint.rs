@@ -0,0 +1,182 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Asset fingerprinting for cache-busting URLs.
+
+use super::*;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Configuration of a [`fingerprint`](fingerprint) rule.
+///
+/// Matching files are copied to their route with the hash of their contents inserted before the
+/// extension, eg `css/main.css` becomes `css/main.1a2b3c4d.css`. Templates refer to them through
+/// the `asset_url` helper with their route without the hash: `{{ asset_url "css/main.css" }}`
+/// renders `/css/main.1a2b3c4d.css`. Rules that use `asset_url` must therefore run after the
+/// `fingerprint` rule.
+///
+/// When an asset's hash differs from the one in the previous build's manifest, every page
+/// rendered after the rule is regenerated.
+pub struct Fingerprint {
+    /// Number of hex digits of the contents' SHA-256 hash in file names.
+    pub hash_length: usize,
+    /// Destination of the manifest, a JSON object of every asset's route and URL.
+    pub manifest: Option<PathBuf>,
+}
+
+impl Fingerprint {
+    /// Use 8 digits of the hash and write the manifest to `assets.json`.
+    pub fn new() -> Self {
+        Fingerprint {
+            hash_length: 8,
+            manifest: Some(PathBuf::from("assets.json")),
+        }
+    }
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns `path` with `hash` inserted before its extension: `css/main.css` becomes
+/// `css/main.<hash>.css`.
+pub fn fingerprinted_path(path: &Path, hash: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, hash, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, hash),
+    };
+    path.with_file_name(name)
+}
+
+/// Copy everything that matches `pattern` to fingerprinted destinations according to `route`,
+/// see [`Fingerprint`](Fingerprint).
+pub fn fingerprint<P: Into<MatchPattern>>(
+    pattern: P,
+    route: Route,
+    fingerprint: Fingerprint,
+) -> Rule {
+    let patterns = pattern.into();
+    Box::new(move |state: &mut State| {
+        let previous: Option<BTreeMap<String, String>> = match fingerprint.manifest {
+            Some(ref manifest) => {
+                let (output_path, _) = state.output_path(manifest, manifest)?;
+                fs::read(output_path)
+                    .ok()
+                    .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            }
+            None => None,
+        };
+
+        for pattern in patterns {
+            for entry in pattern.list() {
+                let rel_path = entry
+                    .path()
+                    .strip_prefix(state.current_dir())?
+                    .to_path_buf();
+                let dest_path = route.apply(&rel_path);
+                let digest = format!("{:x}", Sha256::digest(fs::read(&rel_path)?));
+                let hash = &digest[..fingerprint.hash_length.min(digest.len())];
+                let fingerprinted = fingerprinted_path(&dest_path, hash);
+
+                let key = dest_path.display().to_string();
+                let url = path_to_url(&fingerprinted);
+                let changed = match previous {
+                    Some(ref previous) => previous.get(&key) != Some(&url),
+                    None => !state.output_path(&fingerprinted, &rel_path)?.0.exists(),
+                };
+                if changed {
+                    info!(asset = %key, %url, "asset changed");
+                    state.assets_changed = true;
+                }
+                state.copy_page(rel_path, fingerprinted)?;
+                state.add_asset(key, url);
+            }
+        }
+
+        if let Some(ref manifest) = fingerprint.manifest {
+            let mut metadata = Map::new();
+            metadata.insert(
+                "body".into(),
+                serde_json::to_string_pretty(&*state.assets())?.into(),
+            );
+            state.add_page(
+                manifest.clone(),
+                manifest.clone(),
+                &const_compiler(metadata),
+                Renderer::body(),
+            )?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprinted_path() {
+        assert_eq!(
+            fingerprinted_path(Path::new("css/main.css"), "1a2b"),
+            PathBuf::from("css/main.1a2b.css")
+        );
+        assert_eq!(
+            fingerprinted_path(Path::new("js/app.min.js"), "1a2b"),
+            PathBuf::from("js/app.min.1a2b.js")
+        );
+        assert_eq!(
+            fingerprinted_path(Path::new("LICENSE"), "1a2b"),
+            PathBuf::from("LICENSE.1a2b")
+        );
+    }
+
+    #[test]
+    fn test_asset_url() {
+        let mut state = crate::tests::test_state(
+            "asset-url",
+            &[(
+                "page.hbs",
+                "{{asset_url \"css/a.css\"}} {{asset_url \"/css/b.css\"}}",
+            )],
+        );
+        // The helper sees assets added after the templates were loaded.
+        state.add_asset("css/a.css".into(), "/css/a.1.css".into());
+        state.add_asset("css/b.css".into(), "/css/b.1.css".into());
+        state.add_asset("css/a.css".into(), "/css/a.2.css".into());
+        state.then(create(
+            "page.html".into(),
+            Renderer::LoadAndApplyTemplate("templates/page.hbs"),
+            const_compiler(Map::new()),
+        ));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        assert_eq!(
+            state.artifacts[&uuid_from_path(Path::new("page.html"))].contents,
+            "/css/a.2.css /css/b.1.css"
+        );
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+}
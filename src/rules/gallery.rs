@@ -0,0 +1,525 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Photo galleries with EXIF metadata, enabled with the `gallery` feature.
+
+use super::*;
+use exif::{In, Tag};
+
+/// Configuration of a [`gallery`](gallery) rule.
+///
+/// Every JPEG, PNG and GIF file in the album directory gets a page at `page_route` applied to
+/// its path, eg `photos/paris/louvre.html`, with its variants from `images` and thumbnails from
+/// `thumbnails` under `thumbs/`. Photos are ordered by capture date, then by file name.
+///
+/// Photo pages are rendered with a context containing:
+///
+/// - `title`: the EXIF image description or the file name without its extension
+/// - `url`, `image` and `thumbnail` (see [`ResponsiveImage`](ResponsiveImage)), `img` and
+///   `thumbnail_img`, their HTML with the title as `alt`
+/// - the EXIF fields that are present: `date` (eg `2019-06-15T10:00:00+02:00`, without the offset
+///   if unknown), `camera`, `lens`, `exposure_time` (eg `1/250`), `f_number`, `iso`,
+///   `focal_length` in millimetres and, if `gps` is set, `gps` with `latitude` and `longitude`
+/// - `position` from 1, `count`, `album_title`, `album_url`
+/// - `previous` and `next`, if any, with the `title`, `url`, `thumbnail` and `thumbnail_img` of
+///   the neighbouring photos
+///
+/// The album index at `index_path` gets `title`, `count` and `photos`, the list of photo page
+/// contexts without `previous` and `next`.
+///
+/// Variants are encoded without any metadata; GPS coordinates are only published if `gps` is set.
+pub struct Gallery {
+    /// Album title, by default the directory name.
+    pub title: Option<String>,
+    /// Variants of each photo on its page.
+    pub images: Images,
+    /// Variants of each photo's thumbnail.
+    pub thumbnails: Images,
+    /// Maps a photo's path to its page's destination.
+    pub page_route: Route,
+    pub page_renderer: Renderer,
+    /// Destination of the album index relative to the album directory, if any.
+    pub index_path: Option<PathBuf>,
+    pub index_renderer: Renderer,
+    /// Expose GPS coordinates in the metadata.
+    pub gps: bool,
+    /// Add photo pages to this snapshot.
+    pub snapshot: Option<String>,
+}
+
+impl Gallery {
+    /// Create a gallery with `.html` photo pages, an `index.html` album index and 320 pixel wide
+    /// thumbnails, with 640 pixel variants for high density screens.
+    pub fn new(page_renderer: Renderer, index_renderer: Renderer) -> Self {
+        let mut thumbnails = Images::new();
+        thumbnails.widths = vec![320, 640];
+        thumbnails.sizes = "320px".to_string();
+        Gallery {
+            title: None,
+            images: Images::new(),
+            thumbnails,
+            page_route: Route::SetExtension("html"),
+            page_renderer,
+            index_path: Some(PathBuf::from("index.html")),
+            index_renderer,
+            gps: false,
+            snapshot: None,
+        }
+    }
+}
+
+/// Read the EXIF fields described in [`Gallery`](Gallery) from image `path` into `metadata`.
+pub fn exif_metadata(path: &Path, gps: bool, metadata: &mut Map<String, Value>) -> Result<()> {
+    let mut file = std::io::BufReader::new(fs::File::open(path)?);
+    let exif = match exif::Reader::new().read_from_container(&mut file) {
+        Ok(exif) => exif,
+        Err(exif::Error::NotFound(_)) => return Ok(()),
+        Err(err) => Err(format!(
+            "Could not read EXIF of {}: {}",
+            path.display(),
+            err
+        ))?,
+    };
+    let ascii = |tag: Tag| -> Option<String> {
+        match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+            Some(exif::Value::Ascii(values)) => values
+                .first()
+                .map(|v| String::from_utf8_lossy(v).trim().to_string())
+                .filter(|v| !v.is_empty()),
+            _ => None,
+        }
+    };
+    let rational = |tag: Tag| -> Option<Vec<exif::Rational>> {
+        match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+            Some(exif::Value::Rational(values)) if !values.is_empty() => Some(values.clone()),
+            _ => None,
+        }
+    };
+
+    if let Some(description) = ascii(Tag::ImageDescription) {
+        metadata.insert("title".into(), description.into());
+    }
+    let date = ascii(Tag::DateTimeOriginal).or_else(|| ascii(Tag::DateTime));
+    if let Some(mut date) = date.and_then(|d| exif::DateTime::from_ascii(d.as_bytes()).ok()) {
+        if let Some(offset) = ascii(Tag::OffsetTimeOriginal) {
+            let _ = date.parse_offset(offset.as_bytes());
+        }
+        let mut value = format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            date.year, date.month, date.day, date.hour, date.minute, date.second
+        );
+        if let Some(offset) = date.offset {
+            value.push_str(&format!(
+                "{}{:02}:{:02}",
+                if offset < 0 { '-' } else { '+' },
+                offset.abs() / 60,
+                offset.abs() % 60
+            ));
+        }
+        metadata.insert("date".into(), value.into());
+    }
+    let camera = match (ascii(Tag::Make), ascii(Tag::Model)) {
+        // Models often repeat the make, eg `Canon` and `Canon EOS R6`.
+        (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => make.or(model),
+    };
+    if let Some(camera) = camera {
+        metadata.insert("camera".into(), camera.into());
+    }
+    if let Some(lens) = ascii(Tag::LensModel) {
+        metadata.insert("lens".into(), lens.into());
+    }
+    if let Some(exposure) = rational(Tag::ExposureTime) {
+        let exposure = exposure[0];
+        let value = if exposure.num == 0 || exposure.denom == 0 {
+            None
+        } else if exposure.num < exposure.denom {
+            Some(format!("1/{}", (exposure.to_f64().recip()).round()))
+        } else {
+            Some(format!("{}", exposure.to_f64()))
+        };
+        if let Some(value) = value {
+            metadata.insert("exposure_time".into(), value.into());
+        }
+    }
+    for (tag, key) in [
+        (Tag::FNumber, "f_number"),
+        (Tag::FocalLength, "focal_length"),
+    ] {
+        if let Some(value) = rational(tag).filter(|v| v[0].denom != 0) {
+            let value = (value[0].to_f64() * 10.0).round() / 10.0;
+            metadata.insert(key.into(), value.into());
+        }
+    }
+    if let Some(iso) = exif
+        .get_field(Tag::PhotographicSensitivity, In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+    {
+        metadata.insert("iso".into(), iso.into());
+    }
+    if gps {
+        let coordinate = |tag: Tag, reference: Tag, negative: &str| -> Option<f64> {
+            let value = rational(tag).filter(|v| v.len() == 3 && v.iter().all(|r| r.denom != 0))?;
+            let degrees = value[0].to_f64() + value[1].to_f64() / 60.0 + value[2].to_f64() / 3600.0;
+            Some(if ascii(reference).as_deref() == Some(negative) {
+                -degrees
+            } else {
+                degrees
+            })
+        };
+        if let (Some(latitude), Some(longitude)) = (
+            coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+            coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+        ) {
+            let mut gps = Map::new();
+            gps.insert("latitude".into(), latitude.into());
+            gps.insert("longitude".into(), longitude.into());
+            metadata.insert("gps".into(), gps.into());
+        }
+    }
+    Ok(())
+}
+
+/// A compiler that returns a copy of `metadata` and depends on directory `album`, whose mtime
+/// changes when photos are added or removed, so that the neighbours of photos stay current.
+fn album_compiler(album: PathBuf, metadata: Map<String, Value>) -> Compiler {
+    Box::new(move |state: &mut State, _: &Path| {
+        state.add_dependency(album.clone());
+        Ok(metadata.clone())
+    })
+}
+
+/// Create a photo page for every image in directory `dir`, relative to the current directory,
+/// and an album index, see [`Gallery`](Gallery).
+pub fn gallery<P: Into<PathBuf>>(dir: P, gallery: Gallery) -> Rule {
+    let dir = dir.into();
+    Box::new(move |state: &mut State| {
+        let mut photos = vec![];
+        for entry in fs::read_dir(&dir)
+            .map_err(|err| format!("Could not read album {}: {}", dir.display(), err))?
+        {
+            let path = entry?.path();
+            let path = path
+                .strip_prefix(state.current_dir())
+                .map(Path::to_path_buf)
+                .unwrap_or(path);
+            let extension = path
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            if path.is_file() && ["jpg", "jpeg", "png", "gif"].contains(&extension.as_str()) {
+                photos.push(path);
+            }
+        }
+
+        let mut contexts = vec![];
+        for path in photos {
+            let mut context = Map::new();
+            context.insert(
+                "title".into(),
+                path.file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default()
+                    .into(),
+            );
+            exif_metadata(&path, gallery.gps, &mut context)?;
+            contexts.push((path, context));
+        }
+        // Dates without an offset sort as if they were in UTC.
+        contexts.sort_by(|(a_path, a), (b_path, b)| {
+            let date = |m: &Map<String, Value>| {
+                m.get("date")
+                    .and_then(Value::as_str)
+                    .and_then(parse_date)
+                    .map(|d| d.timestamp())
+            };
+            match (date(a), date(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            }
+            .then_with(|| a_path.cmp(b_path))
+        });
+
+        let album_title = gallery.title.clone().unwrap_or_else(|| {
+            dir.file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+        let index_path = gallery.index_path.as_ref().map(|p| dir.join(p));
+        let count = contexts.len();
+        let mut pages = Vec::with_capacity(count);
+        for (i, (path, mut context)) in contexts.into_iter().enumerate() {
+            let alt = vec![(
+                "alt".to_string(),
+                context["title"].as_str().unwrap_or_default().to_string(),
+            )];
+            let image = add_image(state, &path, &path, &gallery.images)?;
+            let thumbnail_path = path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join("thumbs")
+                .join(path.file_name().unwrap_or_default());
+            let thumbnail = add_image(state, &path, &thumbnail_path, &gallery.thumbnails)?;
+            let dest = gallery.page_route.apply(&path);
+            context.insert("url".into(), path_to_url(&dest).into());
+            context.insert("img".into(), image.html(&alt).into());
+            context.insert("thumbnail_img".into(), thumbnail.html(&alt).into());
+            context.insert("image".into(), serde_json::to_value(&image)?);
+            context.insert("thumbnail".into(), serde_json::to_value(&thumbnail)?);
+            context.insert("position".into(), (i + 1).into());
+            context.insert("count".into(), count.into());
+            context.insert("album_title".into(), album_title.clone().into());
+            if let Some(ref index_path) = index_path {
+                context.insert("album_url".into(), path_to_url(index_path).into());
+            }
+            for (path, image) in [(&path, &image), (&thumbnail_path, &thumbnail)] {
+                let key = path.display().to_string();
+                state.add_asset(key.clone(), image.sources[0].src.clone());
+                state.add_responsive_image(key, image.clone());
+            }
+            pages.push((path, dest, context));
+        }
+
+        let neighbour = |context: &Map<String, Value>| {
+            let mut ret = Map::new();
+            for key in ["title", "url", "thumbnail", "thumbnail_img"] {
+                ret.insert(key.into(), context[key].clone());
+            }
+            Value::Object(ret)
+        };
+        let mut photos = Vec::with_capacity(count);
+        for i in 0..pages.len() {
+            let (ref path, ref dest, ref context) = pages[i];
+            photos.push(Value::Object(context.clone()));
+            let mut metadata = context.clone();
+            if i > 0 {
+                metadata.insert("previous".into(), neighbour(&pages[i - 1].2));
+            }
+            if let Some((_, _, next)) = pages.get(i + 1) {
+                metadata.insert("next".into(), neighbour(next));
+            }
+            let uuid = state.add_page(
+                dest.clone(),
+                path.clone(),
+                &album_compiler(dir.clone(), metadata),
+                gallery.page_renderer.clone(),
+            )?;
+            if let Some(ref snapshot) = gallery.snapshot {
+                state.add_to_snapshot(snapshot.clone(), uuid);
+            }
+        }
+
+        if let Some(index_path) = index_path {
+            let mut metadata = Map::new();
+            metadata.insert("title".into(), album_title.into());
+            metadata.insert("count".into(), count.into());
+            metadata.insert("photos".into(), Value::Array(photos));
+            state.add_page(
+                index_path.clone(),
+                index_path,
+                &album_compiler(dir.clone(), metadata),
+                gallery.index_renderer.clone(),
+            )?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a little endian TIFF structure with `entries` of `(tag, type, count, data)` in
+    /// IFD 0, which holds an Exif IFD pointer to `exif_entries`.
+    fn tiff(
+        entries: &[(u16, u16, u32, Vec<u8>)],
+        exif_entries: &[(u16, u16, u32, Vec<u8>)],
+    ) -> Vec<u8> {
+        fn ifd(
+            offset: u32,
+            entries: &[(u16, u16, u32, Vec<u8>)],
+            next: Option<u32>,
+        ) -> (Vec<u8>, Vec<u8>) {
+            let mut ifd = (entries.len() as u16 + next.is_some() as u16)
+                .to_le_bytes()
+                .to_vec();
+            let mut data = vec![];
+            let data_offset = offset + 2 + 12 * (entries.len() as u32 + next.is_some() as u32) + 4;
+            for (tag, kind, count, value) in entries {
+                ifd.extend_from_slice(&tag.to_le_bytes());
+                ifd.extend_from_slice(&kind.to_le_bytes());
+                ifd.extend_from_slice(&count.to_le_bytes());
+                if value.len() <= 4 {
+                    let mut inline = value.clone();
+                    inline.resize(4, 0);
+                    ifd.extend(inline);
+                } else {
+                    ifd.extend_from_slice(&(data_offset + data.len() as u32).to_le_bytes());
+                    data.extend_from_slice(value);
+                }
+            }
+            if let Some(next) = next {
+                ifd.extend_from_slice(&0x8769u16.to_le_bytes());
+                ifd.extend_from_slice(&4u16.to_le_bytes());
+                ifd.extend_from_slice(&1u32.to_le_bytes());
+                ifd.extend_from_slice(&(data_offset + data.len() as u32 + next).to_le_bytes());
+            }
+            ifd.extend_from_slice(&0u32.to_le_bytes());
+            (ifd, data)
+        }
+        let (ifd0, data0) = ifd(8, entries, Some(0));
+        let exif_offset = 8 + (ifd0.len() + data0.len()) as u32;
+        let (exif_ifd, exif_data) = ifd(exif_offset, exif_entries, None);
+        let mut ret = b"II*\0".to_vec();
+        ret.extend_from_slice(&8u32.to_le_bytes());
+        ret.extend(ifd0);
+        ret.extend(data0);
+        ret.extend(exif_ifd);
+        ret.extend(exif_data);
+        ret
+    }
+
+    fn ascii(tag: u16, s: &str) -> (u16, u16, u32, Vec<u8>) {
+        let mut value = s.as_bytes().to_vec();
+        value.push(0);
+        (tag, 2, value.len() as u32, value)
+    }
+
+    fn rational(tag: u16, values: &[(u32, u32)]) -> (u16, u16, u32, Vec<u8>) {
+        let value = values
+            .iter()
+            .flat_map(|(n, d)| IntoIterator::into_iter(n.to_le_bytes()).chain(d.to_le_bytes()))
+            .collect();
+        (tag, 5, values.len() as u32, value)
+    }
+
+    #[test]
+    fn test_exif_metadata() {
+        let tiff = tiff(
+            &[ascii(0x010F, "Canon"), ascii(0x0110, "Canon EOS R6")],
+            &[
+                rational(0x829A, &[(1, 250)]),
+                rational(0x829D, &[(28, 10)]),
+                (0x8827, 3, 1, vec![100, 0]),
+                ascii(0x9003, "2019:06:15 10:00:00"),
+                ascii(0x9011, "+02:00"),
+                rational(0x920A, &[(35, 1)]),
+            ],
+        );
+        let mut jpeg = vec![];
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_with_encoder(image::codecs::jpeg::JpegEncoder::new(&mut jpeg))
+            .unwrap();
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend(tiff);
+        jpeg.splice(2..2, app1);
+
+        let path = std::env::temp_dir().join(format!("libssg-exif-{}.jpg", std::process::id()));
+        fs::write(&path, jpeg).unwrap();
+        let mut metadata = Map::new();
+        exif_metadata(&path, false, &mut metadata).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            Value::Object(metadata),
+            serde_json::json!({
+                "date": "2019-06-15T10:00:00+02:00",
+                "camera": "Canon EOS R6",
+                "exposure_time": "1/250",
+                "f_number": 2.8,
+                "iso": 100,
+                "focal_length": 35.0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_gallery() {
+        let mut state = crate::tests::test_state(
+            "gallery",
+            &[
+                ("photo.hbs", "{{title}} {{position}}/{{count}}"),
+                ("album.hbs", "{{#each photos}}{{url}} {{/each}}"),
+            ],
+        );
+        let root = state.output_dir().parent().unwrap().to_path_buf();
+        let album = root
+            .strip_prefix(state.current_dir())
+            .unwrap()
+            .join("album");
+        fs::create_dir_all(&album).unwrap();
+        for (name, width) in [("a.jpg", 700), ("b.png", 400)] {
+            image::RgbImage::from_fn(width, 300, |x, y| image::Rgb([x as u8, y as u8, 0]))
+                .save(album.join(name))
+                .unwrap();
+        }
+        let mut gallery = Gallery::new(
+            Renderer::LoadAndApplyTemplate("templates/photo.hbs"),
+            Renderer::LoadAndApplyTemplate("templates/album.hbs"),
+        );
+        gallery.images.cache_dir = None;
+        gallery.thumbnails.cache_dir = None;
+        gallery.snapshot = Some("photos".into());
+        state.then(super::gallery(album.clone(), gallery));
+        if let Some(err) = state.err.take() {
+            panic!("{}", err);
+        }
+
+        let route = |path: &str| state.routes[&state.output_dir().join(album.join(path))];
+        let (a, b) = (route("a.html"), route("b.html"));
+        assert_eq!(state.snapshots["photos"], vec![a, b]);
+        assert_eq!(state.artifacts[&a].path, album.join("a.html"));
+        assert_eq!(state.artifacts[&a].contents, "a 1/2");
+        assert_eq!(state.artifacts[&b].contents, "b 2/2");
+
+        // Photos narrower than some of the widths also get a variant of their own width.
+        let variants = [
+            ("a-480w.jpg", "a.jpg"),
+            ("a-700w.jpg", "a.jpg"),
+            ("thumbs/a-320w.jpg", "a.jpg"),
+            ("thumbs/a-640w.jpg", "a.jpg"),
+            ("b-400w.png", "b.png"),
+            ("thumbs/b-320w.png", "b.png"),
+            ("thumbs/b-400w.png", "b.png"),
+        ];
+        let mut uuids = vec![a, b];
+        for (variant, source) in variants.iter() {
+            let uuid = route(variant);
+            assert_eq!(state.artifacts[&uuid].path, album.join(variant));
+            assert_eq!(state.artifacts[&uuid].resource, album.join(source));
+            uuids.push(uuid);
+        }
+        uuids.sort();
+        uuids.dedup();
+        assert_eq!(uuids.len(), 2 + variants.len());
+
+        let index = route("index.html");
+        assert_eq!(
+            state.artifacts[&index].contents,
+            format!("/{0}/a.html /{0}/b.html ", album.display())
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,624 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Responsive images, enabled with the `images` feature: resized variants in several formats and
+//! an `img` template helper that refers to them.
+
+use super::*;
+use image::{codecs, metadata::Orientation, DynamicImage, ImageDecoder, ImageReader};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Output formats of an [`images`](images) rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// The source's format, JPEG or PNG; GIF sources are converted to PNG.
+    Original,
+    /// Lossless WebP, since the encoder has no lossy mode: smaller than PNG sources but usually
+    /// larger than JPEG ones, so only worth it for PNG and GIF sources. Requires the `webp`
+    /// feature.
+    WebP,
+    /// AVIF. Requires the `avif` feature.
+    Avif,
+}
+
+/// Configuration of an [`images`](images) rule.
+///
+/// Every matching image is resized to each of `widths` that is not wider than itself, plus its
+/// own width if some of them are, and encoded in each of `formats`. Variants are
+/// named after the image's route and width, eg `images/photo-480w.webp`. Encoding drops all
+/// metadata, including EXIF, after applying the EXIF orientation.
+///
+/// Encoded variants are cached in `cache_dir` by the hash of the source and the encoding
+/// options, so unchanged images are not encoded again.
+///
+/// Templates refer to images by their route with the `img` helper:
+/// `{{ img "images/photo.jpg" "Alt text" sizes="(min-width: 800px) 50vw, 100vw" class="photo" }}`
+/// renders a `<picture>` with a `<source>` for each format other than the original and an `<img>`
+/// with `src`, `srcset`, `sizes`, `width`, `height` and `alt`, plus any other hash parameters
+/// as attributes. [`responsive_images`](responsive_images) rewrites `<img>` elements of rendered
+/// pages the same way.
+#[derive(Debug, Clone)]
+pub struct Images {
+    pub widths: Vec<u32>,
+    /// Formats to encode each variant in. The first one is used for `src` and the rest for
+    /// `<source>` elements, AVIF first.
+    pub formats: Vec<ImageFormat>,
+    /// JPEG and AVIF quality, from 1 to 100.
+    pub quality: u8,
+    /// Default `sizes` attribute.
+    pub sizes: String,
+    /// Directory of encoded variants, relative to the current directory.
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Images {
+    /// Widths of 480, 960 and 1920 pixels in the original format, cached in `.cache/images`.
+    pub fn new() -> Self {
+        Images {
+            widths: vec![480, 960, 1920],
+            formats: vec![ImageFormat::Original],
+            quality: 80,
+            sizes: "100vw".to_string(),
+            cache_dir: Some(PathBuf::from(".cache/images")),
+        }
+    }
+}
+
+impl Default for Images {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A variant of an image in one format.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageSource {
+    /// MIME type.
+    pub mime_type: String,
+    /// `srcset` attribute value.
+    pub srcset: String,
+    /// URL of the widest variant.
+    pub src: String,
+}
+
+/// The variants of an image, see [`Images`](Images).
+#[derive(Debug, Clone, Serialize)]
+pub struct ResponsiveImage {
+    /// Width of the widest variant.
+    pub width: u32,
+    /// Height of the widest variant.
+    pub height: u32,
+    pub sizes: String,
+    /// Sources in the order of [`Images::formats`](Images::formats).
+    pub sources: Vec<ImageSource>,
+}
+
+impl ResponsiveImage {
+    /// Returns the HTML of the image with `attributes`, eg `alt`, which may override `sizes`.
+    pub fn html(&self, attributes: &[(String, String)]) -> String {
+        let sizes = attributes
+            .iter()
+            .find(|(name, _)| name == "sizes")
+            .map(|(_, value)| value.as_str())
+            .unwrap_or(&self.sizes);
+        let fallback = &self.sources[0];
+        let mut img = format!(
+            "<img src=\"{}\" srcset=\"{}\" sizes=\"{}\" width=\"{}\" height=\"{}\"",
+            escape_xml(&fallback.src),
+            escape_xml(&fallback.srcset),
+            escape_xml(sizes),
+            self.width,
+            self.height
+        );
+        for (name, value) in attributes.iter() {
+            if !["src", "srcset", "sizes", "width", "height"].contains(&name.as_str()) {
+                img.push_str(&format!(" {}=\"{}\"", name, escape_xml(value)));
+            }
+        }
+        img.push('>');
+        if self.sources.len() == 1 {
+            return img;
+        }
+        let mut ret = String::from("<picture>");
+        let mut sources = self.sources[1..].iter().collect::<Vec<&ImageSource>>();
+        sources.sort_by_key(|s| s.mime_type != "image/avif");
+        for source in sources {
+            ret.push_str(&format!(
+                "<source type=\"{}\" srcset=\"{}\" sizes=\"{}\">",
+                source.mime_type,
+                escape_xml(&source.srcset),
+                escape_xml(sizes)
+            ));
+        }
+        ret.push_str(&img);
+        ret.push_str("</picture>");
+        ret
+    }
+}
+
+/// Returns an `img` helper that renders the images of `images`, see [`Images`](Images).
+pub fn img_helper(
+    images: Arc<RwLock<BTreeMap<String, ResponsiveImage>>>,
+) -> Box<dyn handlebars::HelperDef + Send + Sync> {
+    use handlebars::{Context, Handlebars, Helper, Output, RenderContext, RenderError};
+    Box::new(
+        move |h: &Helper,
+              _: &Handlebars,
+              _: &Context,
+              _: &mut RenderContext,
+              out: &mut dyn Output|
+              -> std::result::Result<(), RenderError> {
+            let path = h
+                .param(0)
+                .and_then(|p| p.value().as_str())
+                .ok_or(RenderError::new(
+                    "Image path as first parameter is required for img helper.",
+                ))?;
+            let images = images.read().unwrap();
+            let image = images
+                .get(path.trim_start_matches('/'))
+                .ok_or_else(|| RenderError::new(format!("Unknown image `{}`.", path)))?;
+            let mut attributes = vec![(
+                "alt".to_string(),
+                h.param(1)
+                    .and_then(|p| p.value().as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+            )];
+            let mut hash = h.hash().iter().collect::<Vec<_>>();
+            hash.sort_by_key(|(name, _)| *name);
+            for (name, value) in hash {
+                let value = match value.value() {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                attributes.push((name.to_string(), value));
+            }
+            out.write(&image.html(&attributes))?;
+            Ok(())
+        },
+    )
+}
+
+impl State {
+    /// Returns the responsive images added by [`images`](crate::rules::images) rules, by route.
+    pub fn responsive_images(&self) -> RwLockReadGuard<'_, BTreeMap<String, ResponsiveImage>> {
+        self.images.read().unwrap()
+    }
+
+    /// Add responsive image `image` at route `path`, eg `images/photo.jpg`, for the `img` template
+    /// helper.
+    pub fn add_responsive_image(&mut self, path: String, image: ResponsiveImage) {
+        self.images.write().unwrap().insert(path, image);
+    }
+}
+
+impl ImageFormat {
+    fn extension(self, source: &Path) -> Result<&'static str> {
+        let extension = source
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        Ok(match self {
+            ImageFormat::WebP => "webp",
+            ImageFormat::Avif => "avif",
+            ImageFormat::Original => match extension.as_str() {
+                "jpg" | "jpeg" => "jpg",
+                "png" | "gif" => "png",
+                _ => Err(format!("Unsupported image format: {}", source.display()))?,
+            },
+        })
+    }
+
+    fn mime_type(extension: &str) -> &'static str {
+        match extension {
+            "jpg" => "image/jpeg",
+            "png" => "image/png",
+            "webp" => "image/webp",
+            _ => "image/avif",
+        }
+    }
+
+    /// Encode `image` as `extension`.
+    fn encode(image: &DynamicImage, extension: &str, quality: u8) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        match extension {
+            "jpg" => DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(
+                codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, quality),
+            )?,
+            "png" => image.write_with_encoder(codecs::png::PngEncoder::new(&mut bytes))?,
+            #[cfg(feature = "webp")]
+            "webp" => {
+                let image = if image.color().has_alpha() {
+                    DynamicImage::ImageRgba8(image.to_rgba8())
+                } else {
+                    DynamicImage::ImageRgb8(image.to_rgb8())
+                };
+                image.write_with_encoder(codecs::webp::WebPEncoder::new_lossless(&mut bytes))?
+            }
+            #[cfg(feature = "avif")]
+            "avif" => image.write_with_encoder(
+                codecs::avif::AvifEncoder::new_with_speed_quality(&mut bytes, 6, quality),
+            )?,
+            #[cfg(not(feature = "webp"))]
+            "webp" => Err("WebP encoding requires the `webp` feature")?,
+            _ => Err("AVIF encoding requires the `avif` feature")?,
+        }
+        Ok(bytes)
+    }
+}
+
+/// Returns the name of variant `width` of destination `dest` with `extension`:
+/// `images/photo.jpg` becomes `images/photo-480w.webp`.
+pub fn image_variant_path(dest: &Path, width: u32, extension: &str) -> PathBuf {
+    let stem = dest
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    dest.with_file_name(format!("{}-{}w.{}", stem, width, extension))
+}
+
+/// Returns the height of an image of `width` x `height` scaled to `to_width`.
+fn scaled_height(width: u32, height: u32, to_width: u32) -> u32 {
+    ((u64::from(height) * u64::from(to_width) + u64::from(width) / 2) / u64::from(width.max(1)))
+        .max(1) as u32
+}
+
+/// Add the variants of image `resource` at destination `dest`, see [`Images`](Images), and
+/// return their description. The image is not added to the `img` helper.
+pub fn add_image(
+    state: &mut State,
+    resource: &Path,
+    dest: &Path,
+    images: &Images,
+) -> Result<ResponsiveImage> {
+    if images.widths.is_empty() || images.formats.is_empty() {
+        Err("Images must have at least one width and format.")?;
+    }
+    let source = fs::read(resource)?;
+    let source_hash = Sha256::digest(&source);
+
+    let mut decoder = ImageReader::new(std::io::Cursor::new(&source))
+        .with_guessed_format()?
+        .into_decoder()
+        .map_err(|err| format!("Could not decode {}: {}", resource.display(), err))?;
+    let orientation = decoder.orientation()?;
+    let (mut source_width, mut source_height) = decoder.dimensions();
+    if let Orientation::Rotate90
+    | Orientation::Rotate270
+    | Orientation::Rotate90FlipH
+    | Orientation::Rotate270FlipH = orientation
+    {
+        std::mem::swap(&mut source_width, &mut source_height);
+    }
+    let mut decoder = Some(decoder);
+    let mut decoded: Option<DynamicImage> = None;
+
+    let mut widths = images
+        .widths
+        .iter()
+        .cloned()
+        .filter(|w| *w <= source_width)
+        .collect::<Vec<u32>>();
+    if widths.len() < images.widths.len() {
+        widths.push(source_width);
+    }
+    widths.sort_unstable();
+    widths.dedup();
+
+    let mut sources = vec![];
+    for format in images.formats.iter() {
+        let extension = format.extension(resource)?;
+        let mut srcset = vec![];
+        let mut src = String::new();
+        for width in widths.iter().cloned() {
+            let height = scaled_height(source_width, source_height, width);
+            let key = format!(
+                "{:x}",
+                Sha256::new()
+                    .chain_update(source_hash)
+                    .chain_update(
+                        format!("{}x{}.{}@{}", width, height, extension, images.quality).as_bytes()
+                    )
+                    .finalize()
+            );
+            let cache_path = images
+                .cache_dir
+                .as_ref()
+                .map(|dir| dir.join(format!("{}.{}", key, extension)));
+            let bytes = match cache_path.as_ref().and_then(|p| fs::read(p).ok()) {
+                Some(bytes) => bytes,
+                None => {
+                    if decoded.is_none() {
+                        let mut image = DynamicImage::from_decoder(decoder.take().unwrap())?;
+                        image.apply_orientation(orientation);
+                        decoded = Some(image);
+                    }
+                    let image = decoded.as_ref().unwrap();
+                    let resized;
+                    let image = if width == source_width {
+                        image
+                    } else {
+                        resized = image.resize_exact(
+                            width,
+                            height,
+                            image::imageops::FilterType::CatmullRom,
+                        );
+                        &resized
+                    };
+                    info!(resource = %resource.display(), width, extension, "encoding image");
+                    let bytes = ImageFormat::encode(image, extension, images.quality)?;
+                    if let (Some(cache_path), false) = (cache_path.as_ref(), state.dry_run()) {
+                        fs::create_dir_all(cache_path.parent().unwrap())?;
+                        fs::write(cache_path, &bytes)?;
+                    }
+                    bytes
+                }
+            };
+            let variant = image_variant_path(dest, width, extension);
+            src = path_to_url(&variant);
+            srcset.push(format!("{} {}w", src, width));
+            let mut metadata = Map::new();
+            metadata.insert("width".into(), width.into());
+            metadata.insert("height".into(), height.into());
+            let (output_path, _) = state.output_path(&variant, resource)?;
+            state.add_bytes(variant, resource.to_path_buf(), metadata, bytes)?;
+            // Pages only need to be rendered again if the variant is new or different.
+            if matches!(
+                state.plan[&output_path].dirty,
+                Some(DirtyReason::Contents) | Some(DirtyReason::Missing)
+            ) {
+                state.assets_changed = true;
+            }
+        }
+        sources.push(ImageSource {
+            mime_type: ImageFormat::mime_type(extension).to_string(),
+            srcset: srcset.join(", "),
+            src,
+        });
+    }
+
+    let width = *widths.last().unwrap();
+    Ok(ResponsiveImage {
+        width,
+        height: scaled_height(source_width, source_height, width),
+        sizes: images.sizes.clone(),
+        sources,
+    })
+}
+
+/// Resize and convert every image that matches `pattern` to destinations according to `route`,
+/// see [`Images`](Images).
+pub fn images<P: Into<MatchPattern>>(pattern: P, route: Route, images: Images) -> Rule {
+    let patterns = pattern.into();
+    Box::new(move |state: &mut State| {
+        for pattern in patterns {
+            for entry in pattern.list() {
+                let rel_path = entry
+                    .path()
+                    .strip_prefix(state.current_dir())?
+                    .to_path_buf();
+                let dest_path = route.apply(&rel_path);
+                let image = add_image(state, &rel_path, &dest_path, &images)?;
+                let key = dest_path.display().to_string();
+                state.add_asset(key.clone(), image.sources[0].src.clone());
+                state.add_responsive_image(key, image);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// A renderer stage that replaces `<img>` elements of the HTML `body` whose `src` is an image of
+/// an [`images`](images) rule with the output of the `img` helper, keeping their other
+/// attributes.
+pub fn responsive_images() -> Renderer {
+    Renderer::Custom(Box::new(rewrite_images))
+}
+
+fn rewrite_images(state: &mut State, context: &mut Map<String, Value>) -> Result<String> {
+    use std::sync::OnceLock;
+    static IMG: OnceLock<regex::Regex> = OnceLock::new();
+    static ATTRIBUTE: OnceLock<regex::Regex> = OnceLock::new();
+    let img = IMG.get_or_init(|| regex::Regex::new(r"(?is)<img\b([^>]*?)\s*/?>").unwrap());
+    let attribute = ATTRIBUTE.get_or_init(|| {
+        regex::Regex::new(r#"([^\s=/]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+)))?"#).unwrap()
+    });
+    let body = context
+        .get("body")
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("metadata does not contain `body`: {:#?}", &context))?;
+    let page = state
+        .rendering
+        .map(|uuid| state.artifacts[&uuid].path.clone())
+        .unwrap_or_default();
+    let images = state.responsive_images();
+    Ok(img
+        .replace_all(body, |c: &regex::Captures| {
+            let attributes = attribute
+                .captures_iter(&c[1])
+                .map(|a| {
+                    let value = a.get(2).or_else(|| a.get(3)).or_else(|| a.get(4));
+                    (
+                        a[1].to_ascii_lowercase(),
+                        value
+                            .map(|v| v.as_str().replace("&amp;", "&"))
+                            .unwrap_or_default(),
+                    )
+                })
+                .collect::<Vec<(String, String)>>();
+            let image = attributes
+                .iter()
+                .find(|(name, _)| name == "src")
+                .and_then(|(_, src)| {
+                    let path = match src.strip_prefix('/') {
+                        Some(absolute) => PathBuf::from(absolute),
+                        None if src.contains(':') => return None,
+                        None => page.parent().unwrap_or_else(|| Path::new("")).join(src),
+                    };
+                    images.get(&path.display().to_string())
+                });
+            match image {
+                Some(image) => image.html(&attributes),
+                None => c[0].to_string(),
+            }
+        })
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_responsive_image_html() {
+        let image = ResponsiveImage {
+            width: 960,
+            height: 640,
+            sizes: "100vw".into(),
+            sources: vec![
+                ImageSource {
+                    mime_type: "image/jpeg".into(),
+                    srcset: "/a-480w.jpg 480w, /a-960w.jpg 960w".into(),
+                    src: "/a-960w.jpg".into(),
+                },
+                ImageSource {
+                    mime_type: "image/webp".into(),
+                    srcset: "/a-480w.webp 480w, /a-960w.webp 960w".into(),
+                    src: "/a-960w.webp".into(),
+                },
+            ],
+        };
+        assert_eq!(
+            image.html(&[("alt".into(), "A & B".into())]),
+            "<picture><source type=\"image/webp\" srcset=\"/a-480w.webp 480w, /a-960w.webp 960w\" sizes=\"100vw\"><img src=\"/a-960w.jpg\" srcset=\"/a-480w.jpg 480w, /a-960w.jpg 960w\" sizes=\"100vw\" width=\"960\" height=\"640\" alt=\"A &amp; B\"></picture>"
+        );
+        assert_eq!(
+            image_variant_path(Path::new("images/a.jpg"), 480, "webp"),
+            PathBuf::from("images/a-480w.webp")
+        );
+    }
+
+    /// Returns the encoded variant `dest` of a build of `state`.
+    fn variant(state: &State, dest: &str) -> DynamicImage {
+        let output_path = state.output_dir().join(dest);
+        let bytes = match state.build_actions.get(&output_path) {
+            Some(action) => action.bytes.clone().unwrap(),
+            None => fs::read(&output_path).unwrap(),
+        };
+        image::load_from_memory(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_add_image() {
+        let mut state = crate::tests::test_state("add-image", &[]);
+        let output_dir = state.output_dir().to_path_buf();
+        let root = output_dir.parent().unwrap().to_path_buf();
+        let dir = root
+            .strip_prefix(state.current_dir())
+            .unwrap()
+            .to_path_buf();
+        let source = dir.join("a.png");
+        image::RgbImage::from_fn(700, 350, |x, y| image::Rgb([x as u8, y as u8, 0]))
+            .save(&source)
+            .unwrap();
+        let options = Images {
+            widths: vec![480, 320, 960],
+            cache_dir: Some(dir.join("cache")),
+            ..Images::new()
+        };
+
+        // Variants are never wider than the source, which is kept at its own width instead.
+        let image = add_image(&mut state, &source, Path::new("a.png"), &options).unwrap();
+        assert_eq!((image.width, image.height), (700, 350));
+        assert_eq!(
+            image.sources[0].srcset,
+            "/a-320w.png 320w, /a-480w.png 480w, /a-700w.png 700w"
+        );
+        assert_eq!(image.sources[0].src, "/a-700w.png");
+        assert_eq!(variant(&state, "a-320w.png").dimensions(), (320, 160));
+        assert_eq!(variant(&state, "a-480w.png").dimensions(), (480, 240));
+        assert_eq!(variant(&state, "a-700w.png").dimensions(), (700, 350));
+        assert!(state.assets_changed);
+        assert_eq!(fs::read_dir(dir.join("cache")).unwrap().count(), 3);
+        state.finish().unwrap();
+
+        // Cached variants are reused, and unchanged outputs don't change assets.
+        for entry in fs::read_dir(dir.join("cache")).unwrap() {
+            let path = entry.unwrap().path();
+            let mut bytes = fs::read(&path).unwrap();
+            bytes.extend_from_slice(b"cached");
+            fs::write(&path, bytes).unwrap();
+        }
+        let mut state = State::with_dirs(root.join("templates"), &output_dir).unwrap();
+        add_image(&mut state, &source, Path::new("a.png"), &options).unwrap();
+        let action = &state.build_actions[&output_dir.join("a-480w.png")];
+        assert!(action.bytes.as_ref().unwrap().ends_with(b"cached"));
+        assert!(state.assets_changed);
+        let mut state = State::with_dirs(root.join("templates"), &output_dir).unwrap();
+        add_image(
+            &mut state,
+            &source,
+            Path::new("a.png"),
+            &Images {
+                cache_dir: None,
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert!(!state.assets_changed);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_add_image_orientation() {
+        let mut state = crate::tests::test_state("add-image-orientation", &[]);
+        let root = state.output_dir().parent().unwrap().to_path_buf();
+        let source = root
+            .strip_prefix(state.current_dir())
+            .unwrap()
+            .join("a.jpg");
+        let mut jpeg = vec![];
+        DynamicImage::ImageRgb8(image::RgbImage::new(40, 20))
+            .write_with_encoder(codecs::jpeg::JpegEncoder::new(&mut jpeg))
+            .unwrap();
+        // An APP1 segment with an EXIF orientation of 6, rotated 90 degrees clockwise.
+        let exif: &[u8] = &[
+            0xff, 0xe1, 0x00, 0x22, b'E', b'x', b'i', b'f', 0, 0, b'I', b'I', 0x2a, 0, 8, 0, 0, 0,
+            1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        jpeg.splice(2..2, exif.iter().cloned());
+        fs::write(&source, jpeg).unwrap();
+
+        let options = Images {
+            widths: vec![10],
+            cache_dir: None,
+            ..Images::new()
+        };
+        let image = add_image(&mut state, &source, Path::new("a.jpg"), &options).unwrap();
+        assert_eq!((image.width, image.height), (10, 20));
+        assert_eq!(variant(&state, "a-10w.jpg").dimensions(), (10, 20));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Split the artifacts of a snapshot into numbered listing pages.
+
+use super::*;
+
+/// Maps a base path and a page number to a page's destination.
+pub type PageRoute = Box<dyn Fn(&Path, usize) -> PathBuf>;
+
+/// Configuration of a [`paginate`](paginate) rule.
+///
+/// Each page is rendered with `renderer` and a context containing a `paginator` object with the
+/// following fields:
+///
+/// - `items`: the page's artifacts' metadata (see
+///   [`BuildArtifact::context`](crate::BuildArtifact::context))
+/// - `current`: the current page number, starting from `1`
+/// - `total_pages`, `total_items` and `page_size`
+/// - `prev_url` and `next_url`: links to the neighbouring pages, or `null`
+/// - `first_url` and `last_url`
+/// - `pages`: the `number` and `url` of every page
+pub struct Paginate {
+    /// Maximum number of items per page.
+    pub page_size: usize,
+    /// Metadata key to sort items by, see [`cmp_metadata`](crate::cmp_metadata). Items without
+    /// it are placed last. If `None`, query order is kept.
+    pub sort_key: Option<String>,
+    /// Reverse sort order, eg for newest first.
+    pub reverse: bool,
+    /// Maps a base path and a page number to the page's destination. By default page `n` of
+    /// base `posts` is `posts/page/n/index.html`.
+    pub route: PageRoute,
+    pub renderer: Renderer,
+}
+
+impl Paginate {
+    /// Create a pagination of `page_size` items per page with the default route.
+    pub fn new(page_size: usize, renderer: Renderer) -> Self {
+        Paginate {
+            page_size,
+            sort_key: None,
+            reverse: false,
+            route: Box::new(|base, page| {
+                base.join("page").join(page.to_string()).join("index.html")
+            }),
+            renderer,
+        }
+    }
+
+    /// Sort `members` according to `sort_key` and `reverse`.
+    fn sort(&self, state: &State, members: &mut [Uuid]) {
+        if let Some(ref key) = self.sort_key {
+            members.sort_by(|a, b| {
+                let a = state.artifacts[a].metadata.get(key);
+                let b = state.artifacts[b].metadata.get(key);
+                match (a, b) {
+                    (Some(_), Some(_)) if self.reverse => cmp_metadata(b, a),
+                    _ => cmp_metadata(a, b),
+                }
+            });
+        } else if self.reverse {
+            members.reverse();
+        }
+    }
+}
+
+/// Create the pages of `members` under `base`, adding `context` to each page's context. Returns
+/// the destination of the first page.
+pub(crate) fn paginate_members(
+    state: &mut State,
+    mut members: Vec<Uuid>,
+    paginate: &Paginate,
+    base: &Path,
+    context: &Map<String, Value>,
+) -> Result<PathBuf> {
+    if paginate.page_size == 0 {
+        Err("Pagination page size must be greater than zero.")?;
+    }
+    paginate.sort(state, &mut members);
+    let total_items = members.len();
+    let total_pages = std::cmp::max(1, total_items.div_ceil(paginate.page_size));
+    let dests = (1..=total_pages)
+        .map(|page| (paginate.route)(base, page))
+        .collect::<Vec<PathBuf>>();
+    let pages = dests
+        .iter()
+        .enumerate()
+        .map(|(i, dest)| {
+            let mut page = Map::new();
+            page.insert("number".into(), (i + 1).into());
+            page.insert("url".into(), path_to_url(dest).into());
+            Value::Object(page)
+        })
+        .collect::<Vec<Value>>();
+    let url = |i: usize| -> Value {
+        dests
+            .get(i)
+            .map(|dest| path_to_url(dest).into())
+            .unwrap_or(Value::Null)
+    };
+
+    for (i, dest) in dests.iter().enumerate() {
+        let items = members
+            .iter()
+            .skip(i * paginate.page_size)
+            .take(paginate.page_size)
+            .map(|uuid| Value::Object(state.artifacts[uuid].context()))
+            .collect::<Vec<Value>>();
+        let mut paginator = Map::new();
+        paginator.insert("items".into(), Value::Array(items));
+        paginator.insert("current".into(), (i + 1).into());
+        paginator.insert("total_pages".into(), total_pages.into());
+        paginator.insert("total_items".into(), total_items.into());
+        paginator.insert("page_size".into(), paginate.page_size.into());
+        paginator.insert(
+            "prev_url".into(),
+            i.checked_sub(1).map(url).unwrap_or(Value::Null),
+        );
+        paginator.insert("next_url".into(), url(i + 1));
+        paginator.insert("first_url".into(), url(0));
+        paginator.insert("last_url".into(), url(total_pages - 1));
+        paginator.insert("pages".into(), Value::Array(pages.clone()));
+
+        let mut metadata = context.clone();
+        metadata.insert("paginator".into(), Value::Object(paginator));
+        state.add_page(
+            dest.clone(),
+            dest.clone(),
+            &const_compiler(metadata),
+            paginate.renderer.clone(),
+        )?;
+    }
+    Ok(dests.into_iter().next().unwrap())
+}
+
+/// Split the artifacts of `query` into pages under `base`, eg `posts/page/1/`, `posts/page/2/`
+/// and so on. Use an empty `base` for pages under the site root.
+pub fn paginate<Q: Into<SnapshotQuery>>(query: Q, base: PathBuf, paginate: Paginate) -> Rule {
+    let query = query.into();
+    Box::new(move |state: &mut State| {
+        let members = query.uuids(state)?;
+        paginate_members(state, members, &paginate, &base, &Map::new())?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_paginate_state(name: &str) -> State {
+        let mut state = crate::tests::test_state(
+            name,
+            &[(
+                "page.hbs",
+                "{{paginator.current}}/{{paginator.total_pages}}:{{#each paginator.items}}{{title}},{{/each}}<{{paginator.prev_url}}|{{paginator.next_url}}>",
+            )],
+        );
+        for (title, n) in &[("a", Some(2)), ("b", None), ("c", Some(3)), ("d", Some(1))] {
+            let mut metadata = Map::new();
+            metadata.insert("title".into(), title.to_string().into());
+            metadata.insert("body".into(), title.to_string().into());
+            if let Some(n) = n {
+                metadata.insert("n".into(), (*n).into());
+            }
+            let path = PathBuf::from(format!("{}.html", title));
+            let uuid = state
+                .add_page(
+                    path.clone(),
+                    path,
+                    &const_compiler(metadata),
+                    Renderer::body(),
+                )
+                .unwrap();
+            state.add_to_snapshot("posts".into(), uuid);
+        }
+        state
+    }
+
+    #[test]
+    fn test_paginate() {
+        let mut state = test_paginate_state("paginate");
+        state.then(paginate(
+            "posts",
+            "posts".into(),
+            Paginate::new(3, Renderer::LoadAndApplyTemplate("templates/page.hbs")),
+        ));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        let contents = |dest: &str| {
+            state.artifacts[&uuid_from_path(Path::new(dest))]
+                .contents
+                .clone()
+        };
+        assert_eq!(
+            contents("posts/page/1/index.html"),
+            "1/2:a,b,c,<|/posts/page/2/>"
+        );
+        assert_eq!(
+            contents("posts/page/2/index.html"),
+            "2/2:d,</posts/page/1/|>"
+        );
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_paginate_sort_and_route() {
+        let mut state = test_paginate_state("paginate-sort");
+        let mut options = Paginate::new(2, Renderer::LoadAndApplyTemplate("templates/page.hbs"));
+        options.sort_key = Some("n".into());
+        options.reverse = true;
+        // The first page is the base itself.
+        options.route = Box::new(|base, page| match page {
+            1 => base.join("index.html"),
+            page => base.join(format!("{}.html", page)),
+        });
+        state.then(paginate("posts", PathBuf::new(), options));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        let contents = |dest: &str| {
+            state.artifacts[&uuid_from_path(Path::new(dest))]
+                .contents
+                .clone()
+        };
+        // Items without the sort key stay last when reversed.
+        assert_eq!(contents("index.html"), "1/2:c,a,<|/2.html>");
+        assert_eq!(contents("2.html"), "2/2:d,b,</|>");
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+
+        let mut state = test_paginate_state("paginate-empty");
+        state.then(paginate(
+            "missing",
+            "posts".into(),
+            Paginate::new(2, Renderer::LoadAndApplyTemplate("templates/page.hbs")),
+        ));
+        // A missing snapshot is an error, a page size of zero too.
+        assert!(state.err.take().is_some());
+        state.then(paginate(
+            "posts",
+            "posts".into(),
+            Paginate::new(0, Renderer::LoadAndApplyTemplate("templates/page.hbs")),
+        ));
+        assert!(state.err.is_some());
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+}
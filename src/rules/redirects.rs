@@ -0,0 +1,284 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Redirects from old URLs after routing changes.
+
+use super::*;
+
+/// Configuration of a [`redirects`](redirects) rule.
+///
+/// Redirects are taken from `rules` and, if `from_metadata` is set, from the `redirect_from`
+/// metadata key of every artifact added so far, eg:
+///
+/// ```text
+/// ---
+/// title: new post
+/// redirect_from:
+///   - /2019/old-post.html
+///   - /old-post/
+/// ---
+/// ```
+///
+/// For every redirect an HTML stub is created at the old path, which redirects with a
+/// `<meta http-equiv="refresh">` tag. Old paths that end with `/` get an `index.html` stub.
+/// Stubs are added with [`State::add_page`](crate::State::add_page), so redirecting from a path
+/// that is generated by another rule is an error. So is a path with `..` components, or a path or
+/// URL that is empty or contains whitespace or `;`.
+pub struct Redirects {
+    /// `(from, to)` site paths or URLs.
+    pub rules: Vec<(String, String)>,
+    /// Read `redirect_from` metadata keys.
+    pub from_metadata: bool,
+    /// Render stubs with this renderer instead of the built-in one. The context contains `from`
+    /// and `to`.
+    pub renderer: Option<Renderer>,
+    /// Also write a Netlify `_redirects` file at this destination.
+    pub netlify: Option<PathBuf>,
+    /// Also write an nginx map file at this destination, to be included in a `map` block:
+    ///
+    /// ```text
+    /// map $uri $redirect_uri {
+    ///     include /path/to/_site/redirects.map;
+    /// }
+    /// ```
+    pub nginx_map: Option<PathBuf>,
+}
+
+impl Redirects {
+    pub fn new(rules: Vec<(String, String)>) -> Self {
+        Redirects {
+            rules,
+            from_metadata: true,
+            renderer: None,
+            netlify: None,
+            nginx_map: None,
+        }
+    }
+}
+
+/// Render the built-in HTML redirect stub.
+pub fn redirect_stub(to: &str) -> String {
+    let to = escape_xml(to);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <title>Redirecting&hellip;</title>
+    <link rel="canonical" href="{to}">
+    <meta name="robots" content="noindex">
+    <meta http-equiv="refresh" content="0; url={to}">
+  </head>
+  <body>
+    <p>This page has moved to <a href="{to}">{to}</a>.</p>
+  </body>
+</html>
+"#,
+        to = to
+    )
+}
+
+/// Returns the destination of the stub for site path `from`, which must not leave the output
+/// directory.
+fn stub_path(from: &str) -> Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(from).components() {
+        match component {
+            std::path::Component::Normal(name) => path.push(name),
+            std::path::Component::RootDir | std::path::Component::CurDir => {}
+            _ => Err(format!(
+                "Redirect from {:?} leaves the output directory",
+                from
+            ))?,
+        }
+    }
+    if from.ends_with('/') || path.as_os_str().is_empty() || path.extension().is_none() {
+        Ok(path.join("index.html"))
+    } else {
+        Ok(path)
+    }
+}
+
+/// Returns an error if `from` or `to` can't be written to a `_redirects` or nginx map line.
+fn check_redirect(from: &str, to: &str) -> Result<()> {
+    for value in &[from, to] {
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == ';') {
+            Err(format!(
+                "Redirect from {:?} to {:?} is empty or contains whitespace or `;`",
+                from, to
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Create redirects from old URLs, see [`Redirects`](Redirects).
+pub fn redirects(redirects: Redirects) -> Rule {
+    Box::new(move |state: &mut State| {
+        let mut rules = redirects.rules.clone();
+        if redirects.from_metadata {
+            let mut artifacts = state
+                .artifacts
+                .values()
+                .filter(|a| a.root == state.root)
+                .collect::<Vec<&BuildArtifact>>();
+            artifacts.sort_by(|a, b| a.path.cmp(&b.path));
+            for artifact in artifacts {
+                if let Some(from) = artifact.metadata.get("redirect_from") {
+                    let to = path_to_url(&artifact.path);
+                    for from in terms(from) {
+                        rules.push((from, to.clone()));
+                    }
+                }
+            }
+        }
+
+        for (from, to) in rules.iter() {
+            check_redirect(from, to)?;
+            let dest = stub_path(from)?;
+            let mut metadata = Map::new();
+            metadata.insert("from".into(), from.clone().into());
+            metadata.insert("to".into(), to.clone().into());
+            metadata.insert("sitemap".into(), false.into());
+            let renderer = match redirects.renderer {
+                Some(ref renderer) => renderer.clone(),
+                None => {
+                    metadata.insert("body".into(), redirect_stub(to).into());
+                    Renderer::body()
+                }
+            };
+            // Stubs have their own resource, so that they collide with any page at `dest`.
+            state.add_page(
+                dest,
+                PathBuf::from(format!("redirect:{}", from)),
+                &const_compiler(metadata),
+                renderer,
+            )?;
+        }
+
+        let mut files = vec![];
+        if let Some(ref path) = redirects.netlify {
+            let body = rules
+                .iter()
+                .map(|(from, to)| format!("{} {} 301\n", from, to))
+                .collect::<String>();
+            files.push((path.clone(), body));
+        }
+        if let Some(ref path) = redirects.nginx_map {
+            let body = rules
+                .iter()
+                .map(|(from, to)| format!("{} {};\n", from, to))
+                .collect::<String>();
+            files.push((path.clone(), body));
+        }
+        for (path, body) in files {
+            let mut metadata = Map::new();
+            metadata.insert("body".into(), body.into());
+            state.add_page(
+                path.clone(),
+                path,
+                &const_compiler(metadata),
+                Renderer::body(),
+            )?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stub_path() {
+        assert_eq!(stub_path("/old/").unwrap(), Path::new("old/index.html"));
+        assert_eq!(stub_path("/old").unwrap(), Path::new("old/index.html"));
+        assert_eq!(stub_path("/").unwrap(), Path::new("index.html"));
+        assert_eq!(
+            stub_path("/2019/./post.html").unwrap(),
+            Path::new("2019/post.html")
+        );
+        assert!(stub_path("/../outside.html").is_err());
+        assert!(stub_path("/a/../../outside.html").is_err());
+        assert!(check_redirect("/a b", "/b").is_err());
+        assert!(check_redirect("/a", "/b;\nrewrite").is_err());
+        assert!(check_redirect("/a", "").is_err());
+        assert!(check_redirect("/a", "https://example.com/b?c=d").is_ok());
+    }
+
+    #[test]
+    fn test_redirects() {
+        let mut state = crate::tests::test_state("redirects", &[]);
+        let mut metadata = Map::new();
+        metadata.insert("body".into(), "post".into());
+        metadata.insert("redirect_from".into(), "/old-post/".into());
+        state
+            .then(create(
+                "post.html".into(),
+                Renderer::body(),
+                const_compiler(metadata),
+            ))
+            .then(redirects(Redirects {
+                netlify: Some("_redirects".into()),
+                nginx_map: Some("redirects.map".into()),
+                ..Redirects::new(vec![("/feed.xml".into(), "/atom.xml".into())])
+            }));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        let contents = |dest: &str| {
+            state.artifacts[&state.routes[&state.output_dir().join(dest)]]
+                .contents
+                .clone()
+        };
+        assert_eq!(contents("feed.xml"), redirect_stub("/atom.xml"));
+        assert_eq!(contents("old-post/index.html"), redirect_stub("/post.html"));
+        assert_eq!(
+            contents("_redirects"),
+            "/feed.xml /atom.xml 301\n/old-post/ /post.html 301\n"
+        );
+        assert_eq!(
+            contents("redirects.map"),
+            "/feed.xml /atom.xml;\n/old-post/ /post.html;\n"
+        );
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+
+        // Redirecting from a generated path or out of the output directory is an error.
+        for from in &["/post.html", "/robots.txt", "/../post.html", "/a b"] {
+            let mut state = crate::tests::test_state("redirects-err", &[]);
+            let mut metadata = Map::new();
+            metadata.insert("body".into(), "post".into());
+            state
+                .then(create(
+                    "post.html".into(),
+                    Renderer::body(),
+                    const_compiler(metadata),
+                ))
+                .then(robots_txt(Robots::allow_all(
+                    "https://example.com/sitemap.xml",
+                )))
+                .then(redirects(Redirects::new(vec![(
+                    from.to_string(),
+                    "/".into(),
+                )])));
+            assert!(state.err.is_some(), "{}", from);
+            fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+        }
+    }
+}
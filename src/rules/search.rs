@@ -0,0 +1,318 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Client-side search index generation.
+
+use super::*;
+use std::collections::BTreeMap;
+
+/// Words ignored when indexing.
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+/// A small script that loads a search index and queries it, exposed as
+/// `libssgSearch(indexUrl)`, which returns a promise of a `search(query)` function. `search`
+/// returns the matching documents ordered by score.
+///
+/// ```text
+/// libssgSearch("/search_index.json").then(search => {
+///     for (const doc of search("static site")) {
+///         console.log(doc.title, doc.url);
+///     }
+/// });
+/// ```
+pub const SEARCH_SCRIPT: &str = r#"function libssgSearch(indexUrl) {
+  "use strict";
+  const tokenize = text => text.toLowerCase().split(/[^\p{L}\p{N}]+/u).filter(t => t.length > 1);
+  return fetch(indexUrl).then(r => r.json()).then(idx => function search(query) {
+    const scores = new Map();
+    const terms = tokenize(query);
+    terms.forEach((term, i) => {
+      const prefix = i === terms.length - 1;
+      if (idx.index) {
+        for (const key in idx.index) {
+          if (key === term || (prefix && key.startsWith(term))) {
+            for (const [doc, score] of idx.index[key]) {
+              scores.set(doc, (scores.get(doc) || 0) + score);
+            }
+          }
+        }
+      } else {
+        idx.docs.forEach((doc, n) => {
+          for (const field in idx.fields) {
+            const text = [].concat(doc[field] || []).join(" ");
+            const count = tokenize(text).filter(t => t === term || (prefix && t.startsWith(term))).length;
+            if (count) {
+              scores.set(n, (scores.get(n) || 0) + count * idx.fields[field]);
+            }
+          }
+        });
+      }
+    });
+    return [...scores].sort((a, b) => b[1] - a[1]).map(([doc]) => idx.docs[doc]);
+  });
+}
+"#;
+
+/// Configuration of a [`search_index`](search_index) rule.
+///
+/// The index is a JSON document with:
+///
+/// - `fields`: an object of field names and their boosts
+/// - `docs`: a list of documents with `title`, `url` and the indexed fields
+/// - `index`, if `inverted_index` is set: an object of terms and lists of `[document, score]`
+///   pairs, where `document` is an index into `docs` and `score` is the sum of the term's
+///   occurrences in each field multiplied by the field's boost
+///
+/// Field `body` is the text of the artifact's contents, `headings` is the list of the text of its
+/// `<h1>` to `<h6>` elements and any other field is read from the artifact's metadata, eg
+/// `title` or `tags`. Terms are lowercase alphanumeric words without stemming; common English
+/// words are skipped.
+pub struct SearchIndex {
+    /// Destination of the index.
+    pub path: PathBuf,
+    /// Indexed fields and their boosts.
+    pub fields: Vec<(String, f64)>,
+    /// Include a prebuilt inverted index, so that clients don't have to tokenize documents.
+    pub inverted_index: bool,
+    /// Truncate the `body` field stored in documents to this many characters. The inverted index
+    /// always uses the full text.
+    pub body_length: Option<usize>,
+    /// Index this named snapshot of each artifact's rendered contents (see
+    /// [`Renderer::Snapshot`](crate::Renderer::Snapshot)) instead of the compiled `body`.
+    pub content_snapshot: Option<String>,
+    /// Also write [`SEARCH_SCRIPT`](SEARCH_SCRIPT) at this destination.
+    pub script_path: Option<PathBuf>,
+}
+
+impl SearchIndex {
+    /// Index `title` with a boost of `10`, `headings` with `5` and `body` with `1` at
+    /// `search_index.json`.
+    pub fn new() -> Self {
+        SearchIndex {
+            path: PathBuf::from("search_index.json"),
+            fields: vec![
+                ("title".to_string(), 10.0),
+                ("headings".to_string(), 5.0),
+                ("body".to_string(), 1.0),
+            ],
+            inverted_index: true,
+            body_length: None,
+            content_snapshot: None,
+            script_path: None,
+        }
+    }
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Split `text` into lowercase index terms.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| t.chars().count() > 1)
+        .map(str::to_lowercase)
+        .filter(|t| !STOP_WORDS.contains(&t.as_str()))
+        .collect()
+}
+
+/// Returns the text of the `<h1>` to `<h6>` elements of `html`.
+fn headings(html: &str) -> Vec<String> {
+    use std::sync::OnceLock;
+    static HEADINGS: OnceLock<regex::Regex> = OnceLock::new();
+    HEADINGS
+        .get_or_init(|| regex::Regex::new(r"(?is)<h[1-6]\b[^>]*>(.*?)</h[1-6]\s*>").unwrap())
+        .captures_iter(html)
+        .map(|c| strip_html(&c[1]))
+        .filter(|h| !h.is_empty())
+        .collect()
+}
+
+/// Returns the text of a metadata value.
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(list) => list.iter().map(value_text).collect::<Vec<_>>().join(" "),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+impl SearchIndex {
+    /// Build the index document of `artifacts`.
+    pub fn build(&self, artifacts: &[&BuildArtifact]) -> Value {
+        let mut docs = Vec::with_capacity(artifacts.len());
+        let mut index: BTreeMap<String, BTreeMap<usize, f64>> = BTreeMap::new();
+        for (n, artifact) in artifacts.iter().enumerate() {
+            let html = match self.content_snapshot {
+                Some(ref name) => artifact.snapshots.get(name).cloned().unwrap_or_default(),
+                None => artifact
+                    .metadata
+                    .get("body")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+            };
+            let mut doc = Map::new();
+            doc.insert(
+                "title".into(),
+                artifact
+                    .metadata
+                    .get("title")
+                    .cloned()
+                    .unwrap_or_else(|| artifact.path.display().to_string().into()),
+            );
+            doc.insert("url".into(), path_to_url(&artifact.path).into());
+            for (field, boost) in self.fields.iter() {
+                let (text, value) = match field.as_str() {
+                    "body" => {
+                        let text = strip_html(&html);
+                        let stored = match self.body_length {
+                            Some(len) => text.chars().take(len).collect(),
+                            None => text.clone(),
+                        };
+                        (text, stored.into())
+                    }
+                    "headings" => {
+                        let headings = headings(&html);
+                        (headings.join(" "), headings.into())
+                    }
+                    _ => {
+                        let value = artifact.metadata.get(field).cloned().unwrap_or(Value::Null);
+                        (value_text(&value), value)
+                    }
+                };
+                doc.insert(field.clone(), value);
+                if self.inverted_index {
+                    for term in tokenize(&text) {
+                        *index.entry(term).or_default().entry(n).or_default() += boost;
+                    }
+                }
+            }
+            docs.push(Value::Object(doc));
+        }
+
+        let mut ret = Map::new();
+        ret.insert(
+            "fields".into(),
+            Value::Object(
+                self.fields
+                    .iter()
+                    .map(|(field, boost)| (field.clone(), (*boost).into()))
+                    .collect(),
+            ),
+        );
+        ret.insert("docs".into(), Value::Array(docs));
+        if self.inverted_index {
+            ret.insert(
+                "index".into(),
+                Value::Object(
+                    index
+                        .into_iter()
+                        .map(|(term, postings)| {
+                            (
+                                term,
+                                Value::Array(
+                                    postings
+                                        .into_iter()
+                                        .map(|(doc, score)| serde_json::json!([doc, score]))
+                                        .collect(),
+                                ),
+                            )
+                        })
+                        .collect(),
+                ),
+            );
+        }
+        Value::Object(ret)
+    }
+}
+
+/// Build a JSON search index of the artifacts of `query`, see [`SearchIndex`](SearchIndex).
+pub fn search_index<Q: Into<SnapshotQuery>>(query: Q, search_index: SearchIndex) -> Rule {
+    let query = query.into();
+    Box::new(move |state: &mut State| {
+        let index = search_index.build(&query.run(state)?);
+        let mut files = vec![(search_index.path.clone(), serde_json::to_string(&index)?)];
+        if let Some(ref path) = search_index.script_path {
+            files.push((path.clone(), SEARCH_SCRIPT.to_string()));
+        }
+        for (path, body) in files {
+            let mut metadata = Map::new();
+            metadata.insert("body".into(), body.into());
+            state.add_page(
+                path.clone(),
+                path,
+                &const_compiler(metadata),
+                Renderer::body(),
+            )?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_index() {
+        let artifact = |path: &str, title: &str, body: &str| {
+            let mut metadata = Map::new();
+            metadata.insert("title".into(), title.into());
+            metadata.insert("body".into(), body.into());
+            BuildArtifact {
+                uuid: uuid_from_path(Path::new(path)),
+                path: PathBuf::from(path),
+                root: None,
+                resource: PathBuf::from(path),
+                metadata,
+                contents: String::new(),
+                snapshots: Default::default(),
+            }
+        };
+        let a = artifact(
+            "a.html",
+            "Static sites",
+            "<h2>Rust</h2><p>The static generator.</p>",
+        );
+        let b = artifact("b.html", "Other", "<p>Rust rust</p>");
+        let index = SearchIndex::new().build(&[&a, &b]);
+
+        assert_eq!(index["docs"][0]["url"], "/a.html");
+        assert_eq!(index["docs"][0]["headings"], serde_json::json!(["Rust"]));
+        assert_eq!(index["docs"][0]["body"], "Rust The static generator.");
+        // title (10) + body (1)
+        assert_eq!(index["index"]["static"], serde_json::json!([[0, 11.0]]));
+        // headings (5) + body (1), body (1) * 2
+        assert_eq!(
+            index["index"]["rust"],
+            serde_json::json!([[0, 6.0], [1, 2.0]])
+        );
+        assert!(index["index"].get("the").is_none());
+    }
+}
@@ -0,0 +1,353 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! `sitemap.xml` generation.
+
+use super::*;
+
+/// The sitemap protocol's maximum number of URLs per sitemap file.
+pub const SITEMAP_MAX_URLS: usize = 50_000;
+
+/// Configuration of a [`sitemap`](sitemap) rule.
+///
+/// Every HTML artifact is listed, unless its metadata has `sitemap: false`. A `sitemap` object
+/// may set the page's `priority` and `changefreq`, eg:
+///
+/// ```text
+/// ---
+/// title: about
+/// sitemap:
+///   priority: 0.8
+///   changefreq: monthly
+/// ---
+/// ```
+///
+/// `lastmod` is the page's `updated` or `date` metadata, or the source file's modification time.
+pub struct Sitemap {
+    /// The site's URL, eg `https://example.com`.
+    pub base_url: String,
+    /// Destination of the sitemap, or of the sitemap index if there are more than `max_urls`
+    /// URLs. In that case, the sitemaps are named after it, eg `sitemap-1.xml`, `sitemap-2.xml`.
+    pub path: PathBuf,
+    /// Maximum number of URLs per sitemap file.
+    pub max_urls: usize,
+}
+
+impl Sitemap {
+    pub fn new<S: Into<String>>(base_url: S) -> Self {
+        Sitemap {
+            base_url: base_url.into(),
+            path: PathBuf::from("sitemap.xml"),
+            max_urls: SITEMAP_MAX_URLS,
+        }
+    }
+}
+
+/// An URL entry of a sitemap.
+#[derive(Debug, Clone)]
+pub struct SitemapUrl {
+    pub loc: String,
+    /// W3C datetime.
+    pub lastmod: Option<String>,
+    pub changefreq: Option<String>,
+    /// From 0.0 to 1.0.
+    pub priority: Option<f64>,
+}
+
+impl SitemapUrl {
+    /// Create an entry from an artifact, or `None` if it is excluded. See [`Sitemap`](Sitemap).
+    pub fn from_artifact(state: &State, artifact: &BuildArtifact, base_url: &str) -> Option<Self> {
+        let options = artifact.metadata.get("sitemap");
+        match options {
+            Some(Value::Bool(false)) => return None,
+            Some(Value::String(s)) if s == "false" => return None,
+            _ => {}
+        }
+        let option = |key: &str| options.and_then(|o| o.get(key));
+        let lastmod = ["updated", "date"]
+            .iter()
+            .filter_map(|key| artifact.metadata.get(*key).and_then(Value::as_str))
+            .find_map(parse_date)
+            .or_else(|| {
+                let mtime = fs::metadata(state.current_dir().join(&artifact.resource))
+                    .and_then(|m| m.modified())
+                    .ok()?;
+                Some(chrono::DateTime::<chrono::Utc>::from(mtime).fixed_offset())
+            })
+            .map(|d| d.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+        Some(SitemapUrl {
+            loc: format!(
+                "{}{}",
+                base_url.trim_end_matches('/'),
+                path_to_url(&artifact.path)
+            ),
+            lastmod,
+            changefreq: option("changefreq")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            priority: option("priority")
+                .and_then(|p| p.as_f64().or_else(|| p.as_str()?.parse().ok()))
+                .filter(|p: &f64| !p.is_nan())
+                .map(|p| p.clamp(0.0, 1.0)),
+        })
+    }
+}
+
+/// Render a `<urlset>` document.
+pub fn render_urlset(urls: &[SitemapUrl]) -> String {
+    use std::fmt::Write;
+
+    let mut ret = String::new();
+    let _ = writeln!(ret, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        ret,
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    );
+    for url in urls {
+        let _ = writeln!(ret, "  <url>");
+        let _ = writeln!(ret, "    <loc>{}</loc>", escape_xml(&url.loc));
+        if let Some(ref lastmod) = url.lastmod {
+            let _ = writeln!(ret, "    <lastmod>{}</lastmod>", lastmod);
+        }
+        if let Some(ref changefreq) = url.changefreq {
+            let _ = writeln!(
+                ret,
+                "    <changefreq>{}</changefreq>",
+                escape_xml(changefreq)
+            );
+        }
+        if let Some(priority) = url.priority {
+            let _ = writeln!(ret, "    <priority>{}</priority>", priority);
+        }
+        let _ = writeln!(ret, "  </url>");
+    }
+    let _ = write!(ret, "</urlset>");
+    ret
+}
+
+/// Render a `<sitemapindex>` document of sitemap URLs `locs`.
+pub fn render_sitemap_index(locs: &[String]) -> String {
+    use std::fmt::Write;
+
+    let mut ret = String::new();
+    let _ = writeln!(ret, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        ret,
+        r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    );
+    for loc in locs {
+        let _ = writeln!(ret, "  <sitemap>");
+        let _ = writeln!(ret, "    <loc>{}</loc>", escape_xml(loc));
+        let _ = writeln!(ret, "  </sitemap>");
+    }
+    let _ = write!(ret, "</sitemapindex>");
+    ret
+}
+
+fn add_xml(state: &mut State, dest: PathBuf, body: String) -> Result<Uuid> {
+    let mut metadata = Map::new();
+    metadata.insert("body".into(), body.into());
+    metadata.insert("sitemap".into(), false.into());
+    state.add_page(
+        dest.clone(),
+        dest,
+        &const_compiler(metadata),
+        Renderer::body(),
+    )
+}
+
+/// List every HTML artifact added so far in a sitemap. Add this rule after all the rules that
+/// create pages.
+pub fn sitemap(sitemap: Sitemap) -> Rule {
+    Box::new(move |state: &mut State| {
+        if sitemap.max_urls == 0 {
+            Err("Sitemap maximum number of URLs must be greater than zero.")?;
+        }
+        let mut artifacts = state
+            .artifacts
+            .values()
+            .filter(|a| {
+                a.root == state.root
+                    && a.path
+                        .extension()
+                        .map(|e| e == "html" || e == "htm")
+                        .unwrap_or(false)
+            })
+            .collect::<Vec<&BuildArtifact>>();
+        artifacts.sort_by(|a, b| a.path.cmp(&b.path));
+        let urls = artifacts
+            .into_iter()
+            .filter_map(|a| SitemapUrl::from_artifact(state, a, &sitemap.base_url))
+            .collect::<Vec<SitemapUrl>>();
+
+        if urls.len() <= sitemap.max_urls {
+            add_xml(state, sitemap.path.clone(), render_urlset(&urls))?;
+            return Ok(());
+        }
+        let stem = sitemap
+            .path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "sitemap".to_string());
+        let mut locs = vec![];
+        for (i, chunk) in urls.chunks(sitemap.max_urls).enumerate() {
+            let dest = sitemap
+                .path
+                .with_file_name(format!("{}-{}.xml", stem, i + 1));
+            locs.push(format!(
+                "{}{}",
+                sitemap.base_url.trim_end_matches('/'),
+                path_to_url(&dest)
+            ));
+            add_xml(state, dest, render_urlset(chunk))?;
+        }
+        add_xml(state, sitemap.path.clone(), render_sitemap_index(&locs))?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_urlset() {
+        let output = render_urlset(&[
+            SitemapUrl {
+                loc: "https://example.com/?a=1&b=2".into(),
+                lastmod: Some("2019-06-15T00:00:00Z".into()),
+                changefreq: Some("monthly".into()),
+                priority: Some(0.8),
+            },
+            SitemapUrl {
+                loc: "https://example.com/posts/".into(),
+                lastmod: None,
+                changefreq: None,
+                priority: None,
+            },
+        ]);
+        let doc = roxmltree::Document::parse(&output).unwrap();
+        let urlset = doc.root_element();
+        assert_eq!(urlset.tag_name().name(), "urlset");
+        let urls = urlset
+            .children()
+            .filter(|c| c.is_element())
+            .collect::<Vec<_>>();
+        assert_eq!(urls.len(), 2);
+        let loc = urls[0].first_element_child().unwrap();
+        assert_eq!(loc.text(), Some("https://example.com/?a=1&b=2"));
+        assert!(output.contains("<priority>0.8</priority>"));
+        assert_eq!(urls[1].children().filter(|c| c.is_element()).count(), 1);
+
+        let output = render_sitemap_index(&["https://example.com/sitemap-1.xml".into()]);
+        let doc = roxmltree::Document::parse(&output).unwrap();
+        assert_eq!(doc.root_element().tag_name().name(), "sitemapindex");
+    }
+
+    #[test]
+    fn test_sitemap() {
+        let mut state = crate::tests::test_state("sitemap", &[]);
+        let root = state.output_dir().parent().unwrap().to_path_buf();
+        let source = root
+            .strip_prefix(state.current_dir())
+            .unwrap()
+            .join("about.md");
+        fs::write(&source, "about").unwrap();
+        let pages = [
+            (
+                "index.html",
+                "index.md",
+                r#"{"date": "2019-06-15", "sitemap": {"priority": 0.85}}"#,
+            ),
+            (
+                "about.html",
+                source.to_str().unwrap(),
+                r#"{"sitemap": {"priority": "3", "changefreq": "monthly"}}"#,
+            ),
+            ("draft.html", "draft.md", r#"{"sitemap": false}"#),
+            ("style.css", "style.css", "{}"),
+            ("posts/index.html", "posts.md", "{}"),
+        ];
+        for (dest, resource, metadata) in pages.iter() {
+            let mut metadata: Map<String, Value> = serde_json::from_str(metadata).unwrap();
+            metadata.insert("body".into(), "".into());
+            state
+                .add_page(
+                    dest.into(),
+                    resource.into(),
+                    &const_compiler(metadata),
+                    Renderer::body(),
+                )
+                .unwrap();
+        }
+        let mtime = fs::metadata(&source).unwrap().modified().unwrap();
+        let mtime = chrono::DateTime::<chrono::Utc>::from(mtime)
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        state.then(sitemap(Sitemap {
+            max_urls: 2,
+            ..Sitemap::new("https://example.com/")
+        }));
+        assert!(state.err.is_none(), "{:?}", state.err);
+        let contents = |dest: &str| {
+            state.artifacts[&uuid_from_path(Path::new(dest))]
+                .contents
+                .clone()
+        };
+
+        // Non-HTML outputs and pages with `sitemap: false` are excluded, and three URLs are split
+        // into two sitemaps of an index.
+        assert_eq!(
+            contents("sitemap.xml"),
+            render_sitemap_index(&[
+                "https://example.com/sitemap-1.xml".into(),
+                "https://example.com/sitemap-2.xml".into()
+            ])
+        );
+        assert_eq!(
+            contents("sitemap-1.xml"),
+            render_urlset(&[
+                SitemapUrl {
+                    loc: "https://example.com/about.html".into(),
+                    lastmod: Some(mtime),
+                    changefreq: Some("monthly".into()),
+                    priority: Some(1.0),
+                },
+                SitemapUrl {
+                    loc: "https://example.com/".into(),
+                    lastmod: Some("2019-06-15T00:00:00Z".into()),
+                    changefreq: None,
+                    priority: Some(0.85),
+                },
+            ])
+        );
+        assert!(contents("sitemap-1.xml").contains("<priority>0.85</priority>"));
+        assert_eq!(
+            contents("sitemap-2.xml"),
+            render_urlset(&[SitemapUrl {
+                loc: "https://example.com/posts/".into(),
+                lastmod: None,
+                changefreq: None,
+                priority: None,
+            }])
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
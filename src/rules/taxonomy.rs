@@ -0,0 +1,291 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Taxonomy pages, such as tags and categories, built from the metadata of a snapshot's artifacts.
+
+use super::*;
+use std::collections::BTreeMap;
+
+/// Configuration of a [`taxonomy`](taxonomy) rule.
+///
+/// Terms are read from the metadata key `key` of every artifact in the query. The value may be
+/// a list of strings or a single comma separated string, eg `tags: [rust, web]` or `tags: rust,
+/// web`. Each term gets a page at `term_route` applied to `<key>/<slug>`; by default this is
+/// `tags/rust/index.html`.
+///
+/// Term pages are rendered with a context containing `term`, `slug`, `url`, `count`, `snapshot`
+/// and `items`, the list of member artifacts' metadata (see
+/// [`BuildArtifact::context`](crate::BuildArtifact::context)). The index page gets `key` and
+/// `terms`, the list of every term's `term`, `slug`, `url`, `count` and, if feeds are enabled,
+/// `feed_url`.
+///
+/// If `paginate` is set, each term's members are instead split into pages with
+/// [`Paginate`](crate::Paginate) under `<key>/<slug>`, rendered with the paginator's renderer, and
+/// the term's `url` points to the first page.
+///
+/// The members of each term are also saved in a snapshot named `<key>:<slug>`, eg `tags:rust`,
+/// for use in later rules.
+pub struct Taxonomy {
+    /// Metadata key to read terms from.
+    pub key: String,
+    /// Turns a term into the slug used in its route and snapshot name.
+    pub slugify: Box<dyn Fn(&str) -> String>,
+    /// Maps `<key>/<slug>` to the term page's destination.
+    pub term_route: Route,
+    pub term_renderer: Renderer,
+    /// Split term pages into pages of this configuration.
+    pub paginate: Option<Paginate>,
+    /// Destination of the index page of all terms, if any.
+    pub index_path: Option<PathBuf>,
+    pub index_renderer: Renderer,
+    /// Generate an RSS feed per term with this channel configuration. The channel title is
+    /// suffixed with the term.
+    pub feed: Option<RssChannel>,
+    /// Maps `<key>/<slug>` to the term feed's destination.
+    pub feed_route: Route,
+}
+
+impl Taxonomy {
+    /// Create a taxonomy for metadata key `key` with default slugs and routes, ie
+    /// `<key>/<slug>/index.html` for term pages and `<key>/index.html` for the index.
+    pub fn new<S: Into<String>>(key: S, term_renderer: Renderer, index_renderer: Renderer) -> Self {
+        let key = key.into();
+        Taxonomy {
+            index_path: Some(PathBuf::from(&key).join("index.html")),
+            key,
+            slugify: Box::new(slugify),
+            term_route: Route::Custom(Box::new(|path| path.join("index.html"))),
+            term_renderer,
+            paginate: None,
+            index_renderer,
+            feed: None,
+            feed_route: Route::Custom(Box::new(|path| path.join("rss.xml"))),
+        }
+    }
+}
+
+/// Collect terms from a metadata value, either a list of strings or a comma separated string.
+pub(crate) fn terms(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect(),
+        Value::Array(list) => list.iter().flat_map(terms).collect(),
+        _ => vec![],
+    }
+}
+
+/// Group the artifacts of `query` by the terms in their metadata key
+/// `taxonomy.key` and create a page for each term and an index page of all terms.
+pub fn taxonomy<Q: Into<SnapshotQuery>>(query: Q, taxonomy: Taxonomy) -> Rule {
+    let query = query.into();
+    Box::new(move |state: &mut State| {
+        let Taxonomy {
+            key,
+            slugify,
+            term_route,
+            term_renderer,
+            paginate,
+            index_path,
+            index_renderer,
+            feed,
+            feed_route,
+        } = taxonomy;
+        // slug -> (term as first spelled, members)
+        let mut groups: BTreeMap<String, (String, Vec<Uuid>)> = BTreeMap::new();
+        for uuid in query.uuids(state)?.iter() {
+            let mut seen = vec![];
+            let artifact_terms = state.artifacts[uuid]
+                .metadata
+                .get(&key)
+                .map(terms)
+                .unwrap_or_default();
+            for term in artifact_terms {
+                let slug = slugify(&term);
+                if slug.is_empty() || seen.contains(&slug) {
+                    continue;
+                }
+                seen.push(slug.clone());
+                groups
+                    .entry(slug)
+                    .or_insert_with(|| (term, vec![]))
+                    .1
+                    .push(*uuid);
+            }
+        }
+
+        let mut index = Vec::with_capacity(groups.len());
+        for (slug, (term, members)) in groups {
+            let base = PathBuf::from(&key).join(&slug);
+            let dest = match paginate {
+                Some(ref paginate) => (paginate.route)(&base, 1),
+                None => term_route.apply(&base),
+            };
+            let snapshot = format!("{}:{}", key, slug);
+            for uuid in members.iter() {
+                state.add_to_snapshot(snapshot.clone(), *uuid);
+            }
+
+            let mut term_context = Map::new();
+            term_context.insert("term".into(), term.clone().into());
+            term_context.insert("slug".into(), slug.into());
+            term_context.insert("url".into(), path_to_url(&dest).into());
+            term_context.insert("count".into(), members.len().into());
+            term_context.insert("snapshot".into(), snapshot.clone().into());
+            let feed_path = feed.as_ref().map(|_| feed_route.apply(&base));
+            if let Some(ref feed_path) = feed_path {
+                term_context.insert("feed_url".into(), path_to_url(feed_path).into());
+            }
+            index.push(Value::Object(term_context.clone()));
+
+            let mut metadata = term_context;
+            metadata.insert("title".into(), term.clone().into());
+            if let Some(ref paginate) = paginate {
+                paginate_members(state, members, paginate, &base, &metadata)?;
+            } else {
+                metadata.insert(
+                    "items".into(),
+                    Value::Array(
+                        members
+                            .iter()
+                            .map(|uuid| Value::Object(state.artifacts[uuid].context()))
+                            .collect(),
+                    ),
+                );
+                state.add_page(
+                    dest.clone(),
+                    dest,
+                    &const_compiler(metadata),
+                    term_renderer.clone(),
+                )?;
+            }
+
+            if let (Some(feed), Some(feed_path)) = (feed.as_ref(), feed_path) {
+                let mut configuration = feed.clone();
+                configuration.title = format!("{}: {}", configuration.title, term);
+                state.add_page(
+                    feed_path.clone(),
+                    feed_path,
+                    &rss_feed(snapshot, configuration),
+                    Renderer::body(),
+                )?;
+            }
+        }
+
+        if let Some(index_path) = index_path {
+            let mut metadata = Map::new();
+            metadata.insert("title".into(), key.clone().into());
+            metadata.insert("key".into(), key.into());
+            metadata.insert("terms".into(), Value::Array(index));
+            state.add_page(
+                index_path.clone(),
+                index_path,
+                &const_compiler(metadata),
+                index_renderer,
+            )?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_taxonomy() {
+        let mut state = crate::tests::test_state(
+            "taxonomy",
+            &[
+                ("term.hbs", "{{term}}:{{#each items}}{{title}},{{/each}}"),
+                (
+                    "index.hbs",
+                    "{{key}}:{{#each terms}}{{term}}={{count}} {{url}} {{feed_url}};{{/each}}",
+                ),
+            ],
+        );
+        let posts: &[(&str, Value)] = &[
+            ("a", serde_json::json!(["Rust", "Web"])),
+            ("b", "rust, CLI".into()),
+            ("c", serde_json::json!(["web", "Web"])),
+            ("d", Value::Null),
+        ];
+        for (title, tags) in posts {
+            let mut metadata = Map::new();
+            metadata.insert("title".into(), title.to_string().into());
+            metadata.insert("body".into(), title.to_string().into());
+            if !tags.is_null() {
+                metadata.insert("tags".into(), tags.clone());
+            }
+            let path = PathBuf::from(format!("{}.html", title));
+            let uuid = state
+                .add_page(
+                    path.clone(),
+                    path,
+                    &const_compiler(metadata),
+                    Renderer::body(),
+                )
+                .unwrap();
+            state.add_to_snapshot("posts".into(), uuid);
+        }
+        let mut tags = Taxonomy::new(
+            "tags",
+            Renderer::LoadAndApplyTemplate("templates/term.hbs"),
+            Renderer::LoadAndApplyTemplate("templates/index.hbs"),
+        );
+        tags.feed = Some(RssChannel::new("Blog", "posts", "https://example.com"));
+        state.then(taxonomy("posts", tags));
+        assert!(state.err.is_none(), "{:?}", state.err);
+
+        let contents = |dest: &str| {
+            state.artifacts[&uuid_from_path(Path::new(dest))]
+                .contents
+                .clone()
+        };
+        // Terms are grouped by slug, keep their first spelling and count each artifact once.
+        assert_eq!(contents("tags/rust/index.html"), "Rust:a,b,");
+        assert_eq!(contents("tags/web/index.html"), "Web:a,c,");
+        assert_eq!(contents("tags/cli/index.html"), "CLI:b,");
+        assert_eq!(
+            contents("tags/index.html"),
+            "tags:CLI=1 /tags/cli/ /tags/cli/rss.xml;Rust=2 /tags/rust/ /tags/rust/rss.xml;Web=2 /tags/web/ /tags/web/rss.xml;"
+        );
+        let members = |name: &str| {
+            state.snapshots[name]
+                .iter()
+                .map(|uuid| state.artifacts[uuid].path.display().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(members("tags:rust"), ["a.html", "b.html"]);
+        assert_eq!(members("tags:web"), ["a.html", "c.html"]);
+        assert_eq!(members("tags:cli"), ["b.html"]);
+
+        let feed = contents("tags/web/rss.xml");
+        assert!(feed.contains("<title>Blog: Web</title>"), "{}", feed);
+        assert_eq!(feed.matches("<item>").count(), 2);
+        assert!(feed.contains("https://example.com/a.html"), "{}", feed);
+        assert!(feed.contains("https://example.com/c.html"), "{}", feed);
+        fs::remove_dir_all(state.output_dir().parent().unwrap()).unwrap();
+    }
+}
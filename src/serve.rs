@@ -0,0 +1,399 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! A development server for the output directory with live reload, enabled with the `serve`
+//! feature, see [`State::serve`](crate::State::serve).
+
+use super::*;
+use std::io::Write;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// URL of the server-sent events stream the live reload script listens to.
+const EVENTS_URL: &str = "/__libssg/live-reload";
+
+/// Reconnects to [`EVENTS_URL`] whenever the connection drops, and reloads the page whenever the
+/// build version it receives differs from the first one, ie after a build or a server restart.
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>(function () {
+  var version;
+  var source = new EventSource("/__libssg/live-reload");
+  source.onmessage = function (event) {
+    if (version !== undefined && version !== event.data) {
+      location.reload();
+    }
+    version = event.data;
+  };
+})();</script>
+"#;
+
+/// Configuration of the development server, see [`State::serve`](crate::State::serve).
+///
+/// Requests for `/posts/hello` are served `posts/hello/index.html` or `posts/hello.html`, and
+/// requests for a directory without a trailing slash are redirected to it.
+#[derive(Debug, Clone)]
+pub struct Serve {
+    /// Address to listen on, eg `127.0.0.1:8000`. Port `0` picks a free port.
+    pub address: String,
+    /// Page served with status 404 for missing paths, relative to the output directory.
+    pub not_found: Option<PathBuf>,
+    /// Whether to inject a script in HTML pages that reloads them after every
+    /// [`State::finish`](crate::State::finish).
+    pub live_reload: bool,
+}
+
+impl Serve {
+    /// Listen on `127.0.0.1:8000`, with `404.html` and live reload.
+    pub fn new() -> Self {
+        Serve {
+            address: "127.0.0.1:8000".into(),
+            not_found: Some(PathBuf::from("404.html")),
+            live_reload: true,
+        }
+    }
+}
+
+impl Default for Serve {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A running development server, see [`State::serve`](crate::State::serve).
+pub struct Server {
+    http: Arc<tiny_http::Server>,
+    address: String,
+    reloader: Arc<Reloader>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for Server {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "Server({})", self.address)
+    }
+}
+
+impl Server {
+    /// Listen on `options.address` and serve `root` from a background thread.
+    pub fn start(root: PathBuf, options: Serve) -> Result<Self> {
+        let http = tiny_http::Server::http(&options.address)
+            .map_err(|err| format!("Could not listen on {}: {}", options.address, err))?;
+        let address = match http.server_addr().to_ip() {
+            Some(address) => address.to_string(),
+            None => options.address.clone(),
+        };
+        let http = Arc::new(http);
+        let reloader = Arc::new(Reloader::new());
+        let thread = {
+            let http = http.clone();
+            let reloader = reloader.clone();
+            std::thread::Builder::new()
+                .name("libssg-serve".into())
+                .spawn(move || {
+                    for request in http.incoming_requests() {
+                        if options.live_reload && request.url() == EVENTS_URL {
+                            let reloader = reloader.clone();
+                            std::thread::spawn(move || reloader.stream(request));
+                        } else if let Err(err) = respond(&root, &options, request) {
+                            debug!(%err, "could not respond");
+                        }
+                    }
+                })?
+        };
+        Ok(Server {
+            http,
+            address,
+            reloader,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the address the server listens on.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Reload the pages open in browsers.
+    pub fn reload(&self) {
+        self.reloader.reload();
+    }
+
+    /// Block until the server stops.
+    pub fn wait(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.http.unblock();
+    }
+}
+
+/// Build version and the live reload streams waiting for the next one.
+struct Reloader {
+    version: Mutex<String>,
+    started: u128,
+    builds: std::sync::atomic::AtomicUsize,
+    clients: Mutex<Vec<mpsc::Sender<String>>>,
+}
+
+impl Reloader {
+    fn new() -> Self {
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Reloader {
+            version: Mutex::new(format!("{}-0", started)),
+            started,
+            builds: Default::default(),
+            clients: Mutex::new(vec![]),
+        }
+    }
+
+    fn reload(&self) {
+        let builds = self
+            .builds
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+            + 1;
+        let version = format!("{}-{}", self.started, builds);
+        *self.version.lock().unwrap() = version.clone();
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|client| client.send(version.clone()).is_ok());
+        debug!(clients = clients.len(), "reloading");
+    }
+
+    /// Send the current version to `request` as a server-sent event, and then every new one
+    /// until the connection is closed.
+    fn stream(&self, request: tiny_http::Request) {
+        let (sender, receiver) = mpsc::channel();
+        let version = self.version.lock().unwrap().clone();
+        self.clients.lock().unwrap().push(sender);
+        let mut writer = request.into_writer();
+        let mut send = |event: &str| -> std::io::Result<()> {
+            writer.write_all(event.as_bytes())?;
+            writer.flush()
+        };
+        if send(&format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\nretry: 1000\ndata: {}\n\n",
+            version
+        ))
+        .is_err()
+        {
+            return;
+        }
+        loop {
+            let event = match receiver.recv_timeout(Duration::from_secs(15)) {
+                Ok(version) => format!("data: {}\n\n", version),
+                // Comments keep the connection alive and detect closed ones.
+                Err(mpsc::RecvTimeoutError::Timeout) => ":\n\n".to_string(),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            };
+            if send(&event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// A response to a request for a path of the output directory.
+#[derive(Debug, PartialEq)]
+enum Resolved {
+    File(PathBuf),
+    Redirect(String),
+    NotFound,
+}
+
+/// Resolve the `url` of a request to a file of `root`.
+fn resolve(root: &Path, url: &str) -> Resolved {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let decoded = match percent_decode(path) {
+        Some(decoded) => decoded,
+        None => return Resolved::NotFound,
+    };
+    let mut file = root.to_path_buf();
+    for component in Path::new(&decoded).components() {
+        match component {
+            std::path::Component::Normal(name) => file.push(name),
+            std::path::Component::RootDir | std::path::Component::CurDir => {}
+            _ => return Resolved::NotFound,
+        }
+    }
+    if file.is_dir() {
+        if !path.ends_with('/') {
+            return Resolved::Redirect(format!("{}/{}", path, &url[path.len()..]));
+        }
+        file.push("index.html");
+    } else if !file.is_file() && !path.ends_with('/') {
+        file.set_file_name(format!(
+            "{}.html",
+            file.file_name().unwrap_or_default().to_string_lossy()
+        ));
+    }
+    if file.is_file() {
+        Resolved::File(file)
+    } else {
+        Resolved::NotFound
+    }
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "xml" => "application/xml",
+        "rss" => "application/rss+xml",
+        "atom" => "application/atom+xml",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        "epub" => "application/epub+zip",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Insert the live reload script before the closing `</body>` tag of `html`, or at its end.
+fn inject_live_reload(html: &mut Vec<u8>) {
+    let index = html
+        .windows(7)
+        .rposition(|w| w.eq_ignore_ascii_case(b"</body>"))
+        .unwrap_or(html.len());
+    html.splice(index..index, LIVE_RELOAD_SCRIPT.bytes());
+}
+
+fn respond(root: &Path, options: &Serve, request: tiny_http::Request) -> Result<()> {
+    let header = |name: &str, value: &str| {
+        tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+    };
+    let (status, file) = match resolve(root, request.url()) {
+        Resolved::File(file) => (200, Some(file)),
+        Resolved::Redirect(location) => {
+            let response =
+                tiny_http::Response::empty(301).with_header(header("Location", &location));
+            return Ok(request.respond(response)?);
+        }
+        Resolved::NotFound => (
+            404,
+            options
+                .not_found
+                .as_ref()
+                .map(|page| root.join(page))
+                .filter(|page| page.is_file()),
+        ),
+    };
+    trace!(url = request.url(), status, "request");
+    let file = match file {
+        Some(file) => file,
+        None => {
+            return Ok(request
+                .respond(tiny_http::Response::from_string("Not Found").with_status_code(404))?)
+        }
+    };
+    let mut contents = fs::read(&file)?;
+    let content_type = content_type(&file);
+    if options.live_reload && content_type.starts_with("text/html") {
+        inject_live_reload(&mut contents);
+    }
+    let response = tiny_http::Response::from_data(contents)
+        .with_status_code(status)
+        .with_header(header("Content-Type", content_type))
+        .with_header(header("Cache-Control", "no-cache"));
+    Ok(request.respond(response)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let root = std::env::temp_dir().join(format!("libssg-serve-{}", std::process::id()));
+        fs::create_dir_all(root.join("posts/hello")).unwrap();
+        for file in [
+            "index.html",
+            "about.html",
+            "posts/hello/index.html",
+            "a b.css",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let file = |path: &str| Resolved::File(root.join(path));
+
+        assert_eq!(resolve(&root, "/"), file("index.html"));
+        assert_eq!(resolve(&root, "/?q=1"), file("index.html"));
+        assert_eq!(resolve(&root, "/about"), file("about.html"));
+        assert_eq!(resolve(&root, "/about.html"), file("about.html"));
+        assert_eq!(
+            resolve(&root, "/posts/hello/"),
+            file("posts/hello/index.html")
+        );
+        assert_eq!(
+            resolve(&root, "/posts/hello?q=1"),
+            Resolved::Redirect("/posts/hello/?q=1".into())
+        );
+        assert_eq!(resolve(&root, "/a%20b.css"), file("a b.css"));
+        assert_eq!(resolve(&root, "/missing"), Resolved::NotFound);
+        assert_eq!(resolve(&root, "/about/"), Resolved::NotFound);
+        assert_eq!(resolve(&root, "/../etc/passwd"), Resolved::NotFound);
+        assert_eq!(resolve(&root, "/%2e%2e/etc/passwd"), Resolved::NotFound);
+        fs::remove_dir_all(&root).unwrap();
+
+        let mut html = b"<html><body><p>hi</p></BODY></html>".to_vec();
+        inject_live_reload(&mut html);
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.starts_with("<html><body><p>hi</p><script>"));
+        assert!(html.ends_with("</script>\n</BODY></html>"));
+    }
+}
@@ -0,0 +1,423 @@
+/*
+ * libssg
+ *
+ * Copyright 2020 Manos Pitsidianakis
+ *
+ * This file is part of libssg.
+ *
+ * libssg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * libssg is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with libssg. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Watch mode with incremental rebuilds, enabled with the `watch` feature, see
+//! [`State::watch`](crate::State::watch).
+
+use super::*;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Configuration of watch mode, see [`State::watch`](crate::State::watch).
+#[derive(Debug, Clone)]
+pub struct Watch {
+    /// Paths to watch recursively, relative to the current directory.
+    pub paths: Vec<PathBuf>,
+    /// Paths to ignore, relative to the current directory. Output directories and hidden files,
+    /// eg `.git` and `.cache`, are always ignored.
+    pub ignore: Vec<PathBuf>,
+    /// How long to wait for further changes before rebuilding.
+    pub debounce: Duration,
+}
+
+impl Watch {
+    /// Watch the current directory, which contains the sources, `templates` and data files,
+    /// ignoring `target`, and rebuild 200ms after the last change.
+    pub fn new() -> Self {
+        Watch {
+            paths: vec![PathBuf::from(".")],
+            ignore: vec![PathBuf::from("target")],
+            debounce: Duration::from_millis(200),
+        }
+    }
+}
+
+impl Default for Watch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Snapshots read by the rule being run.
+#[derive(Debug, Default)]
+pub(crate) struct Reads {
+    snapshots: BTreeSet<String>,
+    all: bool,
+}
+
+impl Reads {
+    pub(crate) fn add(&mut self, name: Option<&str>) {
+        match name {
+            Some(name) => {
+                self.snapshots.insert(name.to_string());
+            }
+            None => self.all = true,
+        }
+    }
+}
+
+/// What a rule read and added to the state in a build, to replay it in the next build if none of
+/// it changed.
+#[derive(Clone, Default)]
+struct RuleRecord {
+    /// Resources and dependencies of the rule's artifacts.
+    inputs: BTreeSet<PathBuf>,
+    /// Snapshots the rule read.
+    reads: BTreeSet<String>,
+    /// Whether the rule read any artifact, or generated artifacts that are not read from a file.
+    reads_all: bool,
+    /// Snapshots the rule added artifacts to.
+    writes: BTreeSet<String>,
+    artifacts: Vec<BuildArtifact>,
+    routes: Vec<(PathBuf, Uuid)>,
+    plan: Vec<PlannedOutput>,
+    dependencies: Vec<(Uuid, Vec<PathBuf>)>,
+    snapshots: Vec<(String, Uuid)>,
+    assets: Vec<(String, String)>,
+    #[cfg(feature = "images")]
+    images: Vec<(String, ResponsiveImage)>,
+}
+
+impl RuleRecord {
+    /// Whether the rule must run again because of `changed` paths, the snapshots `written` by the
+    /// rules that ran before it, if any did.
+    fn affected(&self, changed: &BTreeSet<PathBuf>, written: &BTreeSet<String>, ran: bool) -> bool {
+        (self.reads_all && ran)
+            || !self.reads.is_disjoint(written)
+            || changed
+                .iter()
+                .any(|path| self.inputs.iter().any(|input| path.starts_with(input)))
+    }
+}
+
+impl State {
+    /// Build the site with the rules returned by `rules`, then rebuild it whenever a watched path
+    /// changes, until watching fails. Failed builds are logged and retried on the next change.
+    ///
+    /// `rules` must return the same rules in the same order every time. A rebuild only runs the
+    /// rules whose resources or dependencies changed and the rules that read the snapshots they
+    /// add to; the other rules are not run and their artifacts are reused. Rules that read
+    /// [`State::artifacts`](State::artifacts) or generate pages without a resource file, eg
+    /// sitemaps, run whenever any rule does, and rules after one that changes asset URLs run as
+    /// well. Adding or removing files, or changing templates or files that no rule depends on,
+    /// rebuilds everything.
+    ///
+    /// ```no_run
+    /// use libssg::*;
+    ///
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut state = State::new()?;
+    ///     state.watch(Watch::new(), || {
+    ///         vec![
+    ///             match_pattern(
+    ///                 "^posts/*",
+    ///                 Route::SetExtension("html"),
+    ///                 Renderer::LoadAndApplyTemplate("templates/post.hbs"),
+    ///                 pandoc(),
+    ///             ),
+    ///             copy("^css/*", Route::Id),
+    ///         ]
+    ///     })
+    /// }
+    /// ```
+    pub fn watch<F>(&mut self, options: Watch, mut rules: F) -> Result<()>
+    where
+        F: FnMut() -> Vec<Rule>,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        for path in options.paths.iter() {
+            // Without `.` components, so that event paths can be compared with inputs.
+            let path = self
+                .current_dir
+                .join(path)
+                .components()
+                .collect::<PathBuf>();
+            notify::Watcher::watch(&mut watcher, &path, notify::RecursiveMode::Recursive)?;
+        }
+        let mut records = self.build(rules(), None);
+        info!(paths = ?options.paths, "watching for changes");
+        loop {
+            let mut changed = BTreeSet::new();
+            let mut add = |event: notify::Result<notify::Event>| match event {
+                Ok(event) if !event.kind.is_access() => changed.extend(event.paths),
+                Ok(_) => {}
+                Err(err) => warn!(%err, "watch error"),
+            };
+            add(receiver.recv()?);
+            while let Ok(event) = receiver.recv_timeout(options.debounce) {
+                add(event);
+            }
+            let records_ref = records.as_deref().unwrap_or_default();
+            let is_input = |path: &Path| {
+                records_ref
+                    .iter()
+                    .any(|r| r.inputs.iter().any(|i| path.starts_with(i)))
+            };
+            // Temporary files of editors are created and removed before the build.
+            changed.retain(|path| {
+                !self.is_ignored(path, &options) && (path.exists() || is_input(path))
+            });
+            if changed.is_empty() {
+                continue;
+            }
+            let incremental = changed.iter().all(|path| path.exists() && is_input(path));
+            info!(?changed, incremental, "rebuilding");
+            let previous = records.take().filter(|_| incremental);
+            records = self.build(rules(), previous.map(|records| (records, changed)));
+        }
+    }
+
+    /// Whether changes to `path` should not trigger a rebuild.
+    fn is_ignored(&self, path: &Path, options: &Watch) -> bool {
+        let relative = match path.strip_prefix(&self.current_dir) {
+            Ok(relative) => relative,
+            Err(_) => return true,
+        };
+        std::iter::once(&self.output_dir)
+            .chain(self.output_roots.values())
+            .any(|dir| path.starts_with(dir))
+            || options
+                .ignore
+                .iter()
+                .any(|ignored| relative.starts_with(ignored))
+            || relative.components().any(|component| {
+                let name = component.as_os_str().to_string_lossy();
+                name.starts_with('.') && name != "." || name.ends_with('~')
+            })
+    }
+
+    /// Run `rules` and finish, and return what each rule did or `None` if the build failed.
+    /// With the records of the `previous` build and the paths `changed` since, rules that are not
+    /// affected by the changes are replayed instead of run.
+    fn build(
+        &mut self,
+        rules: Vec<Rule>,
+        previous: Option<(Vec<RuleRecord>, BTreeSet<PathBuf>)>,
+    ) -> Option<Vec<RuleRecord>> {
+        if let Err(err) = self.reset_build() {
+            warn!(%err, "build failed");
+            return None;
+        }
+        let (mut previous, changed) = match previous {
+            Some((records, changed)) if records.len() == rules.len() => {
+                (records.into_iter().map(Some).collect(), changed)
+            }
+            _ => (vec![], BTreeSet::new()),
+        };
+        let mut records = Vec::with_capacity(rules.len());
+        let mut written = BTreeSet::new();
+        let mut ran = false;
+        for (n, rule) in rules.into_iter().enumerate() {
+            match previous.get_mut(n).and_then(Option::take) {
+                Some(record)
+                    if !self.assets_changed && !record.affected(&changed, &written, ran) =>
+                {
+                    self.replay(&record);
+                    records.push(record);
+                }
+                _ => {
+                    let record = self.record(rule);
+                    written.extend(record.writes.iter().cloned());
+                    ran = true;
+                    records.push(record);
+                }
+            }
+        }
+        match self.finish() {
+            Ok(_) => Some(records),
+            Err(err) => {
+                warn!(%err, "build failed");
+                None
+            }
+        }
+    }
+
+    /// Clear the artifacts, snapshots and build actions of the previous build and reload the
+    /// templates.
+    fn reset_build(&mut self) -> Result<()> {
+        self.templates = load_templates(
+            &self.templates_dir,
+            &self.assets,
+            #[cfg(feature = "images")]
+            &self.images,
+        )?;
+        self.artifacts.clear();
+        self.build_actions.clear();
+        self.routes.clear();
+        self.snapshots.clear();
+        self.dependencies.clear();
+        self.pending_dependencies.clear();
+        self.assets.write().unwrap().clear();
+        #[cfg(feature = "images")]
+        self.images.write().unwrap().clear();
+        self.assets_changed = false;
+        self.plan.clear();
+        self.started = std::time::Instant::now();
+        self.timings.clear();
+        self.commands.clear();
+        self.rules_run = 0;
+        self.rendering = None;
+        self.err = None;
+        Ok(())
+    }
+
+    /// Run `rule` and return what it read and added.
+    fn record(&mut self, rule: Rule) -> RuleRecord {
+        let artifacts = self.artifacts.keys().cloned().collect::<HashSet<Uuid>>();
+        let routes = self.routes.keys().cloned().collect::<HashSet<PathBuf>>();
+        let snapshots = self
+            .snapshots
+            .iter()
+            .map(|(name, members)| (name.clone(), members.len()))
+            .collect::<HashMap<String, usize>>();
+        let assets = self.assets().clone();
+        #[cfg(feature = "images")]
+        let images = self
+            .responsive_images()
+            .keys()
+            .cloned()
+            .collect::<HashSet<String>>();
+        self.reads.replace(Default::default());
+
+        self.then(rule);
+
+        let reads = self.reads.replace(Default::default());
+        let mut record = RuleRecord {
+            reads: reads.snapshots,
+            reads_all: reads.all,
+            ..Default::default()
+        };
+        for (uuid, artifact) in self.artifacts.iter() {
+            if artifacts.contains(uuid) {
+                continue;
+            }
+            let dependencies = self.dependencies.get(uuid).cloned().unwrap_or_default();
+            for input in std::iter::once(&artifact.resource).chain(dependencies.iter()) {
+                let input = self.current_dir.join(input);
+                record.reads_all |= !input.exists();
+                record.inputs.insert(input);
+            }
+            record.artifacts.push(artifact.clone());
+            record.dependencies.push((*uuid, dependencies));
+        }
+        record.reads_all |= record.artifacts.is_empty();
+        for (path, uuid) in self.routes.iter() {
+            if !routes.contains(path) {
+                record.routes.push((path.clone(), *uuid));
+                record.plan.extend(self.plan.get(path).cloned());
+            }
+        }
+        for (name, members) in self.snapshots.iter() {
+            let start = snapshots.get(name).cloned().unwrap_or_default();
+            for uuid in members[start..].iter() {
+                record.writes.insert(name.clone());
+                record.snapshots.push((name.clone(), *uuid));
+            }
+        }
+        record.assets = self
+            .assets()
+            .iter()
+            .filter(|(path, url)| assets.get(*path) != Some(*url))
+            .map(|(path, url)| (path.clone(), url.clone()))
+            .collect();
+        #[cfg(feature = "images")]
+        {
+            record.images = self
+                .responsive_images()
+                .iter()
+                .filter(|(path, _)| !images.contains(*path))
+                .map(|(path, image)| (path.clone(), image.clone()))
+                .collect();
+        }
+        record
+    }
+
+    /// Add what `record`'s rule added in the previous build, with its outputs as cached.
+    fn replay(&mut self, record: &RuleRecord) {
+        self.rules_run += 1;
+        for artifact in record.artifacts.iter() {
+            self.artifacts.insert(artifact.uuid, artifact.clone());
+        }
+        self.routes.extend(record.routes.iter().cloned());
+        for output in record.plan.iter() {
+            self.plan.insert(
+                output.output.clone(),
+                PlannedOutput {
+                    dirty: None,
+                    ..output.clone()
+                },
+            );
+        }
+        self.dependencies
+            .extend(record.dependencies.iter().cloned());
+        for (name, uuid) in record.snapshots.iter() {
+            self.add_to_snapshot(name.clone(), *uuid);
+        }
+        for (path, url) in record.assets.iter() {
+            self.add_asset(path.clone(), url.clone());
+        }
+        #[cfg(feature = "images")]
+        for (path, image) in record.images.iter() {
+            self.add_responsive_image(path.clone(), image.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_affected() {
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<BTreeSet<_>>();
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<BTreeSet<_>>();
+        let posts = RuleRecord {
+            inputs: paths(&["/site/posts/a.md", "/site/data/authors.json"]),
+            writes: names(&["posts"]),
+            ..Default::default()
+        };
+        let album = RuleRecord {
+            inputs: paths(&["/site/photos/paris"]),
+            ..Default::default()
+        };
+        let feed = RuleRecord {
+            reads: names(&["posts"]),
+            ..Default::default()
+        };
+        let sitemap = RuleRecord {
+            reads_all: true,
+            ..Default::default()
+        };
+        let (no_paths, no_names) = (BTreeSet::new(), BTreeSet::new());
+
+        let changed = paths(&["/site/data/authors.json"]);
+        assert!(posts.affected(&changed, &no_names, false));
+        assert!(!album.affected(&changed, &no_names, false));
+        assert!(album.affected(&paths(&["/site/photos/paris/a.jpg"]), &no_names, false));
+        assert!(!album.affected(&paths(&["/site/photos/paris-2/a.jpg"]), &no_names, false));
+        assert!(feed.affected(&no_paths, &names(&["posts"]), true));
+        assert!(!feed.affected(&no_paths, &names(&["photos"]), true));
+        assert!(sitemap.affected(&no_paths, &no_names, true));
+        assert!(!sitemap.affected(&no_paths, &no_names, false));
+    }
+}